@@ -0,0 +1,18 @@
+; hello.s - copies a string into screen memory at $0400
+screen = $0400
+
+	.org $1000
+
+start:
+	ldx #0
+loop:
+	lda message,x
+	sta screen,x
+	inx
+	cpx #message_end - message
+	bne loop
+	brk
+
+message:
+	.text "hello world"
+message_end:
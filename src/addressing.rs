@@ -5,7 +5,7 @@ use lazy_static::lazy_static;
 
 use crate::program::Program;
 
-#[derive(PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum AddressMode {
 	Implied,
 	Accumulator,
@@ -22,6 +22,17 @@ pub enum AddressMode {
 	IndirectY,
 }
 
+impl AddressMode {
+	// Instruction length in bytes, including the opcode
+	pub fn size(&self) -> u16 {
+		match self {
+			AddressMode::Implied | AddressMode::Accumulator => 1,
+			AddressMode::Absolute | AddressMode::AbsoluteX | AddressMode::AbsoluteY | AddressMode::Indirect => 3,
+			_ => 2,
+		}
+	}
+}
+
 type AddrFunc = fn(&mut Program) -> ();
 
 lazy_static! {
@@ -71,19 +82,19 @@ pub fn addr_relative_immediate(program: &mut Program) {
 pub fn addr_zeropage(program: &mut Program) {
 	let addr = fetch_byte(program);
 	program.abs_address = addr as u16;
-	program.fetched_byte = program.get_memory(addr as u16);
+	program.fetched_byte = program.read_memory(addr as u16);
 }
 
 pub fn addr_zeropage_x(program: &mut Program) {
 	let addr = fetch_byte(program);
-	program.abs_address = addr as u16;
-	program.fetched_byte = program.get_memory(addr as u16 + program.reg_x as u16);
+	program.abs_address = addr.wrapping_add(program.reg_x) as u16;
+	program.fetched_byte = program.read_memory(program.abs_address);
 }
 
 pub fn addr_zeropage_y(program: &mut Program) {
 	let addr = fetch_byte(program);
-	program.abs_address = addr as u16;
-	program.fetched_byte = program.get_memory(addr as u16 + program.reg_y as u16);
+	program.abs_address = addr.wrapping_add(program.reg_y) as u16;
+	program.fetched_byte = program.read_memory(program.abs_address);
 }
 
 pub fn addr_absolute(program: &mut Program) {
@@ -91,23 +102,25 @@ pub fn addr_absolute(program: &mut Program) {
 	let hi = fetch_byte(program);
 	let addr = make_u16(lo, hi);
 	program.abs_address = addr;
-	program.fetched_byte = program.get_memory(addr);
+	program.fetched_byte = program.read_memory(addr);
 }
 
 pub fn addr_absolute_x(program: &mut Program) {
 	let lo = fetch_byte(program);
 	let hi = fetch_byte(program);
 	let addr = make_u16(lo, hi);
-	program.abs_address = addr + program.reg_x as u16;
-	program.fetched_byte = program.get_memory(addr + program.reg_x as u16);
+	program.abs_address = addr.wrapping_add(program.reg_x as u16);
+	program.page_crossed = (addr & 0xff00) != (program.abs_address & 0xff00);
+	program.fetched_byte = program.read_memory(program.abs_address);
 }
 
 pub fn addr_absolute_y(program: &mut Program) {
 	let lo = fetch_byte(program);
 	let hi = fetch_byte(program);
 	let addr = make_u16(lo, hi);
-	program.abs_address = addr + program.reg_y as u16;
-	program.fetched_byte = program.get_memory(addr + program.reg_y as u16);
+	program.abs_address = addr.wrapping_add(program.reg_y as u16);
+	program.page_crossed = (addr & 0xff00) != (program.abs_address & 0xff00);
+	program.fetched_byte = program.read_memory(program.abs_address);
 }
 
 pub fn addr_indirect(program: &mut Program) {
@@ -116,32 +129,33 @@ pub fn addr_indirect(program: &mut Program) {
 	let addr = make_u16(lo, hi);
 	program.ind_address = addr;
 
-	let lo_abs = program.get_memory(addr);
+	let lo_abs = program.read_memory(addr);
 	
 	// REPLICATE PAGE CHANGE BUG
-	let hi_abs = if lo == 0xff { program.get_memory(addr + 1 - 0xff) } else { program.get_memory(addr + 1) };
+	let hi_abs = if lo == 0xff { program.read_memory(addr - 0xff) } else { program.read_memory(addr + 1) };
 
 	let addr_abs = make_u16(lo_abs, hi_abs);
 	program.abs_address = addr_abs;
-	program.fetched_byte = program.get_memory(addr_abs);
+	program.fetched_byte = program.read_memory(addr_abs);
 }
 
 pub fn addr_x_indirect(program: &mut Program) {
 	let byte = fetch_byte(program);
 	program.ind_address = byte as u16;
-	let lo = program.get_memory(byte as u16 + program.reg_x as u16);
-	let hi = program.get_memory(byte as u16 + 1 + program.reg_x as u16);
+	let lo = program.read_memory(byte.wrapping_add(program.reg_x) as u16);
+	let hi = program.read_memory(byte.wrapping_add(program.reg_x).wrapping_add(1) as u16);
 	let addr = make_u16(lo, hi);
 	program.abs_address = addr;
-	program.fetched_byte = program.get_memory(addr);
+	program.fetched_byte = program.read_memory(addr);
 }
 
 pub fn addr_indirect_y(program: &mut Program) {
 	let byte = fetch_byte(program);
 	program.ind_address = byte as u16;
-	let lo = program.get_memory(byte as u16);
-	let hi = program.get_memory(byte as u16 + 1);
+	let lo = program.read_memory(byte as u16);
+	let hi = program.read_memory(byte.wrapping_add(1) as u16);
 	let addr = make_u16(lo, hi);
-	program.abs_address = addr;
-	program.fetched_byte = program.get_memory(addr + program.reg_y as u16);
+	program.abs_address = addr.wrapping_add(program.reg_y as u16);
+	program.page_crossed = (addr & 0xff00) != (program.abs_address & 0xff00);
+	program.fetched_byte = program.read_memory(program.abs_address);
 }
@@ -0,0 +1,776 @@
+// assembler.rs
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::addressing::AddressMode;
+use crate::expression::{self, Expr, Resolver};
+use crate::loader::{LoadedImage, Segment};
+use crate::opcodes::{find_opcode, is_mnemonic};
+use crate::symbols::SymbolTable;
+
+pub struct AsmError {
+	pub line: usize,
+	pub message: String,
+}
+
+impl fmt::Display for AsmError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.message)
+	}
+}
+
+pub struct Assembly {
+	// One segment for each run of assembled bytes, entered at the lowest address
+	pub image: LoadedImage,
+	pub symbols: HashMap<String, u16>,
+	// Source line of the instruction at each address
+	pub lines: BTreeMap<u16, usize>,
+}
+
+impl Assembly {
+	pub fn origin(&self) -> u16 {
+		self.image.entry.unwrap_or(0)
+	}
+
+	// A .prg file holds a single block, so the gaps between segments are filled with zeros
+	pub fn to_prg(&self) -> Vec<u8> {
+		let origin = self.origin();
+		let mut prg = vec![(origin & 0xff) as u8, (origin >> 8) as u8];
+		for segment in &self.image.segments {
+			prg.resize(2 + (segment.address - origin) as usize, 0);
+			prg.extend_from_slice(&segment.data);
+		}
+		prg
+	}
+}
+
+enum Operand {
+	None,
+	Accumulator,
+	Immediate(Expr),
+	Direct(Expr),
+	IndexedX(Expr),
+	IndexedY(Expr),
+	Indirect(Expr),
+	IndirectX(Expr),
+	IndirectY(Expr),
+}
+
+enum DataItem {
+	Value(Expr),
+	Text(Vec<u8>),
+}
+
+enum Statement {
+	Empty,
+	Org(Expr),
+	Bytes(Vec<DataItem>),
+	Words(Vec<Expr>),
+	Instruction(String, Operand),
+}
+
+struct ParsedLine {
+	label: Option<String>,
+	assignment: Option<(String, Expr)>,
+	statement: Statement,
+}
+
+struct SymbolResolver<'a> {
+	symbols: &'a HashMap<String, i64>,
+	address: u16,
+}
+
+impl<'a> Resolver for SymbolResolver<'a> {
+	fn resolve(&self, name: &str) -> Option<i64> {
+		if name == "*" {
+			return Some(self.address as i64);
+		}
+
+		self.symbols.get(name).copied()
+	}
+}
+
+// =============================================================
+
+fn strip_comment(line: &str) -> &str {
+	let mut in_string = false;
+	let mut in_char = false;
+	for (i, c) in line.char_indices() {
+		match c {
+			'"' if !in_char => { in_string = !in_string; },
+			'\'' if !in_string => { in_char = !in_char; },
+			';' if !in_string && !in_char => { return &line[..i]; },
+			_ => {}
+		}
+	}
+
+	line
+}
+
+fn split_args(text: &str) -> Vec<String> {
+	let mut args = Vec::new();
+	let mut current = String::new();
+	let mut in_string = false;
+	let mut escaped = false;
+	for c in text.chars() {
+		if in_string {
+			current.push(c);
+			if escaped {
+				escaped = false;
+			}
+			else if c == '\\' {
+				escaped = true;
+			}
+			else if c == '"' {
+				in_string = false;
+			}
+		}
+		else if c == '"' {
+			in_string = true;
+			current.push(c);
+		}
+		else if c == ',' {
+			args.push(current.trim().to_string());
+			current.clear();
+		}
+		else {
+			current.push(c);
+		}
+	}
+
+	args.push(current.trim().to_string());
+	args
+}
+
+fn parse_string(text: &str) -> Result<Vec<u8>, String> {
+	let inner = &text[1..];
+	let mut bytes = Vec::new();
+	let mut chars = inner.chars();
+	loop {
+		match chars.next() {
+			Some('"') => {
+				if chars.next().is_some() {
+					return Err(format!("Unexpected text after string {}", text));
+				}
+				return Ok(bytes);
+			},
+			Some('\\') => {
+				bytes.push(match chars.next() {
+					Some('n') => b'\n',
+					Some('r') => b'\r',
+					Some('t') => b'\t',
+					Some('0') => 0,
+					Some('"') => b'"',
+					Some('\\') => b'\\',
+					other => { return Err(format!("Invalid escape sequence \\{}", other.unwrap_or(' '))); },
+				});
+			},
+			Some(c) if c.is_ascii() => { bytes.push(c as u8); },
+			Some(c) => { return Err(format!("Non-ASCII character '{}' in string", c)); },
+			None => { return Err(String::from("Unterminated string")); },
+		}
+	}
+}
+
+fn matching_paren(text: &str) -> Option<usize> {
+	let mut depth = 0;
+	for (i, c) in text.char_indices() {
+		match c {
+			'(' => { depth += 1; },
+			')' => {
+				depth -= 1;
+				if depth == 0 {
+					return Some(i);
+				}
+			},
+			_ => {}
+		}
+	}
+
+	None
+}
+
+fn split_index(text: &str) -> Option<(&str, char)> {
+	let (base, index) = text.rsplit_once(',')?;
+	match index.trim().to_ascii_lowercase().as_str() {
+		"x" => Some((base.trim(), 'x')),
+		"y" => Some((base.trim(), 'y')),
+		_ => None,
+	}
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+	let text = text.trim();
+	if text.is_empty() {
+		return Ok(Operand::None);
+	}
+
+	if text.eq_ignore_ascii_case("a") {
+		return Ok(Operand::Accumulator);
+	}
+
+	if let Some(value) = text.strip_prefix('#') {
+		return Ok(Operand::Immediate(expression::parse(value)?));
+	}
+
+	if text.starts_with('(') {
+		if let Some((base, 'y')) = split_index(text) {
+			if matching_paren(base) == Some(base.len() - 1) {
+				return Ok(Operand::IndirectY(expression::parse(&base[1..base.len() - 1])?));
+			}
+		}
+
+		// Only indirect if the parentheses wrap the whole operand, so (a+1)*2 stays an expression
+		if matching_paren(text) == Some(text.len() - 1) {
+			let inner = &text[1..text.len() - 1];
+			return match split_index(inner) {
+				Some((base, 'x')) => Ok(Operand::IndirectX(expression::parse(base)?)),
+				_ => Ok(Operand::Indirect(expression::parse(inner)?)),
+			};
+		}
+	}
+
+	match split_index(text) {
+		Some((base, 'x')) => Ok(Operand::IndexedX(expression::parse(base)?)),
+		Some((base, _)) => Ok(Operand::IndexedY(expression::parse(base)?)),
+		None => Ok(Operand::Direct(expression::parse(text)?)),
+	}
+}
+
+fn is_identifier(text: &str) -> bool {
+	let mut chars = text.chars();
+	match chars.next() {
+		Some(c) if c.is_alphabetic() || c == '_' || c == '@' || c == '.' => {},
+		_ => { return false; },
+	}
+
+	chars.all(|c| c.is_alphanumeric() || c == '_' || c == '@' || c == '.')
+}
+
+fn parse_line(line: &str) -> Result<ParsedLine, String> {
+	let mut text = strip_comment(line).trim_end();
+	let mut parsed = ParsedLine{label: None, assignment: None, statement: Statement::Empty};
+
+	// Constant assignment (name = value) or origin assignment (*= value)
+	if let Some((name, value)) = text.split_once('=') {
+		let name = name.trim();
+		if name == "*" {
+			parsed.statement = Statement::Org(expression::parse(value)?);
+			return Ok(parsed);
+		}
+		if is_identifier(name) {
+			parsed.assignment = Some((name.to_string(), expression::parse(value)?));
+			return Ok(parsed);
+		}
+	}
+
+	let first_end = text.find(|c: char| c.is_whitespace()).unwrap_or(text.len());
+	let first = &text[..first_end];
+	if let Some(label) = first.strip_suffix(':') {
+		if !is_identifier(label) {
+			return Err(format!("Invalid label \"{}\"", label));
+		}
+		parsed.label = Some(label.to_string());
+		text = &text[first_end..];
+	}
+	else if !line.starts_with(char::is_whitespace) && is_identifier(first) && !first.starts_with('.') && !is_mnemonic(first) {
+		parsed.label = Some(first.to_string());
+		text = &text[first_end..];
+	}
+
+	let text = text.trim();
+	if text.is_empty() {
+		return Ok(parsed);
+	}
+
+	let (word, rest) = match text.find(char::is_whitespace) {
+		Some(pos) => (&text[..pos], text[pos..].trim()),
+		None => (text, ""),
+	};
+
+	parsed.statement = match word.to_lowercase().as_str() {
+		".org" => Statement::Org(expression::parse(rest)?),
+		".byte" | ".text" => {
+			let mut items = Vec::new();
+			for arg in split_args(rest) {
+				if arg.starts_with('"') {
+					items.push(DataItem::Text(parse_string(&arg)?));
+				}
+				else {
+					items.push(DataItem::Value(expression::parse(&arg)?));
+				}
+			}
+			Statement::Bytes(items)
+		},
+		".word" => {
+			let mut items = Vec::new();
+			for arg in split_args(rest) {
+				items.push(expression::parse(&arg)?);
+			}
+			Statement::Words(items)
+		},
+		_ if word.starts_with('.') => {
+			return Err(format!("Unknown directive \"{}\"", word));
+		},
+		_ if is_mnemonic(word) => Statement::Instruction(word.to_uppercase(), parse_operand(rest)?),
+		_ => {
+			return Err(format!("Unknown instruction \"{}\"", word));
+		},
+	};
+
+	Ok(parsed)
+}
+
+// Picks the addressing mode for an instruction; `value` is None if the operand can't be resolved yet
+fn select_mode(mnemonic: &str, operand: &Operand, value: Option<i64>) -> Result<AddressMode, String> {
+	let fits_zeropage = |amode: AddressMode| {
+		value.is_some_and(|v| (0..=0xff).contains(&v)) && find_opcode(mnemonic, amode).is_some()
+	};
+
+	let amode = match operand {
+		Operand::None => {
+			if find_opcode(mnemonic, AddressMode::Implied).is_some() { AddressMode::Implied } else { AddressMode::Accumulator }
+		},
+		Operand::Accumulator => AddressMode::Accumulator,
+		Operand::Immediate(_) => AddressMode::Immediate,
+		Operand::Direct(_) => {
+			if find_opcode(mnemonic, AddressMode::Relative).is_some() { AddressMode::Relative }
+			else if fits_zeropage(AddressMode::Zeropage) { AddressMode::Zeropage }
+			else { AddressMode::Absolute }
+		},
+		Operand::IndexedX(_) => {
+			if fits_zeropage(AddressMode::ZeropageX) || find_opcode(mnemonic, AddressMode::AbsoluteX).is_none() { AddressMode::ZeropageX } else { AddressMode::AbsoluteX }
+		},
+		Operand::IndexedY(_) => {
+			if fits_zeropage(AddressMode::ZeropageY) || find_opcode(mnemonic, AddressMode::AbsoluteY).is_none() { AddressMode::ZeropageY } else { AddressMode::AbsoluteY }
+		},
+		Operand::Indirect(_) => AddressMode::Indirect,
+		Operand::IndirectX(_) => AddressMode::IndirectX,
+		Operand::IndirectY(_) => AddressMode::IndirectY,
+	};
+
+	match find_opcode(mnemonic, amode) {
+		Some(_) => Ok(amode),
+		None => Err(format!("Addressing mode {:?} is not valid for {}", amode, mnemonic)),
+	}
+}
+
+fn operand_expr(operand: &Operand) -> Option<&Expr> {
+	match operand {
+		Operand::None | Operand::Accumulator => None,
+		Operand::Immediate(e) | Operand::Direct(e) | Operand::IndexedX(e) | Operand::IndexedY(e)
+			| Operand::Indirect(e) | Operand::IndirectX(e) | Operand::IndirectY(e) => Some(e),
+	}
+}
+
+fn check_byte(value: i64) -> Result<u8, String> {
+	if (-128..=0xff).contains(&value) {
+		Ok(value as u8)
+	}
+	else {
+		Err(format!("Value ${:x} does not fit in a byte", value))
+	}
+}
+
+fn check_word(value: i64) -> Result<u16, String> {
+	if (-0x8000..=0xffff).contains(&value) {
+		Ok(value as u16)
+	}
+	else {
+		Err(format!("Value ${:x} does not fit in a word", value))
+	}
+}
+
+fn encode_instruction(mnemonic: &str, amode: AddressMode, operand: &Operand, address: u16, resolver: &dyn Resolver) -> Result<Vec<u8>, String> {
+	let opcode = find_opcode(mnemonic, amode).ok_or(format!("Addressing mode {:?} is not valid for {}", amode, mnemonic))?;
+	let mut bytes = vec![opcode];
+	let value = match operand_expr(operand) {
+		Some(expr) => expr.evaluate(resolver)?,
+		None => { return Ok(bytes); },
+	};
+
+	match amode {
+		AddressMode::Relative => {
+			let offset = value - (address as i64 + 2);
+			if !(-128..=127).contains(&offset) {
+				return Err(format!("Branch target ${:x} out of range ({} bytes)", value, offset));
+			}
+			bytes.push(offset as u8);
+		},
+		_ if amode.size() == 3 => {
+			let word = check_word(value)?;
+			bytes.push((word & 0xff) as u8);
+			bytes.push((word >> 8) as u8);
+		},
+		_ => {
+			bytes.push(check_byte(value)?);
+		},
+	}
+
+	Ok(bytes)
+}
+
+// =============================================================
+
+// Assembles a single statement at `address`, for patching code in place, with the program's symbols
+pub fn assemble_line(text: &str, address: u16, table: &SymbolTable) -> Result<Vec<u8>, String> {
+	let symbols: HashMap<String, i64> = table.names().filter_map(|name| table.lookup(name).map(|a| (name.clone(), a as i64))).collect();
+	let resolver = SymbolResolver{symbols: &symbols, address};
+	let parsed = parse_line(&format!("\t{}", text))?;
+	if parsed.assignment.is_some() {
+		return Err(String::from("Symbols can't be defined here"));
+	}
+
+	match &parsed.statement {
+		Statement::Instruction(mnemonic, operand) => {
+			let value = operand_expr(operand).map(|expr| expr.evaluate(&resolver)).transpose()?;
+			let amode = select_mode(mnemonic, operand, value)?;
+			encode_instruction(mnemonic, amode, operand, address, &resolver)
+		},
+		Statement::Bytes(items) => {
+			let mut bytes = Vec::new();
+			for item in items {
+				match item {
+					DataItem::Value(expr) => bytes.push(check_byte(expr.evaluate(&resolver)?)?),
+					DataItem::Text(text) => bytes.extend_from_slice(text),
+				}
+			}
+			Ok(bytes)
+		},
+		Statement::Words(items) => {
+			let mut bytes = Vec::new();
+			for expr in items {
+				let word = check_word(expr.evaluate(&resolver)?)?;
+				bytes.push((word & 0xff) as u8);
+				bytes.push((word >> 8) as u8);
+			}
+			Ok(bytes)
+		},
+		Statement::Org(_) => Err(String::from(".org can't be used here")),
+		Statement::Empty => Ok(Vec::new()),
+	}
+}
+
+fn uses_current_address(expr: &Expr) -> bool {
+	match expr {
+		Expr::CurrentAddress => true,
+		Expr::Memory(inner) | Expr::Unary(_, inner) => uses_current_address(inner),
+		Expr::Binary(_, lhs, rhs) => uses_current_address(lhs) || uses_current_address(rhs),
+		Expr::Number(_) | Expr::Symbol(_) => false,
+	}
+}
+
+fn define_symbol(symbols: &mut HashMap<String, i64>, name: &str, value: i64, line: usize, errors: &mut Vec<AsmError>) {
+	if symbols.insert(name.to_string(), value).is_some() {
+		errors.push(AsmError{line, message: format!("Symbol \"{}\" defined more than once", name)});
+	}
+}
+
+pub fn assemble(source: &str) -> Result<Assembly, Vec<AsmError>> {
+	let mut errors = Vec::new();
+	let mut symbols = HashMap::<String, i64>::new();
+	let mut lines = Vec::new();
+
+	for (i, line) in source.lines().enumerate() {
+		match parse_line(line) {
+			Ok(parsed) => lines.push((i + 1, parsed)),
+			Err(message) => errors.push(AsmError{line: i + 1, message}),
+		}
+	}
+
+	// Pass 1: assign addresses to labels and fix the size of every instruction
+	let mut pending = Vec::new();
+	let mut layout = Vec::new();
+	let mut pc: i64 = 0;
+	for (number, parsed) in &lines {
+		let resolver = SymbolResolver{symbols: &symbols, address: pc as u16};
+
+		if let Some((name, expr)) = &parsed.assignment {
+			match expr.evaluate(&resolver) {
+				Ok(value) => define_symbol(&mut symbols, name, value, *number, &mut errors),
+				// Pending constants are worked out after pass 1, when * no longer means this line's address
+				Err(_) if uses_current_address(expr) => {
+					errors.push(AsmError{line: *number, message: String::from("A constant can't use * and a label defined later")});
+				},
+				Err(_) => pending.push((*number, name, expr)),
+			}
+			continue;
+		}
+
+		let mut size: i64 = 0;
+		let mut amode = None;
+		match &parsed.statement {
+			Statement::Org(expr) => {
+				match expr.evaluate(&resolver) {
+					Ok(value) => { pc = value; },
+					Err(message) => errors.push(AsmError{line: *number, message}),
+				}
+			},
+			Statement::Bytes(items) => {
+				size = items.iter().map(|item| match item {
+					DataItem::Value(_) => 1,
+					DataItem::Text(text) => text.len() as i64,
+				}).sum();
+			},
+			Statement::Words(items) => {
+				size = items.len() as i64 * 2;
+			},
+			Statement::Instruction(mnemonic, operand) => {
+				let value = operand_expr(operand).and_then(|expr| expr.evaluate(&resolver).ok());
+				match select_mode(mnemonic, operand, value) {
+					Ok(mode) => {
+						size = mode.size() as i64;
+						amode = Some(mode);
+					},
+					Err(message) => errors.push(AsmError{line: *number, message}),
+				}
+			},
+			Statement::Empty => {},
+		}
+
+		if let Some(label) = &parsed.label {
+			define_symbol(&mut symbols, label, pc, *number, &mut errors);
+		}
+
+		if !(0..=0x10000 - size).contains(&pc) {
+			errors.push(AsmError{line: *number, message: format!("Address ${:x} is outside of memory", pc)});
+		}
+
+		layout.push((pc as u16, amode));
+		pc += size;
+	}
+
+	// Constants that referred to labels further down
+	while !pending.is_empty() {
+		let before = pending.len();
+		pending.retain(|(number, name, expr)| {
+			let resolver = SymbolResolver{symbols: &symbols, address: 0};
+			match expr.evaluate(&resolver) {
+				Ok(value) => {
+					define_symbol(&mut symbols, name, value, *number, &mut errors);
+					false
+				},
+				Err(_) => true,
+			}
+		});
+
+		if pending.len() == before {
+			for (number, _, expr) in &pending {
+				if let Err(message) = expr.evaluate(&SymbolResolver{symbols: &symbols, address: 0}) {
+					errors.push(AsmError{line: *number, message});
+				}
+			}
+			break;
+		}
+	}
+
+	// Pass 2: emit bytes. Lines that failed in pass 1 are skipped, the rest are still checked so that every
+	// error is reported at once.
+	let mut image = vec![None; 0x10000];
+	let mut source_lines = BTreeMap::new();
+	let statements = lines.iter().filter(|(_, parsed)| parsed.assignment.is_none());
+	for ((number, parsed), (address, amode)) in statements.zip(layout) {
+		let resolver = SymbolResolver{symbols: &symbols, address};
+		let result = match &parsed.statement {
+			Statement::Bytes(items) => {
+				let mut bytes = Vec::new();
+				for item in items {
+					match item {
+						DataItem::Value(expr) => {
+							match expr.evaluate(&resolver).and_then(check_byte) {
+								Ok(byte) => bytes.push(byte),
+								Err(message) => errors.push(AsmError{line: *number, message}),
+							}
+						},
+						DataItem::Text(text) => bytes.extend_from_slice(text),
+					}
+				}
+				Ok(bytes)
+			},
+			Statement::Words(items) => {
+				let mut bytes = Vec::new();
+				for expr in items {
+					match expr.evaluate(&resolver).and_then(check_word) {
+						Ok(word) => {
+							bytes.push((word & 0xff) as u8);
+							bytes.push((word >> 8) as u8);
+						},
+						Err(message) => errors.push(AsmError{line: *number, message}),
+					}
+				}
+				Ok(bytes)
+			},
+			Statement::Instruction(mnemonic, operand) => {
+				let Some(amode) = amode else { continue; };
+				source_lines.insert(address, *number);
+				encode_instruction(mnemonic, amode, operand, address, &resolver)
+			},
+			Statement::Org(_) | Statement::Empty => Ok(Vec::new()),
+		};
+
+		match result {
+			Ok(bytes) => {
+				// Anything past the end of memory was already reported in pass 1
+				for (slot, byte) in image.iter_mut().skip(address as usize).zip(bytes) {
+					*slot = Some(byte);
+				}
+			},
+			Err(message) => errors.push(AsmError{line: *number, message}),
+		}
+	}
+
+	if !errors.is_empty() {
+		errors.sort_by_key(|e| e.line);
+		return Err(errors);
+	}
+
+	// Separate .org blocks stay separate, so loading them leaves the memory in between alone
+	let mut segments: Vec<Segment> = Vec::new();
+	for (address, byte) in image.iter().enumerate() {
+		let Some(byte) = byte else { continue; };
+		match segments.last_mut() {
+			Some(segment) if segment.address as usize + segment.data.len() == address => segment.data.push(*byte),
+			_ => segments.push(Segment{address: address as u16, data: vec![*byte]}),
+		}
+	}
+	let entry = segments.first().map(|s| s.address);
+
+	// Constants outside of the address space can't be shown as labels
+	let symbols = symbols.into_iter()
+		.filter(|(_, value)| (0..=0xffff).contains(value))
+		.map(|(name, value)| (name, value as u16))
+		.collect();
+
+	Ok(Assembly{image: LoadedImage{segments, entry}, symbols, lines: source_lines})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assemble_ok(source: &str) -> Assembly {
+		match assemble(source) {
+			Ok(assembly) => assembly,
+			Err(errors) => panic!("{}", errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n")),
+		}
+	}
+
+	fn bytes(line: &str) -> Vec<u8> {
+		let assembly = assemble_ok(&format!("\t.org $1000\n\t{}\n", line));
+		assert_eq!(assembly.image.segments.len(), 1);
+		assembly.image.segments[0].data.clone()
+	}
+
+	fn errors(source: &str) -> Vec<String> {
+		match assemble(source) {
+			Ok(_) => Vec::new(),
+			Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+		}
+	}
+
+	#[test]
+	fn addressing_modes() {
+		assert_eq!(bytes("rts"), [0x60]);
+		assert_eq!(bytes("asl"), [0x0a]);
+		assert_eq!(bytes("asl a"), [0x0a]);
+		assert_eq!(bytes("lda #$10"), [0xa9, 0x10]);
+		assert_eq!(bytes("lda $10"), [0xa5, 0x10]);
+		assert_eq!(bytes("lda $1234"), [0xad, 0x34, 0x12]);
+		assert_eq!(bytes("lda $10,x"), [0xb5, 0x10]);
+		assert_eq!(bytes("lda $1234,X"), [0xbd, 0x34, 0x12]);
+		assert_eq!(bytes("lda $1234,y"), [0xb9, 0x34, 0x12]);
+		assert_eq!(bytes("ldx $10,y"), [0xb6, 0x10]);
+		assert_eq!(bytes("stx $10,y"), [0x96, 0x10]);
+		assert_eq!(bytes("lda ($10,x)"), [0xa1, 0x10]);
+		assert_eq!(bytes("lda ($10),y"), [0xb1, 0x10]);
+		assert_eq!(bytes("jmp ($1234)"), [0x6c, 0x34, 0x12]);
+	}
+
+	#[test]
+	fn mode_fallbacks() {
+		// There's no zero page,Y form of LDA
+		assert_eq!(bytes("lda $12,y"), [0xb9, 0x12, 0x00]);
+		// Parentheses that don't wrap the whole operand are part of the expression
+		assert_eq!(bytes("lda ($10+1)*2"), [0xa5, 0x22]);
+		assert_eq!(bytes("lda #<$1234"), [0xa9, 0x34]);
+		assert_eq!(bytes("lda #>$1234"), [0xa9, 0x12]);
+	}
+
+	#[test]
+	fn forward_references() {
+		let assembly = assemble_ok("\t.org $1000
+start:	ldx #0
+loop:	lda later
+	beq done
+	inx
+	bne loop
+done:	jmp start
+	.org $10
+later:	.byte 0
+");
+		// later isn't known in pass 1, so it's assembled as absolute even though it's in zero page
+		assert_eq!(assembly.origin(), 0x0010);
+		let segments: Vec<_> = assembly.image.segments.iter().map(|s| (s.address, s.data.clone())).collect();
+		assert_eq!(segments, [
+			(0x0010, vec![0x00]),
+			(0x1000, vec![0xa2, 0x00, 0xad, 0x10, 0x00, 0xf0, 0x03, 0xe8, 0xd0, 0xf8, 0x4c, 0x00, 0x10]),
+		]);
+		assert_eq!(assembly.symbols["done"], 0x100a);
+		assert_eq!(assembly.symbols["later"], 0x0010);
+		assert_eq!(assembly.lines[&0x1005], 4);
+	}
+
+	#[test]
+	fn constants_and_data() {
+		let assembly = assemble_ok("\t.org $0800
+screen = base + $100
+base = $0300
+	lda screen
+	.byte 1, \"hi\", 'a'
+	.word screen, *
+");
+		assert_eq!(assembly.origin(), 0x0800);
+		assert_eq!(assembly.image.segments[0].data, [0xad, 0x00, 0x04, 0x01, b'h', b'i', b'a', 0x00, 0x04, 0x07, 0x08]);
+	}
+
+	#[test]
+	fn forward_constants() {
+		assert_eq!(assemble_ok("x = later + 1\n\t.org $10\nlater:\tnop\n").symbols["x"], 0x11);
+		assert_eq!(errors("x = later\nx:\tnop\nlater:\tnop\n"), ["line 1: Symbol \"x\" defined more than once"]);
+		assert_eq!(errors("\t.org $10\nx = * + later\nlater:\tnop\n"), ["line 2: A constant can't use * and a label defined later"]);
+	}
+
+	#[test]
+	fn prg_header() {
+		assert_eq!(assemble_ok("\t.org $c000\n\tnop\n").to_prg(), [0x00, 0xc0, 0xea]);
+		assert_eq!(assemble_ok("\t.org $c003\n\trts\n\t.org $c000\n\tnop\n").to_prg(), [0x00, 0xc0, 0xea, 0x00, 0x00, 0x60]);
+	}
+
+	#[test]
+	fn errors_from_both_passes() {
+		let found = errors("\t.org $1000\n\tfoo\n\tlda #$100\n\tjmp nowhere\n\tjmp ($10),y\n");
+		assert_eq!(found, [
+			"line 2: Unknown instruction \"foo\"",
+			"line 3: Value $100 does not fit in a byte",
+			"line 4: Undefined symbol \"nowhere\"",
+			"line 5: Addressing mode IndirectY is not valid for JMP",
+		]);
+	}
+
+	#[test]
+	fn branch_range() {
+		let found = errors("\t.org $1000\n\tbne far\n\t.org $1100\nfar:\tnop\n");
+		assert_eq!(found, ["line 2: Branch target $1100 out of range (254 bytes)"]);
+		assert_eq!(errors("a:\tnop\na:\tnop\n"), ["line 2: Symbol \"a\" defined more than once"]);
+	}
+
+	#[test]
+	fn single_lines() {
+		let mut symbols = SymbolTable::new();
+		symbols.insert("print", 0xffd2);
+		assert_eq!(assemble_line("jsr print", 0x0600, &symbols), Ok(vec![0x20, 0xd2, 0xff]));
+		assert_eq!(assemble_line("bne *", 0x0600, &symbols), Ok(vec![0xd0, 0xfe]));
+		assert!(assemble_line("x = 1", 0x0600, &symbols).is_err());
+	}
+}
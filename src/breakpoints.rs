@@ -0,0 +1,170 @@
+// breakpoints.rs
+
+use crate::expression::{self, Expr};
+use crate::program::Program;
+
+pub struct Condition {
+	pub text: String,
+	pub expr: Expr,
+}
+
+// A breakpoint condition that couldn't be evaluated, with the breakpoint the program stopped at for it, so that
+// whoever is running the program can show the error
+pub struct ConditionError {
+	pub id: u32,
+	pub message: String,
+}
+
+pub struct Breakpoint {
+	pub id: u32,
+	pub address: u16,
+	pub condition: Option<Condition>,
+	pub enabled: bool,
+	pub temporary: bool,
+	pub hits: u32,
+	pub ignore_count: u32,
+}
+
+impl Condition {
+	pub fn parse(text: &str) -> Result<Self, String> {
+		Ok(Condition{text: text.to_string(), expr: expression::parse(text)?})
+	}
+
+	pub fn is_true(&self, program: &Program) -> Result<bool, String> {
+		self.expr.evaluate(program).map(|value| value != 0).map_err(|message| format!("In condition \"{}\": {}", self.text, message))
+	}
+}
+
+impl Breakpoint {
+	pub fn new(id: u32, address: u16, condition: Option<Condition>, temporary: bool) -> Self {
+		Breakpoint{
+			id,
+			address,
+			condition,
+			enabled: true,
+			temporary,
+			hits: 0,
+			ignore_count: 0,
+		}
+	}
+
+	// Whether execution at the breakpoint's address counts as a hit, ignore counts aside
+	pub fn is_triggered(&self, program: &Program) -> Result<bool, String> {
+		match &self.condition {
+			Some(condition) if self.enabled => condition.is_true(program),
+			_ => Ok(self.enabled),
+		}
+	}
+
+	pub fn describe(&self) -> String {
+		let mut text = format!("{}{}: ${:04x}", if self.temporary { "temporary " } else { "" }, self.id, self.address);
+		if let Some(condition) = &self.condition {
+			text += format!(" if {}", condition.text).as_str();
+		}
+		if !self.enabled {
+			text += " (disabled)";
+		}
+		text += format!(", hit {} time{}", self.hits, if self.hits == 1 { "" } else { "s" }).as_str();
+		if self.ignore_count > 0 {
+			text += format!(", ignoring next {}", self.ignore_count).as_str();
+		}
+		text
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+	Read,
+	Write,
+	Change,
+}
+
+pub struct Watchpoint {
+	pub kind: WatchKind,
+	pub start: u16,
+	pub end: u16,
+}
+
+pub struct WatchHit {
+	pub kind: WatchKind,
+	pub address: u16,
+	pub old_value: u8,
+	pub new_value: u8,
+	pub pc: u16,
+}
+
+impl WatchKind {
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"r" | "read" => Some(WatchKind::Read),
+			"w" | "write" => Some(WatchKind::Write),
+			"c" | "change" => Some(WatchKind::Change),
+			_ => None,
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			WatchKind::Read => "read",
+			WatchKind::Write => "write",
+			WatchKind::Change => "change",
+		}
+	}
+}
+
+impl Watchpoint {
+	// Whether an access of this kind to the address should be reported
+	pub fn matches(&self, kind: WatchKind, address: u16, old_value: u8, new_value: u8) -> bool {
+		if address < self.start || address > self.end {
+			return false;
+		}
+
+		match self.kind {
+			WatchKind::Read => kind == WatchKind::Read,
+			WatchKind::Write => kind == WatchKind::Write,
+			WatchKind::Change => kind == WatchKind::Write && old_value != new_value,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn conditions() {
+		let mut program = Program::new();
+		program.poke(0x10, &[0x42]);
+		let breakpoint = Breakpoint::new(1, 0x0600, Some(Condition::parse("x == 3 && mem[$10] == $42").unwrap()), false);
+
+		assert_eq!(breakpoint.is_triggered(&program), Ok(false));
+		program.reg_x = 3;
+		assert_eq!(breakpoint.is_triggered(&program), Ok(true));
+
+		let disabled = Breakpoint{enabled: false, ..Breakpoint::new(2, 0x0600, None, false)};
+		assert_eq!(disabled.is_triggered(&program), Ok(false));
+
+		let broken = Breakpoint::new(3, 0x0600, Some(Condition::parse("nowhere > 1").unwrap()), false);
+		assert_eq!(broken.is_triggered(&program), Err(String::from("In condition \"nowhere > 1\": Undefined symbol \"nowhere\"")));
+		assert!(Condition::parse("x ==").is_err());
+	}
+
+	#[test]
+	fn conditional_breakpoint_in_a_loop() {
+		// ldx #$00 / loop: inx / cpx #$05 / bne loop / brk
+		let mut program = Program::new();
+		program.poke(0x0600, &[0xa2, 0x00, 0xe8, 0xe0, 0x05, 0xd0, 0xfb, 0x00]);
+		program.program_counter = 0x0600;
+		program.add_breakpoint(0x0603, Some(Condition::parse("x == 3").unwrap()), false);
+
+		let mut stops = Vec::new();
+		while !program.flag_break {
+			if let Ok(Some(id)) = program.check_breakpoints(program.program_counter) {
+				stops.push((id, program.reg_x));
+			}
+			program.step(false).unwrap();
+		}
+		assert_eq!(stops, [(1, 3)]);
+		assert_eq!(program.breakpoints[0].hits, 1);
+	}
+}
@@ -0,0 +1,56 @@
+// callstack.rs
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameKind {
+	Call,
+	Interrupt,
+}
+
+// A shadow of the subroutine calls and interrupts on the hardware stack, kept by watching JSR, BRK,
+// interrupt entry and the stack pointer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+	pub kind: FrameKind,
+	pub call_site: u16,
+	pub subroutine: u16,
+	pub return_address: u16,
+	// The stack pointer from before the return address was pushed
+	pub stack_pointer: u8,
+}
+
+// Something that put the shadow stack out of step with how the program really returns
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StackAnomaly {
+	// A return address was pulled or skipped over by something other than RTS or RTI, e.g. PLA PLA or TXS
+	Discarded{frame: Frame, address: u16},
+	// RTS or RTI with no call to return from, usually an address pushed by hand and used as a jump
+	UnmatchedReturn{address: u16, target: u16},
+	// A return that didn't go back to where the call came from, because the address on the stack was changed
+	Redirected{frame: Frame, address: u16, target: u16},
+}
+
+// The stack pointer wrapping around, which the stack guard looks for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StackFault {
+	// A push with the stack pointer at $00, so the next byte goes to $01ff
+	Overflow{address: u16},
+	// A pull with the stack pointer at $ff, so the byte comes from $0100
+	Underflow{address: u16},
+}
+
+// Who put a byte on the stack
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StackWriter {
+	Instruction(u16),
+	Interrupt,
+}
+
+impl Frame {
+	// The address the call pushed, which is what RTS or RTI pulls back off the stack
+	pub fn pushed_address(&self) -> u16 {
+		match self.kind {
+			FrameKind::Call => self.return_address.wrapping_sub(1),
+			FrameKind::Interrupt => self.return_address,
+		}
+	}
+}
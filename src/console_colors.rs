@@ -27,3 +27,14 @@ macro_rules! con_reset {
 		"\x1b[0m"
 	};
 }
+
+// Set whenever an error is printed, so --exit-on-error can stop a batch run at the first one
+pub static ERROR_REPORTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+#[macro_export]
+macro_rules! print_error {
+	($($arg:tt)*) => {{
+		$crate::console_colors::ERROR_REPORTED.store(true, std::sync::atomic::Ordering::Relaxed);
+		eprintln!("{}Error:{} {}", con_red!(), con_reset!(), format!($($arg)*));
+	}};
+}
@@ -0,0 +1,270 @@
+// coverage.rs
+
+// Coverage counts how often each instruction ran and which way each conditional branch went. Reports
+// list instructions that never ran as well: with source info loaded that's every assembled instruction,
+// otherwise the code found by reading on from executed instructions and from the targets of branches
+// that were never taken, up to the next JMP, RTS, RTI or BRK.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::debug;
+use crate::program::Program;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BranchCounts {
+	pub taken: u64,
+	pub not_taken: u64,
+}
+
+pub struct Coverage {
+	pub executed: BTreeMap<u16, u64>,
+	pub branches: BTreeMap<u16, BranchCounts>,
+}
+
+struct Line {
+	address: u16,
+	text: String,
+	count: u64,
+	branch: Option<BranchCounts>,
+}
+
+#[derive(Default)]
+struct Summary {
+	instructions: usize,
+	executed: usize,
+	branches: usize,
+	both_ways: usize,
+	one_way: usize,
+}
+
+impl Coverage {
+	pub fn new() -> Self {
+		Coverage{executed: BTreeMap::new(), branches: BTreeMap::new()}
+	}
+
+	// branch_taken is None for anything that isn't a conditional branch
+	pub fn record(&mut self, address: u16, branch_taken: Option<bool>) {
+		*self.executed.entry(address).or_insert(0) += 1;
+		if let Some(taken) = branch_taken {
+			let counts = self.branches.entry(address).or_default();
+			if taken {
+				counts.taken += 1;
+			}
+			else {
+				counts.not_taken += 1;
+			}
+		}
+	}
+}
+
+// =============================================================
+
+fn is_branch(program: &Program, address: u16) -> bool {
+	matches!(program.get_memory(address), 0x10 | 0x30 | 0x50 | 0x70 | 0x90 | 0xb0 | 0xd0 | 0xf0)
+}
+
+fn branch_target(program: &Program, address: u16) -> u16 {
+	address.wrapping_add(2).wrapping_add(program.get_memory(address.wrapping_add(1)) as i8 as u16)
+}
+
+// BRK, JMP, RTI and RTS never carry on to the next instruction
+fn ends_flow(program: &Program, address: u16) -> bool {
+	matches!(program.get_memory(address), 0x00 | 0x4c | 0x6c | 0x40 | 0x60)
+}
+
+fn instruction_addresses(program: &Program, coverage: &Coverage) -> BTreeSet<u16> {
+	if let Some(source) = &program.source {
+		let mut addresses: BTreeSet<u16> = source.lines.keys().copied().collect();
+		addresses.extend(coverage.executed.keys());
+		return addresses;
+	}
+
+	let mut starts: Vec<u16> = coverage.executed.keys().copied().collect();
+	starts.extend(coverage.branches.iter().filter(|(_, counts)| counts.taken == 0).map(|(address, _)| branch_target(program, *address)));
+	starts.sort_unstable();
+
+	let mut addresses = BTreeSet::new();
+	for start in starts {
+		let mut address = start;
+		while addresses.insert(address) {
+			if ends_flow(program, address) {
+				break;
+			}
+			let (_, len) = debug::disassemble(program, address);
+			let next = address.wrapping_add(len);
+			// Stop before running into the middle of an instruction that ran, or off the end of memory
+			if next < address || coverage.executed.range(address + 1..next).next().is_some() {
+				break;
+			}
+			address = next;
+		}
+	}
+	addresses
+}
+
+fn listing(program: &Program, coverage: &Coverage) -> (Vec<Line>, Summary) {
+	let mut summary = Summary::default();
+	let lines: Vec<Line> = instruction_addresses(program, coverage).into_iter().map(|address| {
+		let count = coverage.executed.get(&address).copied().unwrap_or(0);
+		let branch = if is_branch(program, address) { Some(coverage.branches.get(&address).copied().unwrap_or_default()) } else { None };
+
+		summary.instructions += 1;
+		summary.executed += (count > 0) as usize;
+		if let Some(counts) = branch {
+			summary.branches += 1;
+			match (counts.taken > 0, counts.not_taken > 0) {
+				(true, true) => { summary.both_ways += 1; },
+				(false, false) => {},
+				_ => { summary.one_way += 1; },
+			}
+		}
+
+		Line{address, text: debug::disassemble(program, address).0, count, branch}
+	}).collect();
+	(lines, summary)
+}
+
+fn percent(part: usize, whole: usize) -> f64 {
+	if whole == 0 { 100.0 } else { part as f64 * 100.0 / whole as f64 }
+}
+
+fn summary_text(summary: &Summary) -> String {
+	format!("Instructions: {} of {} executed ({:.1}%)\nBranches: {} of {} went both ways, {} only one way, {} never ran\n",
+		summary.executed, summary.instructions, percent(summary.executed, summary.instructions),
+		summary.both_ways, summary.branches, summary.one_way, summary.branches - summary.both_ways - summary.one_way)
+}
+
+fn branch_text(counts: &BranchCounts) -> String {
+	match (counts.taken, counts.not_taken) {
+		(0, 0) => String::new(),
+		(taken, 0) => format!("always taken ({})", taken),
+		(0, not_taken) => format!("never taken ({})", not_taken),
+		(taken, not_taken) => format!("taken {}, not taken {}", taken, not_taken),
+	}
+}
+
+// A branch that only ever went one way is flagged with !
+fn is_partial(line: &Line) -> bool {
+	line.branch.map(|counts| line.count > 0 && (counts.taken == 0 || counts.not_taken == 0)).unwrap_or(false)
+}
+
+pub fn summary(program: &Program, coverage: &Coverage) -> String {
+	summary_text(&listing(program, coverage).1)
+}
+
+// Annotated disassembly, gcov style: the execution count, or ##### for instructions that never ran
+pub fn text_report(program: &Program, coverage: &Coverage) -> String {
+	let (lines, summary) = listing(program, coverage);
+	let mut text = summary_text(&summary);
+	let mut previous: Option<u16> = None;
+	for line in &lines {
+		if previous.map(|p| p.wrapping_add(debug::disassemble(program, p).1) != line.address).unwrap_or(false) {
+			text += "\n";
+		}
+		previous = Some(line.address);
+
+		if let Some(name) = program.symbols.name_for(line.address) {
+			text += &format!("{:>10} {}:\n", "", name);
+		}
+		let count = if line.count > 0 { line.count.to_string() } else { String::from("#####") };
+		let annotated = format!("{:>9} {} ${:04x}: {:<14} {}", count, if is_partial(line) { '!' } else { ' ' }, line.address, line.text,
+			line.branch.as_ref().map(branch_text).unwrap_or_default());
+		text += annotated.trim_end();
+		text += "\n";
+	}
+	text
+}
+
+fn escape_html(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+pub fn html_report(program: &Program, coverage: &Coverage) -> String {
+	let (lines, summary) = listing(program, coverage);
+	let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>fe6502 coverage</title>\n<style>
+body { font-family: monospace; }
+.hit { background: #d4f8d4; }
+.miss { background: #f8d4d4; }
+.partial { background: #f8f0c0; }
+.label { color: #086; font-weight: bold; }
+</style>\n</head>\n<body>\n");
+
+	html += &format!("<pre>{}</pre>\n<pre>\n", escape_html(&summary_text(&summary)));
+	for line in &lines {
+		if let Some(name) = program.symbols.name_for(line.address) {
+			html += &format!("<span class=\"label\">{}:</span>\n", escape_html(name));
+		}
+		let class = if line.count == 0 { "miss" } else if is_partial(line) { "partial" } else { "hit" };
+		let count = if line.count > 0 { line.count.to_string() } else { String::from("#####") };
+		html += &format!("<span class=\"{}\">{:>9} ${:04x}: {:<14} {}</span>\n", class, count, line.address, escape_html(&line.text),
+			line.branch.as_ref().map(branch_text).unwrap_or_default());
+	}
+	html += "</pre>\n</body>\n</html>\n";
+	html
+}
+
+// lcov tracefile for genhtml and editor plugins, which needs to know the source line of each instruction
+pub fn lcov_report(program: &Program, coverage: &Coverage) -> Result<String, String> {
+	let source = program.source.as_ref().ok_or("lcov needs source line info, assemble the program with asm first")?;
+
+	// A line with several instructions counts as run as often as the most run of them
+	let mut line_counts: BTreeMap<usize, u64> = BTreeMap::new();
+	let mut branch_lines = Vec::new();
+	for (address, line) in &source.lines {
+		let count = coverage.executed.get(address).copied().unwrap_or(0);
+		let entry = line_counts.entry(*line).or_insert(0);
+		*entry = (*entry).max(count);
+		if is_branch(program, *address) {
+			branch_lines.push((*line, count, coverage.branches.get(address).copied().unwrap_or_default()));
+		}
+	}
+
+	let mut text = format!("TN:\nSF:{}\n", source.file);
+	let mut branches_hit = 0;
+	for (block, (line, count, counts)) in branch_lines.iter().enumerate() {
+		for (branch, taken) in [(0, counts.taken), (1, counts.not_taken)] {
+			// - marks a branch on a line that never ran
+			let taken_text = if *count == 0 { String::from("-") } else { taken.to_string() };
+			text += &format!("BRDA:{},{},{},{}\n", line, block, branch, taken_text);
+			branches_hit += (taken > 0) as usize;
+		}
+	}
+	text += &format!("BRF:{}\nBRH:{}\n", branch_lines.len() * 2, branches_hit);
+
+	for (line, count) in &line_counts {
+		text += &format!("DA:{},{}\n", line, count);
+	}
+	text += &format!("LF:{}\nLH:{}\nend_of_record\n", line_counts.len(), line_counts.values().filter(|c| **c > 0).count());
+	Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::symbols::SourceMap;
+
+	#[test]
+	fn lcov() {
+		// ldx #$02 / loop: dex / bne loop / brk / nop / beq *+2, with the last two on one line
+		let mut program = Program::new();
+		program.poke(0x0600, &[0xa2, 0x02, 0xca, 0xd0, 0xfd, 0x00, 0xea, 0xf0, 0x00]);
+		program.program_counter = 0x0600;
+		assert!(lcov_report(&program, &Coverage::new()).is_err());
+
+		let lines = [(0x0600, 1), (0x0602, 2), (0x0603, 3), (0x0605, 4), (0x0606, 5), (0x0607, 5)].iter().copied().collect();
+		program.source = Some(SourceMap{file: String::from("main.s"), lines});
+		program.coverage = Some(Coverage::new());
+		while !program.flag_break {
+			program.step(false).unwrap();
+		}
+
+		let coverage = program.coverage.take().unwrap();
+		assert_eq!(lcov_report(&program, &coverage), Ok([
+			"TN:", "SF:main.s",
+			"BRDA:3,0,0,1", "BRDA:3,0,1,1", "BRDA:5,1,0,-", "BRDA:5,1,1,-", "BRF:4", "BRH:2",
+			"DA:1,1", "DA:2,2", "DA:3,2", "DA:4,1", "DA:5,0", "LF:5", "LH:4",
+			"end_of_record\n",
+		].join("\n")));
+	}
+}
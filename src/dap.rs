@@ -0,0 +1,582 @@
+// dap.rs
+
+// A Debug Adapter Protocol server over stdin and stdout. Stdout carries the protocol, so nothing here
+// may print to it; errors go to the client as output events instead.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use crate::breakpoints::Condition;
+use crate::debug;
+use crate::expression;
+use crate::program::{Program, StepMode};
+use crate::symbols;
+
+const THREAD_ID: i64 = 1;
+const REGISTERS_REFERENCE: i64 = 1;
+const FLAGS_REFERENCE: i64 = 2;
+
+// How many instructions run between checks for new requests, such as pause
+const INSTRUCTIONS_PER_POLL: u32 = 1000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum BreakpointKind {
+	Source,
+	Function,
+	Instruction,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Resume {
+	Continue,
+	Step,
+	Until(StepMode),
+}
+
+// Messages go to stdout, or anything else that takes bytes
+struct Server<'a, W: Write> {
+	program: &'a mut Program,
+	requests: Receiver<Value>,
+	output: W,
+	seq: i64,
+	// Each breakpoint the client set, so one kind can be replaced without touching the others. An id shared by
+	// several kinds appears once for each.
+	breakpoint_kinds: Vec<(u32, BreakpointKind)>,
+	stop_on_entry: bool,
+	running: Option<Resume>,
+	// Set when resuming from a stop, so the breakpoint the program is sitting on doesn't stop it again
+	skip_breakpoint: bool,
+}
+
+// =============================================================
+
+// Reads Content-Length framed messages until stdin closes
+fn read_messages(sender: mpsc::Sender<Value>) {
+	let mut stdin = BufReader::new(io::stdin());
+	loop {
+		let mut length = None;
+		loop {
+			let mut line = String::new();
+			if stdin.read_line(&mut line).unwrap_or(0) == 0 {
+				return;
+			}
+
+			let line = line.trim();
+			if line.is_empty() {
+				break;
+			}
+			if let Some(value) = line.strip_prefix("Content-Length:") {
+				length = value.trim().parse::<usize>().ok();
+			}
+		}
+
+		let mut body = vec![0; length.unwrap_or(0)];
+		if stdin.read_exact(&mut body).is_err() {
+			return;
+		}
+		if let Ok(message) = serde_json::from_slice(&body) {
+			if sender.send(message).is_err() {
+				return;
+			}
+		}
+	}
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+	const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+	let mut text = String::new();
+	for chunk in bytes.chunks(3) {
+		let n = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+		for i in 0..4 {
+			if i <= chunk.len() {
+				text.push(ALPHABET[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+			}
+			else {
+				text.push('=');
+			}
+		}
+	}
+	text
+}
+
+// Memory references are 0x or $ hex, or a symbol
+fn parse_reference(program: &Program, text: &str) -> Option<u16> {
+	match text.strip_prefix("0x") {
+		Some(hex) => u16::from_str_radix(hex, 16).ok(),
+		None => program.symbols.parse_address(text),
+	}
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+	match (Path::new(a).canonicalize(), Path::new(b).canonicalize()) {
+		(Ok(a), Ok(b)) => a == b,
+		_ => Path::new(a).file_name() == Path::new(b).file_name(),
+	}
+}
+
+fn parse_condition(breakpoint: &Value) -> Result<Option<Condition>, String> {
+	match breakpoint["condition"].as_str() {
+		Some(text) if !text.trim().is_empty() => Condition::parse(text).map(Some),
+		_ => Ok(None),
+	}
+}
+
+// =============================================================
+
+impl<'a, W: Write> Server<'a, W> {
+	fn send(&mut self, mut message: Value) {
+		self.seq += 1;
+		message["seq"] = json!(self.seq);
+		let text = message.to_string();
+		write!(self.output, "Content-Length: {}\r\n\r\n{}", text.len(), text).ok();
+		self.output.flush().ok();
+	}
+
+	fn respond(&mut self, request: &Value, result: Result<Value, String>) {
+		let mut response = json!({
+			"type": "response",
+			"request_seq": request["seq"],
+			"command": request["command"],
+			"success": result.is_ok(),
+		});
+		match result {
+			Ok(body) => { response["body"] = body; },
+			Err(message) => { response["message"] = json!(message); },
+		}
+		self.send(response);
+	}
+
+	fn event(&mut self, event: &str, body: Value) {
+		self.send(json!({"type": "event", "event": event, "body": body}));
+	}
+
+	fn stopped(&mut self, reason: &str, breakpoint: Option<u32>) {
+		self.running = None;
+		let mut body = json!({"reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true});
+		if let Some(id) = breakpoint {
+			body["hitBreakpointIds"] = json!([id]);
+		}
+		self.event("stopped", body);
+	}
+
+	fn resume(&mut self, resume: Resume) {
+		self.running = Some(resume);
+		self.skip_breakpoint = true;
+	}
+
+	// Replaces all breakpoints of one kind with the given addresses and conditions. A source, function and instruction
+	// breakpoint at the same address with the same condition share one id, which stays until none of them want it.
+	fn replace_breakpoints(&mut self, kind: BreakpointKind, wanted: Vec<Result<(u16, Option<Condition>), String>>) -> Vec<Value> {
+		let old: Vec<u32> = self.breakpoint_kinds.iter().filter(|(_, k)| *k == kind).map(|(id, _)| *id).collect();
+		self.breakpoint_kinds.retain(|(_, k)| *k != kind);
+		for id in old {
+			if !self.breakpoint_kinds.iter().any(|(other, _)| *other == id) {
+				self.program.remove_breakpoint(id);
+			}
+		}
+
+		wanted.into_iter().map(|breakpoint| match breakpoint {
+			Ok((address, condition)) => {
+				let (id, _) = self.program.add_breakpoint(address, condition, false);
+				self.breakpoint_kinds.push((id, kind));
+				let mut result = json!({"id": id, "verified": true, "instructionReference": format!("0x{:04x}", address)});
+				if let Some(line) = self.program.source.as_ref().and_then(|s| s.line_for(address)) {
+					result["line"] = json!(line);
+				}
+				result
+			},
+			Err(message) => json!({"verified": false, "message": message}),
+		}).collect()
+	}
+
+	fn function_name(&self, address: u16) -> String {
+		self.program.symbols.format_address(address, 4)
+	}
+
+	fn stack_frame(&self, id: usize, name: String, address: u16) -> Value {
+		let mut frame = json!({
+			"id": id,
+			"name": name,
+			"line": 0,
+			"column": 0,
+			"instructionPointerReference": format!("0x{:04x}", address),
+		});
+
+		if let Some(source) = &self.program.source {
+			if let Some(line) = source.line_for(address) {
+				let name = Path::new(&source.file).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+				frame["source"] = json!({"name": name, "path": source.file});
+				frame["line"] = json!(line);
+				frame["column"] = json!(1);
+			}
+		}
+		frame
+	}
+
+	// With symbols or source loaded, each JSR still on the call stack becomes a frame
+	fn stack_trace(&self) -> Value {
+		let program = &*self.program;
+		let mut frames = Vec::new();
+		let mut pc = program.program_counter;
+		let tracked = program.source.is_some() || program.symbols.iter().next().is_some();
+
+		if tracked {
+			for frame in program.call_stack.iter().rev() {
+				frames.push(self.stack_frame(frames.len(), self.function_name(frame.subroutine), pc));
+				pc = frame.call_site;
+			}
+		}
+		frames.push(self.stack_frame(frames.len(), self.function_name(program.origin), pc));
+
+		json!({"stackFrames": frames, "totalFrames": frames.len()})
+	}
+
+	fn variables(&self, reference: i64) -> Value {
+		let program = &*self.program;
+		let byte = |name: &str, value: u8| json!({"name": name, "value": format!("${:02x} ({})", value, value), "variablesReference": 0});
+		let variables = match reference {
+			REGISTERS_REFERENCE => vec![
+				byte("A", program.reg_a),
+				byte("X", program.reg_x),
+				byte("Y", program.reg_y),
+				byte("SP", program.stack_pointer),
+				json!({"name": "PC", "value": format!("${:04x}", program.program_counter), "variablesReference": 0,
+					"memoryReference": format!("0x{:04x}", program.program_counter)}),
+				byte("P", program.get_status()),
+				json!({"name": "cycles", "value": program.cycles.to_string(), "variablesReference": 0}),
+			],
+			FLAGS_REFERENCE => [
+				("N", program.flag_negative), ("V", program.flag_overflow), ("B", program.flag_break), ("D", program.flag_decimal),
+				("I", program.flag_interrupt), ("Z", program.flag_zero), ("C", program.flag_carry),
+			].iter().map(|(name, set)| json!({"name": name, "value": (*set as u8).to_string(), "variablesReference": 0})).collect(),
+			_ => Vec::new(),
+		};
+		json!({"variables": variables})
+	}
+
+	fn read_memory(&self, args: &Value) -> Result<Value, String> {
+		let reference = args["memoryReference"].as_str().unwrap_or("");
+		let base = parse_reference(self.program, reference).ok_or(format!("Invalid memory reference \"{}\"", reference))?;
+		let address = base.wrapping_add(args["offset"].as_i64().unwrap_or(0) as u16);
+		let count = args["count"].as_u64().unwrap_or(0).min(0x10000) as usize;
+		let bytes: Vec<u8> = (0..count).map(|i| self.program.get_memory(address.wrapping_add(i as u16))).collect();
+		Ok(json!({"address": format!("0x{:04x}", address), "data": base64_encode(&bytes)}))
+	}
+
+	// Returns false once the client is done with the session
+	fn handle_request(&mut self, request: &Value) -> bool {
+		let args = &request["arguments"];
+		let command = request["command"].as_str().unwrap_or("");
+		let result = match command {
+			"initialize" => {
+				let capabilities = json!({
+					"supportsConfigurationDoneRequest": true,
+					"supportsFunctionBreakpoints": true,
+					"supportsConditionalBreakpoints": true,
+					"supportsInstructionBreakpoints": true,
+					"supportsReadMemoryRequest": true,
+					"supportsEvaluateForHovers": true,
+					"supportsTerminateRequest": true,
+				});
+				self.respond(request, Ok(capabilities));
+				self.event("initialized", json!({}));
+				return true;
+			},
+
+			// Attaching picks up a program that hasn't started, so it always stops on entry
+			"launch" | "attach" => {
+				self.stop_on_entry = command == "attach" || args["stopOnEntry"].as_bool().unwrap_or(false);
+				let loaded = match args["program"].as_str() {
+					Some(filename) => self.program.load(filename, None, None).map(|_| ()),
+					None if command == "attach" => Ok(()),
+					None => Err(String::from("Missing \"program\" in the launch configuration")),
+				};
+				let symbols = match args["symbols"].as_str() {
+					Some(filename) => symbols::load_symbol_file(filename).map(|symbols| {
+						for (name, address) in &symbols {
+							self.program.symbols.insert(name, *address);
+						}
+					}),
+					None => Ok(()),
+				};
+				loaded.and(symbols).map(|_| json!({}))
+			},
+
+			"setBreakpoints" => {
+				let path = args["source"]["path"].as_str().unwrap_or("").to_string();
+				let wanted = args["breakpoints"].as_array().cloned().unwrap_or_default().iter().map(|breakpoint| {
+					let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+					let source = self.program.source.as_ref().filter(|s| same_file(&s.file, &path)).ok_or("Source file isn't loaded")?;
+					let (address, _) = source.address_for(line).ok_or("No code at or after this line")?;
+					Ok((address, parse_condition(breakpoint)?))
+				}).collect();
+				Ok(json!({"breakpoints": self.replace_breakpoints(BreakpointKind::Source, wanted)}))
+			},
+
+			"setFunctionBreakpoints" => {
+				let wanted = args["breakpoints"].as_array().cloned().unwrap_or_default().iter().map(|breakpoint| {
+					let name = breakpoint["name"].as_str().unwrap_or("");
+					let address = self.program.symbols.parse_address(name).ok_or(format!("Unknown function \"{}\"", name))?;
+					Ok((address, parse_condition(breakpoint)?))
+				}).collect();
+				Ok(json!({"breakpoints": self.replace_breakpoints(BreakpointKind::Function, wanted)}))
+			},
+
+			"setInstructionBreakpoints" => {
+				let wanted = args["breakpoints"].as_array().cloned().unwrap_or_default().iter().map(|breakpoint| {
+					let reference = breakpoint["instructionReference"].as_str().unwrap_or("");
+					let address = parse_reference(self.program, reference).ok_or(format!("Invalid instruction reference \"{}\"", reference))?;
+					let address = address.wrapping_add(breakpoint["offset"].as_i64().unwrap_or(0) as u16);
+					Ok((address, parse_condition(breakpoint)?))
+				}).collect();
+				Ok(json!({"breakpoints": self.replace_breakpoints(BreakpointKind::Instruction, wanted)}))
+			},
+
+			"configurationDone" => {
+				self.respond(request, Ok(json!({})));
+				if self.stop_on_entry {
+					self.stopped("entry", None);
+				}
+				else {
+					self.running = Some(Resume::Continue);
+					self.skip_breakpoint = false;
+				}
+				return true;
+			},
+
+			"threads" => Ok(json!({"threads": [{"id": THREAD_ID, "name": "6502"}]})),
+
+			"stackTrace" => Ok(self.stack_trace()),
+
+			"scopes" => Ok(json!({"scopes": [
+				{"name": "Registers", "presentationHint": "registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false},
+				{"name": "Flags", "variablesReference": FLAGS_REFERENCE, "expensive": false},
+			]})),
+
+			"variables" => Ok(self.variables(args["variablesReference"].as_i64().unwrap_or(0))),
+
+			"readMemory" => self.read_memory(args),
+
+			"evaluate" => {
+				let text = args["expression"].as_str().unwrap_or("");
+				expression::parse(text)
+					.and_then(|expr| expr.evaluate(&*self.program))
+					.map(|value| json!({"result": format!("${:x} ({})", value, value), "variablesReference": 0}))
+			},
+
+			"continue" => {
+				self.resume(Resume::Continue);
+				Ok(json!({"allThreadsContinued": true}))
+			},
+
+			"next" => {
+				let resume = StepMode::over(self.program).map(Resume::Until).unwrap_or(Resume::Step);
+				self.resume(resume);
+				Ok(json!({}))
+			},
+
+			"stepIn" => {
+				self.resume(Resume::Step);
+				Ok(json!({}))
+			},
+
+			"stepOut" => {
+				self.resume(Resume::Until(StepMode::Out{stack_pointer: self.program.stack_pointer}));
+				Ok(json!({}))
+			},
+
+			"pause" => {
+				self.respond(request, Ok(json!({})));
+				if self.running.is_some() {
+					self.stopped("pause", None);
+				}
+				return true;
+			},
+
+			"disconnect" | "terminate" => {
+				self.respond(request, Ok(json!({})));
+				self.event("terminated", json!({}));
+				return false;
+			},
+
+			_ => Err(format!("Unsupported request \"{}\"", command)),
+		};
+
+		self.respond(request, result);
+		true
+	}
+
+	// Runs a batch of instructions, stopping early for breakpoints, watchpoints, finished steps or the end of the program
+	fn run_batch(&mut self, resume: Resume) {
+		for _ in 0..INSTRUCTIONS_PER_POLL {
+			let pc = self.program.program_counter;
+			if !self.skip_breakpoint {
+				let hit = self.program.check_breakpoints(pc).unwrap_or_else(|error| {
+					self.event("output", json!({"category": "stderr", "output": format!("{}\n", error.message)}));
+					Some(error.id)
+				});
+				if let Some(id) = hit {
+					self.stopped("breakpoint", Some(id));
+					return;
+				}
+			}
+			self.skip_breakpoint = false;
+
+			let opcode = match self.program.step(false) {
+				Ok(opcode) => opcode,
+				Err(byte) => {
+					self.event("output", json!({"category": "stderr", "output": format!("{}\n", debug::describe_invalid_opcode(byte, pc))}));
+					self.stopped("exception", None);
+					return;
+				},
+			};
+
+			// BRK ends the program, as it does in the REPL
+			if self.program.flag_break {
+				self.running = None;
+				self.event("exited", json!({"exitCode": 0}));
+				self.event("terminated", json!({}));
+				return;
+			}
+
+			if !self.program.watch_hits.is_empty() {
+				self.stopped("data breakpoint", None);
+				return;
+			}
+
+			let done = match resume {
+				Resume::Continue => false,
+				Resume::Step => true,
+				Resume::Until(mode) => mode.is_done(self.program, &opcode),
+			};
+			if done {
+				self.stopped("step", None);
+				return;
+			}
+		}
+	}
+}
+
+pub fn serve(program: &mut Program) {
+	let (sender, requests) = mpsc::channel();
+	thread::spawn(move || read_messages(sender));
+
+	let mut server = Server{
+		program,
+		requests,
+		output: io::stdout(),
+		seq: 0,
+		breakpoint_kinds: Vec::new(),
+		stop_on_entry: false,
+		running: None,
+		skip_breakpoint: false,
+	};
+
+	loop {
+		// While the program runs, requests are only checked between batches of instructions
+		let request = match server.running {
+			Some(_) => match server.requests.try_recv() {
+				Ok(request) => Some(request),
+				Err(TryRecvError::Empty) => None,
+				Err(TryRecvError::Disconnected) => { return; },
+			},
+			None => match server.requests.recv() {
+				Ok(request) => Some(request),
+				Err(_) => { return; },
+			},
+		};
+
+		if let Some(request) = request {
+			if request["type"] == "request" && !server.handle_request(&request) {
+				return;
+			}
+		}
+
+		if let Some(resume) = server.running {
+			server.run_batch(resume);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::collections::BTreeMap;
+
+	use crate::symbols::SourceMap;
+
+	fn server(program: &mut Program) -> Server<'_, Vec<u8>> {
+		let (_, requests) = mpsc::channel();
+		Server{
+			program,
+			requests,
+			output: Vec::new(),
+			seq: 0,
+			breakpoint_kinds: Vec::new(),
+			stop_on_entry: false,
+			running: None,
+			skip_breakpoint: false,
+		}
+	}
+
+	// Sends one request and returns the body of its response
+	fn request(server: &mut Server<Vec<u8>>, command: &str, arguments: Value) -> Value {
+		server.output.clear();
+		server.handle_request(&json!({"seq": 1, "type": "request", "command": command, "arguments": arguments}));
+		let text = String::from_utf8(server.output.clone()).unwrap();
+		let (header, body) = text.split_once("\r\n\r\n").unwrap();
+		assert_eq!(header, format!("Content-Length: {}", body.len()));
+		let response: Value = serde_json::from_str(body).unwrap();
+		assert_eq!(response["command"], command);
+		response["body"].clone()
+	}
+
+	#[test]
+	fn base64() {
+		assert_eq!(base64_encode(b""), "");
+		assert_eq!(base64_encode(b"f"), "Zg==");
+		assert_eq!(base64_encode(b"fo"), "Zm8=");
+		assert_eq!(base64_encode(b"foo"), "Zm9v");
+		assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+		assert_eq!(base64_encode(&[0xff, 0xfe, 0x00, 0x3e]), "//4APg==");
+	}
+
+	#[test]
+	fn breakpoints_round_trip() {
+		let mut program = Program::new();
+		let lines: BTreeMap<u16, usize> = [(0x0600, 3), (0x0602, 4), (0x0605, 6)].iter().copied().collect();
+		program.source = Some(SourceMap{file: String::from("missing/main.s"), lines});
+		let mut server = server(&mut program);
+
+		let source = json!({"path": "elsewhere/main.s"});
+		let body = request(&mut server, "setBreakpoints", json!({"source": source, "breakpoints": [
+			{"line": 4}, {"line": 5, "condition": "x == 2"}, {"line": 9}, {"line": 3, "condition": "x =="},
+		]}));
+		let breakpoints = body["breakpoints"].as_array().unwrap();
+		assert_eq!(breakpoints.len(), 4);
+		assert_eq!((&breakpoints[0]["verified"], &breakpoints[0]["line"], &breakpoints[0]["instructionReference"]), (&json!(true), &json!(4), &json!("0x0602")));
+		assert_eq!((&breakpoints[1]["verified"], &breakpoints[1]["line"]), (&json!(true), &json!(6)));
+		assert_eq!(breakpoints[2]["verified"], json!(false));
+		assert_eq!(breakpoints[3]["verified"], json!(false));
+		let shared = breakpoints[0]["id"].as_u64().unwrap() as u32;
+
+		let addresses: Vec<(u16, Option<&str>)> = server.program.breakpoints.iter().map(|b| (b.address, b.condition.as_ref().map(|c| c.text.as_str()))).collect();
+		assert_eq!(addresses, [(0x0602, None), (0x0605, Some("x == 2"))]);
+
+		// The same breakpoint set as an instruction breakpoint shares its id
+		let body = request(&mut server, "setInstructionBreakpoints", json!({"breakpoints": [{"instructionReference": "0x0600", "offset": 2}]}));
+		assert_eq!(body["breakpoints"][0]["id"], json!(shared));
+		assert_eq!(server.program.breakpoints.len(), 2);
+
+		// Clearing the source breakpoints leaves the one the instruction breakpoint still wants
+		request(&mut server, "setBreakpoints", json!({"source": source, "breakpoints": []}));
+		assert_eq!(server.program.breakpoints.iter().map(|b| b.id).collect::<Vec<u32>>(), [shared]);
+		request(&mut server, "setInstructionBreakpoints", json!({"breakpoints": []}));
+		assert!(server.program.breakpoints.is_empty());
+	}
+}
@@ -1,10 +1,23 @@
 // debug.rs
 
-use crate::addressing::AddressMode;
+use crate::addressing::{AddressMode, make_u16};
 use crate::program::Program;
-use crate::opcodes::{Opcode, InstructionData};
+use crate::breakpoints::{WatchHit, WatchKind};
+use crate::callstack::{FrameKind, StackAnomaly, StackFault, StackWriter};
+use crate::opcodes::{Opcode, InstructionData, INSTRUCTION_DATA};
 
+use num::FromPrimitive;
+
+// Called after the addressing mode has run, so the operand is re-read from memory rather than taken from abs_address,
+// which by then holds the effective address
 pub fn print_instruction(program: &mut Program, address: u16, byte: u8, opcode: &Opcode, instruction_data: &InstructionData) {
+	let lo = program.get_memory(address.wrapping_add(1));
+	let hi = program.get_memory(address.wrapping_add(2));
+	let operand = match instruction_data.amode.size() {
+		3 => make_u16(lo, hi),
+		_ => lo as u16,
+	};
+
 	let opcode_str = opcode.to_string();
 	let mut string = format!("${:x}: {}{}{}", address, con_yellow!(), &opcode_str[0..3], con_green!());
 	match instruction_data.amode {
@@ -12,59 +25,39 @@ pub fn print_instruction(program: &mut Program, address: u16, byte: u8, opcode:
 			string += " A";
 		},
 		AddressMode::Immediate => {
-			string += format!(" #${:x}", program.fetched_byte).as_str();
+			string += format!(" #${:x}", operand).as_str();
 		},
 		AddressMode::Absolute | AddressMode::Zeropage => {
-			string += format!(" ${:x}", program.abs_address).as_str();
+			string += format!(" {}", program.symbols.format_address(operand, 1)).as_str();
 		},
 		AddressMode::AbsoluteX | AddressMode::ZeropageX => {
-			string += format!(" ${:x},X", program.abs_address).as_str();
+			string += format!(" {},X", program.symbols.format_address(operand, 1)).as_str();
 		},
 		AddressMode::AbsoluteY | AddressMode::ZeropageY => {
-			string += format!(" ${:x},Y", program.abs_address).as_str();
+			string += format!(" {},Y", program.symbols.format_address(operand, 1)).as_str();
 		},
 		AddressMode::Relative => {
-			string += format!(" ${:x}", program.program_counter.wrapping_add(program.rel_address as u16)).as_str();
+			string += format!(" {}", program.symbols.format_address(address.wrapping_add(2).wrapping_add(lo as i8 as u16), 1)).as_str();
 		},
 		AddressMode::Indirect => {
-			string += format!(" (${:x})", program.ind_address).as_str();
+			string += format!(" ({})", program.symbols.format_address(operand, 1)).as_str();
 		},
 		AddressMode::IndirectX => {
-			string += format!(" (${:x},X)", program.ind_address).as_str();
+			string += format!(" ({},X)", program.symbols.format_address(operand, 1)).as_str();
 		},
 		AddressMode::IndirectY => {
-			string += format!(" (${:x}),Y", program.ind_address).as_str();
+			string += format!(" ({}),Y", program.symbols.format_address(operand, 1)).as_str();
 		},
 		_ => {}
 	}
 
-	let mut string_2 = String::from(con_red!());
+	let string_2 = match instruction_data.amode.size() {
+		1 => format!(" (${:02x})", byte),
+		2 => format!(" (${:02x} ${:02x})", byte, lo),
+		_ => format!(" (${:02x} ${:02x} ${:02x})", byte, lo, hi),
+	};
 
-	match instruction_data.amode {
-		AddressMode::Implied | AddressMode::Accumulator => {
-			string_2 += format!(" (${:02x})", byte).as_str();
-		},
-		AddressMode::Immediate => {
-			string_2 += format!(" (${:02x} ${:02x})", byte, program.fetched_byte).as_str();
-		}
-		AddressMode::Zeropage | AddressMode::ZeropageX | AddressMode::ZeropageY => {
-			string_2 += format!(" (${:02x} ${:02x})", byte, program.abs_address).as_str();
-		},
-		AddressMode::Absolute | AddressMode::AbsoluteX | AddressMode::AbsoluteY => {
-			string_2 += format!(" (${:02x} ${:02x} ${:02x})", byte, program.abs_address & 0xff, program.abs_address >> 8).as_str();
-		},
-		AddressMode::Indirect => {
-			string_2 += format!(" (${:02x} ${:02x} ${:02x})", byte, program.ind_address & 0xff, program.ind_address >> 8).as_str();
-		}
-		AddressMode::IndirectX | AddressMode::IndirectY => {
-			string_2 += format!(" (${:02x} ${:02x})", byte, program.ind_address).as_str();
-		}
-		AddressMode::Relative => {
-			string_2 += format!(" (${:02x} ${:02x})", byte, program.rel_address).as_str();
-		}
-	}
-
-	println!("{:32} {}{}", string, string_2, con_reset!());
+	println!("{:32} {}{}{}", string, con_red!(), string_2, con_reset!());
 }
 
 pub fn print_status(program: &mut Program) {
@@ -79,12 +72,394 @@ pub fn print_status(program: &mut Program) {
 	);
 }
 
-pub fn print_memory(program: &Program, cmd_args: &Vec<String>) {
-		let addr_str = &cmd_args[1];
-		let addr = u16::from_str_radix(addr_str.trim_start_matches('$'), 16).unwrap();
-		let len = if cmd_args.len() == 3 { cmd_args[2].parse::<usize>().unwrap() } else { 10 };
-		for i in 0..len {
-			let byte = program.get_memory(addr + i as u16);
-			println!("${:04x}: ${:<02x}   {:<3}   {}", addr + i as u16, byte, byte, if byte >= 32 && byte <= 126 { byte as char } else { '\0' });
+pub fn describe_watch_hit(program: &Program, hit: &WatchHit) -> String {
+	let (instruction, _) = disassemble(program, hit.pc);
+	let values = match hit.kind {
+		WatchKind::Read => format!("${:02x}", hit.new_value),
+		_ => format!("${:02x} -> ${:02x}", hit.old_value, hit.new_value),
+	};
+	format!("WATCHPOINT ({}) at {}: {} by ${:04x} {}",
+		hit.kind.name(), program.symbols.format_address(hit.address, 4), values, hit.pc, instruction)
+}
+
+pub fn print_watch_hits(program: &Program) {
+	for hit in &program.watch_hits {
+		println!("{}", describe_watch_hit(program, hit));
+	}
+}
+
+pub fn describe_stack_anomaly(program: &Program, anomaly: &StackAnomaly) -> String {
+	let symbols = &program.symbols;
+	match anomaly {
+		StackAnomaly::Discarded{frame, address} => format!("{} at ${:04x} dropped the return to ${:04x} from {} called at ${:04x}",
+			disassemble(program, *address).0, address, frame.return_address, symbols.format_address(frame.subroutine, 4), frame.call_site),
+		StackAnomaly::UnmatchedReturn{address, target} => format!("{} at ${:04x} went to {} without a call to return from",
+			disassemble(program, *address).0, address, symbols.format_address(*target, 4)),
+		StackAnomaly::Redirected{frame, address, target} => format!("{} at ${:04x} went to {} instead of ${:04x}, where {} was called from",
+			disassemble(program, *address).0, address, symbols.format_address(*target, 4), frame.return_address, symbols.format_address(frame.subroutine, 4)),
+	}
+}
+
+pub fn print_stack_anomalies(program: &Program) {
+	for anomaly in &program.stack_anomalies {
+		println!("{}Warning:{} {}", con_yellow!(), con_reset!(), describe_stack_anomaly(program, anomaly));
+	}
+}
+
+// Innermost frame first, each checked against the return address really on the stack
+pub fn print_backtrace(program: &Program) {
+	let symbols = &program.symbols;
+	let frames = &program.call_stack;
+	let name_of = |index: usize| match index {
+		0 => symbols.format_address(program.origin, 4),
+		_ => symbols.format_address(frames[index - 1].subroutine, 4),
+	};
+
+	println!("#0  ${:04x} in {}", program.program_counter, name_of(frames.len()));
+	for (depth, (index, frame)) in frames.iter().enumerate().rev().enumerate() {
+		let entry = match frame.kind {
+			FrameKind::Call => "called",
+			FrameKind::Interrupt => "interrupted",
+		};
+		println!("#{:<2} ${:04x} in {}, {} {}, returns to ${:04x}", depth + 1, frame.call_site, name_of(index), entry,
+			symbols.format_address(frame.subroutine, 4), frame.return_address);
+
+		let hi = program.get_memory(0x100 | frame.stack_pointer as u16);
+		let lo = program.get_memory(0x100 | frame.stack_pointer.wrapping_sub(1) as u16);
+		let on_stack = make_u16(lo, hi);
+		if on_stack != frame.pushed_address() {
+			let returns_to = if frame.kind == FrameKind::Call { on_stack.wrapping_add(1) } else { on_stack };
+			println!("    {}Warning:{} the stack at $01{:02x} has been changed, returning will go to ${:04x}",
+				con_yellow!(), con_reset!(), frame.stack_pointer.wrapping_sub(1), returns_to);
+		}
+	}
+}
+
+pub fn describe_invalid_opcode(byte: u8, address: u16) -> String {
+	format!("Invalid opcode (${:02x}) at ${:04x}", byte, address)
+}
+
+pub fn describe_stack_fault(program: &Program, fault: &StackFault) -> String {
+	let (kind, address, from, to) = match fault {
+		StackFault::Overflow{address} => ("OVERFLOW", address, 0x00, 0xff),
+		StackFault::Underflow{address} => ("UNDERFLOW", address, 0xff, 0x00),
+	};
+	format!("STACK {} at ${:04x}: {} wrapped SP from ${:02x} to ${:02x}", kind, address, disassemble(program, *address).0, from, to)
+}
+
+pub fn print_stack_faults(program: &Program) {
+	for fault in &program.stack_faults {
+		println!("{}", describe_stack_fault(program, fault));
+	}
+}
+
+pub fn format_status(status: u8) -> String {
+	"NV-BDIZC".chars().enumerate().map(|(i, flag)| if flag != '-' && status & (0x80 >> i) == 0 { '.' } else { flag }).collect()
+}
+
+pub struct StackEntry {
+	pub address: u16,
+	pub bytes: Vec<u8>,
+	// "return address", "status" or empty, shown highlighted before the note
+	pub label: &'static str,
+	pub note: String,
+}
+
+// The bytes from SP+1 up to $01ff, top of the stack first, with what pushed them
+pub fn describe_stack(program: &Program) -> Vec<StackEntry> {
+	let symbols = &program.symbols;
+	let mut entries = Vec::new();
+	let mut slot = program.stack_pointer as u16 + 1;
+	while slot <= 0xff {
+		let address = 0x100 | slot;
+		let byte = program.get_memory(address);
+
+		// A return address takes two bytes, the low one on top
+		let frame = program.call_stack.iter().find(|f| f.stack_pointer.wrapping_sub(1) as u16 == slot && f.stack_pointer != 0);
+		if let Some(frame) = frame {
+			let hi = program.get_memory(address + 1);
+			let entry = match frame.kind {
+				FrameKind::Call => format!("JSR {}", symbols.format_address(frame.subroutine, 4)),
+				FrameKind::Interrupt => format!("interrupt to {}", symbols.format_address(frame.subroutine, 4)),
+			};
+			let note = format!("${:04x} from {} at ${:04x}", make_u16(byte, hi), entry, frame.call_site);
+			entries.push(StackEntry{address, bytes: vec![byte, hi], label: "return address", note});
+			slot += 2;
+			continue;
+		}
+
+		let is_interrupt_status = program.call_stack.iter().any(|f| f.kind == FrameKind::Interrupt && f.stack_pointer.wrapping_sub(2) as u16 == slot);
+		let (label, note) = match program.stack_writers[slot as usize] {
+			Some(StackWriter::Interrupt) if is_interrupt_status => ("status", format!("{} pushed by the interrupt", format_status(byte))),
+			Some(StackWriter::Interrupt) => ("", String::from("pushed by an interrupt")),
+			Some(StackWriter::Instruction(pusher)) => {
+				let (text, _) = disassemble(program, pusher);
+				match text.as_str() {
+					"PHP" | "BRK" => ("status", format!("{} pushed by {} at ${:04x}", format_status(byte), text, pusher)),
+					"JSR" => ("", format!("part of a return address pushed by JSR at ${:04x} that's no longer on the call stack", pusher)),
+					_ => ("", format!("pushed by {} at ${:04x}", text, pusher)),
+				}
+			},
+			None => ("", String::new()),
+		};
+		entries.push(StackEntry{address, bytes: vec![byte], label, note});
+		slot += 1;
+	}
+	entries
+}
+
+pub fn print_stack(program: &Program) {
+	if program.stack_pointer == 0xff {
+		println!("The stack is empty (SP=$ff)");
+		return;
+	}
+
+	for entry in describe_stack(program) {
+		let bytes: Vec<String> = entry.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+		println!("${:04x}: {:<8} {}{}{} {}", entry.address, bytes.join(" "), con_green!(), entry.label, con_reset!(), entry.note);
+	}
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum DumpView {
+	Bytes,
+	Words,
+	Binary,
+}
+
+// memory [from] [length] or memory [from]-[to], then -w for words, -b for binary, -n [count] for bytes per line.
+// The dump wraps around from $ffff to $0000.
+pub fn print_memory(program: &Program, cmd_args: &[String]) {
+	let usage = || print_error!("Usage: memory [from] [length] or [from]-[to], with -w, -b or -n [bytes per line]");
+
+	let (from, to) = match cmd_args.get(1).map(|r| r.split_once('-')) {
+		Some(Some((from, to))) => (from, Some(to)),
+		Some(None) => (cmd_args[1].as_str(), None),
+		None => { usage(); return; },
+	};
+
+	let start = match program.symbols.parse_address(from) {
+		Some(start) => start,
+		None => { usage(); return; },
+	};
+
+	let mut len = match to.map(|t| program.symbols.parse_address(t)) {
+		Some(Some(end)) => end.wrapping_sub(start) as usize + 1,
+		Some(None) => { usage(); return; },
+		None => 128,
+	};
+
+	let mut view = DumpView::Bytes;
+	let mut width = None;
+	let mut i = 2;
+	while i < cmd_args.len() {
+		match cmd_args[i].as_str() {
+			"-w" => view = DumpView::Words,
+			"-b" => view = DumpView::Binary,
+			"-n" => {
+				i += 1;
+				match cmd_args.get(i).and_then(|n| n.parse::<usize>().ok()) {
+					Some(n) if n > 0 => width = Some(n),
+					_ => { usage(); return; },
+				}
+			},
+			length if to.is_none() => match length.parse::<usize>() {
+				Ok(n) => len = n,
+				Err(_) => { usage(); return; },
+			},
+			_ => { usage(); return; },
 		}
+		i += 1;
+	}
+
+	len = len.min(0x10000);
+	let width = width.unwrap_or(match view {
+		DumpView::Bytes | DumpView::Words => 16,
+		DumpView::Binary => 4,
+	});
+
+	for line in dump_lines(program, start, len, view, width) {
+		println!("{}", line);
+	}
+}
+
+// The dump [width] bytes to a line, the last line short if [len] doesn't divide evenly
+fn dump_lines(program: &Program, start: u16, len: usize, view: DumpView, width: usize) -> Vec<String> {
+	let mut lines = Vec::new();
+	let mut offset = 0;
+	while offset < len {
+		let address = start.wrapping_add(offset as u16);
+		let count = width.min(len - offset);
+		let bytes: Vec<u8> = (0..count).map(|i| program.get_memory(address.wrapping_add(i as u16))).collect();
+
+		let columns = match view {
+			DumpView::Bytes => bytes.iter().enumerate()
+				.map(|(i, b)| format!("{}{:02x}", if i > 0 && i % 8 == 0 { " " } else { "" }, b))
+				.collect::<Vec<String>>().join(" "),
+			DumpView::Words => bytes.chunks(2)
+				.map(|w| if w.len() == 2 { format!("{:04x}", make_u16(w[0], w[1])) } else { format!("  {:02x}", w[0]) })
+				.collect::<Vec<String>>().join(" "),
+			DumpView::Binary => bytes.iter().map(|b| format!("{:08b}", b)).collect::<Vec<String>>().join(" "),
+		};
+
+		// Short last lines are padded so the gutter stays lined up
+		let full_width = match view {
+			DumpView::Bytes => width * 3 - 1 + (width - 1) / 8,
+			DumpView::Words => width.div_ceil(2) * 5 - 1,
+			DumpView::Binary => width * 9 - 1,
+		};
+		let gutter: String = bytes.iter().map(|b| if (32..=126).contains(b) { *b as char } else { '.' }).collect();
+		lines.push(format!("${:04x}: {:<full_width$}  |{}|", address, columns, gutter, full_width = full_width));
+
+		offset += count;
+	}
+	lines
+}
+
+// Decodes the instruction at `address` without executing it, returning the text and its length
+pub fn disassemble(program: &Program, address: u16) -> (String, u16) {
+	let byte = program.get_memory(address);
+	let opcode: Opcode = match FromPrimitive::from_u8(byte) {
+		Some(opcode) => opcode,
+		None => { return (format!(".byte ${:02x}", byte), 1); },
+	};
+
+	let amode = INSTRUCTION_DATA[&opcode].amode;
+	let lo = program.get_memory(address.wrapping_add(1));
+	let hi = program.get_memory(address.wrapping_add(2));
+	let word = make_u16(lo, hi);
+	let symbols = &program.symbols;
+	let operand = match amode {
+		AddressMode::Implied => String::new(),
+		AddressMode::Accumulator => String::from(" A"),
+		AddressMode::Immediate => format!(" #${:02x}", lo),
+		AddressMode::Zeropage => format!(" {}", symbols.format_address(lo as u16, 2)),
+		AddressMode::ZeropageX => format!(" {},X", symbols.format_address(lo as u16, 2)),
+		AddressMode::ZeropageY => format!(" {},Y", symbols.format_address(lo as u16, 2)),
+		AddressMode::Absolute => format!(" {}", symbols.format_address(word, 4)),
+		AddressMode::AbsoluteX => format!(" {},X", symbols.format_address(word, 4)),
+		AddressMode::AbsoluteY => format!(" {},Y", symbols.format_address(word, 4)),
+		AddressMode::Relative => format!(" {}", symbols.format_address(address.wrapping_add(2).wrapping_add(lo as i8 as u16), 4)),
+		AddressMode::Indirect => format!(" ({})", symbols.format_address(word, 4)),
+		AddressMode::IndirectX => format!(" ({},X)", symbols.format_address(lo as u16, 2)),
+		AddressMode::IndirectY => format!(" ({}),Y", symbols.format_address(lo as u16, 2)),
+	};
+
+	(format!("{}{}", opcode.mnemonic(), operand), amode.size())
+}
+
+pub fn print_disassembly(program: &Program, address: u16, count: usize) {
+	let mut addr = address;
+	for _ in 0..count {
+		if let Some(name) = program.symbols.name_for(addr) {
+			println!("{}{}:{}", con_green!(), name, con_reset!());
+		}
+
+		let (text, len) = disassemble(program, addr);
+		let bytes: Vec<String> = (0..len).map(|i| format!("{:02x}", program.get_memory(addr.wrapping_add(i)))).collect();
+		println!("${:04x}: {:<9} {}{}{}", addr, bytes.join(" "), con_yellow!(), text, con_reset!());
+		addr = addr.wrapping_add(len);
+	}
+}
+
+// Each line shows an instruction with the registers and memory as they were before it ran
+pub fn print_history(program: &Program, cmd_args: &[String]) {
+	let count = match cmd_args.get(1).map(|c| c.parse::<usize>()) {
+		Some(Ok(count)) => count,
+		None => 10,
+		Some(Err(_)) => {
+			print_error!("Invalid instruction count");
+			return;
+		},
+	};
+
+	if program.history.is_empty() {
+		println!("No instructions recorded");
+		return;
+	}
+
+	let skip = program.history.len().saturating_sub(count);
+	for (i, step) in program.history.iter().enumerate().skip(skip) {
+		let registers = &step.registers;
+		let (text, _) = disassemble(program, registers.pc);
+		let mut line = format!("{:>6} ${:04x}: {}{:<14}{} A=${:02x} X=${:02x} Y=${:02x} SP=${:02x} P=${:02x}",
+			i as isize - program.history.len() as isize, registers.pc, con_yellow!(), text, con_reset!(),
+			registers.a, registers.x, registers.y, registers.sp, registers.status);
+		for (address, old_value, _) in &step.writes {
+			line += format!("  {} was ${:02x}", program.symbols.format_address(*address, 4), old_value).as_str();
+		}
+		println!("{}", line);
+	}
+}
+
+// The hottest instructions and subroutines by cycles, [count] of each
+pub fn print_profile(program: &Program, cmd_args: &[String]) {
+	let count = match cmd_args.get(1).map(|c| c.parse::<usize>()) {
+		Some(Ok(count)) => count,
+		None => 20,
+		Some(Err(_)) => {
+			print_error!("Invalid count");
+			return;
+		},
+	};
+
+	let profile = match &program.profile {
+		Some(profile) => profile,
+		None => {
+			println!("No profile recorded, start a run with --profile");
+			return;
+		},
+	};
+
+	let percent = |cycles: u64| if profile.cycles == 0 { 0.0 } else { cycles as f64 * 100.0 / profile.cycles as f64 };
+	println!("\n{} instructions, {} cycles", profile.instructions, profile.cycles);
+
+	let mut addresses: Vec<_> = profile.addresses.iter().collect();
+	addresses.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+	println!("\n{:>10} {:>6} {:>10}  Instruction", "Cycles", "%", "Count");
+	for (address, counts) in addresses.iter().take(count) {
+		let (text, _) = disassemble(program, **address);
+		let label = program.symbols.name_for(**address).map(|n| format!("  {}{}{}", con_green!(), n, con_reset!())).unwrap_or_default();
+		println!("{:>10} {:>5.1}% {:>10}  ${:04x}: {}{:<14}{}{}", counts.cycles, percent(counts.cycles), counts.executions,
+			address, con_yellow!(), text, con_reset!(), label);
+	}
+
+	let mut subroutines: Vec<_> = profile.subroutines(program.cycles).into_iter().collect();
+	if subroutines.is_empty() {
+		return;
+	}
+	subroutines.sort_by(|a, b| b.1.total_cycles.cmp(&a.1.total_cycles).then(a.0.cmp(&b.0)));
+	println!("\n{:>10} {:>6} {:>10} {:>6} {:>8}  Subroutine", "Total", "%", "Self", "%", "Calls");
+	for (address, counts) in subroutines.iter().take(count) {
+		println!("{:>10} {:>5.1}% {:>10} {:>5.1}% {:>8}  {}{}{}", counts.total_cycles, percent(counts.total_cycles),
+			counts.self_cycles, percent(counts.self_cycles), counts.calls, con_green!(), program.symbols.format_address(*address, 4), con_reset!());
+	}
+	println!("{:>10} {:>6} {:>10} {:>5.1}% {:>8}  (outside any subroutine)", "", "", profile.top_level_cycles, percent(profile.top_level_cycles), "");
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn dump_wraps_past_ffff() {
+		let mut program = Program::new();
+		program.poke(0xfffe, &[0x41, 0x42, 0x00, 0x7f]);
+
+		assert_eq!(dump_lines(&program, 0xfffe, 4, DumpView::Bytes, 2), ["$fffe: 41 42  |AB|", "$0000: 00 7f  |..|"]);
+		assert_eq!(dump_lines(&program, 0xffff, 3, DumpView::Bytes, 16), [format!("$ffff: {:<48}  |B..|", "42 00 7f")]);
+		assert_eq!(dump_lines(&program, 0xfffe, 3, DumpView::Words, 4), ["$fffe: 4241   00  |AB.|"]);
+		assert_eq!(dump_lines(&program, 0xffff, 2, DumpView::Binary, 1), ["$ffff: 01000010  |B|", "$0000: 00000000  |.|"]);
+		assert_eq!(dump_lines(&program, 0x1000, 0x10000, DumpView::Bytes, 16).len(), 0x1000);
+		assert!(dump_lines(&program, 0x1000, 0, DumpView::Bytes, 16).is_empty());
+	}
+
+	#[test]
+	fn stack_fault_messages() {
+		let mut program = Program::new();
+		program.poke(0x0600, &[0x48]);
+		assert_eq!(describe_stack_fault(&program, &StackFault::Overflow{address: 0x0600}), "STACK OVERFLOW at $0600: PHA wrapped SP from $00 to $ff");
+		assert_eq!(describe_stack_fault(&program, &StackFault::Underflow{address: 0x0601}), "STACK UNDERFLOW at $0601: BRK wrapped SP from $ff to $00");
+	}
+
 }
@@ -0,0 +1,444 @@
+// expression.rs
+
+// Numbers are decimal unless prefixed: $ or 0x for hex, % for binary, 'c' for a character.
+// parse_address reads bare numbers as hex instead, for commands that take addresses.
+// Comparisons and logical operators give 1 for true and 0 for false.
+
+pub trait Resolver {
+	fn resolve(&self, name: &str) -> Option<i64>;
+
+	fn memory(&self, _address: u16) -> Option<u8> {
+		None
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOp {
+	Negate,
+	Complement,
+	Not,
+	LowByte,
+	HighByte,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+	Add,
+	Subtract,
+	Multiply,
+	Divide,
+	Modulo,
+	And,
+	Or,
+	Xor,
+	ShiftLeft,
+	ShiftRight,
+	Equal,
+	NotEqual,
+	Less,
+	LessEqual,
+	Greater,
+	GreaterEqual,
+	LogicalAnd,
+	LogicalOr,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+	Number(i64),
+	Symbol(String),
+	CurrentAddress,
+	Memory(Box<Expr>),
+	Unary(UnaryOp, Box<Expr>),
+	Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+	Number(i64),
+	Ident(String),
+	Op(&'static str),
+	LParen,
+	RParen,
+	LBracket,
+	RBracket,
+}
+
+// Longest first, so "<<" isn't read as two "<"
+const OPERATORS: [&str; 21] = [
+	"<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+	"+", "-", "*", "/", "%", "&", "|", "^", "~", "!", "<", ">", "=",
+];
+
+// =============================================================
+
+fn tokenize(text: &str, radix: u32) -> Result<Vec<Token>, String> {
+	let chars: Vec<char> = text.chars().collect();
+	let mut tokens = Vec::new();
+	let mut i = 0;
+
+	while i < chars.len() {
+		let c = chars[i];
+		if c.is_whitespace() {
+			i += 1;
+			continue;
+		}
+
+		let expects_operand = matches!(tokens.last(), None | Some(Token::Op(_)) | Some(Token::LParen) | Some(Token::LBracket));
+
+		if c == '$' || (c == '0' && i + 1 < chars.len() && (chars[i + 1] == 'x' || chars[i + 1] == 'X')) {
+			i += if c == '$' { 1 } else { 2 };
+			let start = i;
+			while i < chars.len() && chars[i].is_ascii_hexdigit() {
+				i += 1;
+			}
+			let digits: String = chars[start..i].iter().collect();
+			let value = i64::from_str_radix(&digits, 16).map_err(|_| format!("Invalid hex number \"{}\"", digits))?;
+			tokens.push(Token::Number(value));
+		}
+		else if c == '%' && expects_operand {
+			i += 1;
+			let start = i;
+			while i < chars.len() && (chars[i] == '0' || chars[i] == '1') {
+				i += 1;
+			}
+			let digits: String = chars[start..i].iter().collect();
+			let value = i64::from_str_radix(&digits, 2).map_err(|_| format!("Invalid binary number \"{}\"", digits))?;
+			tokens.push(Token::Number(value));
+		}
+		else if c.is_ascii_digit() {
+			let start = i;
+			while i < chars.len() && chars[i].is_digit(radix) {
+				i += 1;
+			}
+			let digits: String = chars[start..i].iter().collect();
+			tokens.push(Token::Number(i64::from_str_radix(&digits, radix).map_err(|_| format!("Invalid number \"{}\"", digits))?));
+		}
+		else if c == '\'' {
+			if i + 2 < chars.len() && chars[i + 2] == '\'' {
+				tokens.push(Token::Number(chars[i + 1] as i64));
+				i += 3;
+			}
+			else {
+				return Err(String::from("Unterminated character literal"));
+			}
+		}
+		else if c.is_alphabetic() || c == '_' || c == '.' || c == '@' {
+			let start = i;
+			while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.' || chars[i] == '@') {
+				i += 1;
+			}
+			tokens.push(Token::Ident(chars[start..i].iter().collect()));
+		}
+		else if c == '(' {
+			tokens.push(Token::LParen);
+			i += 1;
+		}
+		else if c == ')' {
+			tokens.push(Token::RParen);
+			i += 1;
+		}
+		else if c == '[' {
+			tokens.push(Token::LBracket);
+			i += 1;
+		}
+		else if c == ']' {
+			tokens.push(Token::RBracket);
+			i += 1;
+		}
+		else {
+			let rest: String = chars[i..].iter().take(2).collect();
+			match OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+				Some(op) => {
+					tokens.push(Token::Op(op));
+					i += op.len();
+				},
+				None => {
+					return Err(format!("Unexpected character '{}'", c));
+				},
+			}
+		}
+	}
+
+	Ok(tokens)
+}
+
+fn binary_op(token: &Token) -> Option<(BinaryOp, u8)> {
+	match token {
+		Token::Op("||") => Some((BinaryOp::LogicalOr, 1)),
+		Token::Op("&&") => Some((BinaryOp::LogicalAnd, 2)),
+		Token::Op("|") => Some((BinaryOp::Or, 3)),
+		Token::Op("^") => Some((BinaryOp::Xor, 4)),
+		Token::Op("&") => Some((BinaryOp::And, 5)),
+		Token::Op("==") | Token::Op("=") => Some((BinaryOp::Equal, 6)),
+		Token::Op("!=") => Some((BinaryOp::NotEqual, 6)),
+		Token::Op("<") => Some((BinaryOp::Less, 7)),
+		Token::Op("<=") => Some((BinaryOp::LessEqual, 7)),
+		Token::Op(">") => Some((BinaryOp::Greater, 7)),
+		Token::Op(">=") => Some((BinaryOp::GreaterEqual, 7)),
+		Token::Op("<<") => Some((BinaryOp::ShiftLeft, 8)),
+		Token::Op(">>") => Some((BinaryOp::ShiftRight, 8)),
+		Token::Op("+") => Some((BinaryOp::Add, 9)),
+		Token::Op("-") => Some((BinaryOp::Subtract, 9)),
+		Token::Op("*") => Some((BinaryOp::Multiply, 10)),
+		Token::Op("/") => Some((BinaryOp::Divide, 10)),
+		Token::Op("%") => Some((BinaryOp::Modulo, 10)),
+		_ => None,
+	}
+}
+
+struct Parser {
+	tokens: Vec<Token>,
+	pos: usize,
+}
+
+impl Parser {
+	fn peek(&self) -> Option<&Token> {
+		self.tokens.get(self.pos)
+	}
+
+	fn next(&mut self) -> Option<Token> {
+		let token = self.tokens.get(self.pos).cloned();
+		self.pos += 1;
+		token
+	}
+
+	fn parse_binary(&mut self, min_precedence: u8) -> Result<Expr, String> {
+		let mut lhs = self.parse_unary()?;
+		while let Some((op, precedence)) = self.peek().and_then(binary_op) {
+			if precedence < min_precedence {
+				break;
+			}
+
+			self.pos += 1;
+			let rhs = self.parse_binary(precedence + 1)?;
+			lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+		}
+
+		Ok(lhs)
+	}
+
+	fn parse_unary(&mut self) -> Result<Expr, String> {
+		let op = match self.peek() {
+			Some(Token::Op("-")) => Some(UnaryOp::Negate),
+			Some(Token::Op("~")) => Some(UnaryOp::Complement),
+			Some(Token::Op("!")) => Some(UnaryOp::Not),
+			Some(Token::Op("<")) => Some(UnaryOp::LowByte),
+			Some(Token::Op(">")) => Some(UnaryOp::HighByte),
+			_ => None,
+		};
+
+		match op {
+			Some(op) => {
+				self.pos += 1;
+				Ok(Expr::Unary(op, Box::new(self.parse_unary()?)))
+			},
+			None => self.parse_primary(),
+		}
+	}
+
+	fn parse_primary(&mut self) -> Result<Expr, String> {
+		match self.next() {
+			Some(Token::Number(value)) => Ok(Expr::Number(value)),
+			Some(Token::Ident(name)) => {
+				if name == "mem" && self.peek() == Some(&Token::LBracket) {
+					self.pos += 1;
+					let address = self.parse_binary(0)?;
+					return match self.next() {
+						Some(Token::RBracket) => Ok(Expr::Memory(Box::new(address))),
+						_ => Err(String::from("Missing closing bracket")),
+					};
+				}
+
+				Ok(Expr::Symbol(name))
+			},
+			Some(Token::Op("*")) => Ok(Expr::CurrentAddress),
+			Some(Token::LParen) => {
+				let inner = self.parse_binary(0)?;
+				match self.next() {
+					Some(Token::RParen) => Ok(inner),
+					_ => Err(String::from("Missing closing parenthesis")),
+				}
+			},
+			Some(token) => Err(format!("Unexpected {:?} in expression", token)),
+			None => Err(String::from("Unexpected end of expression")),
+		}
+	}
+}
+
+pub fn parse(text: &str) -> Result<Expr, String> {
+	parse_with_radix(text, 10)
+}
+
+// Bare numbers are hex, so 10+1 is $11. A name made of hex digits, like ff, is left as a symbol for the
+// resolver to fall back on.
+pub fn parse_address(text: &str) -> Result<Expr, String> {
+	parse_with_radix(text, 16)
+}
+
+fn parse_with_radix(text: &str, radix: u32) -> Result<Expr, String> {
+	let mut parser = Parser{tokens: tokenize(text, radix)?, pos: 0};
+	let expr = parser.parse_binary(0)?;
+	if parser.pos < parser.tokens.len() {
+		return Err(format!("Unexpected {:?} in expression", parser.tokens[parser.pos]));
+	}
+
+	Ok(expr)
+}
+
+// =============================================================
+
+impl Expr {
+	pub fn evaluate(&self, resolver: &dyn Resolver) -> Result<i64, String> {
+		match self {
+			Expr::Number(value) => Ok(*value),
+			Expr::Symbol(name) => resolver.resolve(name).ok_or(format!("Undefined symbol \"{}\"", name)),
+			Expr::CurrentAddress => resolver.resolve("*").ok_or(String::from("Current address is not available here")),
+			Expr::Memory(address) => {
+				let address = address.evaluate(resolver)?;
+				if !(0..=0xffff).contains(&address) {
+					return Err(format!("Address ${:x} is outside of memory", address));
+				}
+				resolver.memory(address as u16).map(|b| b as i64).ok_or(String::from("Memory is not available here"))
+			},
+			Expr::Unary(op, operand) => {
+				let value = operand.evaluate(resolver)?;
+				Ok(match op {
+					UnaryOp::Negate => value.wrapping_neg(),
+					UnaryOp::Complement => !value,
+					UnaryOp::Not => (value == 0) as i64,
+					UnaryOp::LowByte => value & 0xff,
+					UnaryOp::HighByte => (value >> 8) & 0xff,
+				})
+			},
+			Expr::Binary(BinaryOp::LogicalAnd, lhs, rhs) => {
+				Ok((lhs.evaluate(resolver)? != 0 && rhs.evaluate(resolver)? != 0) as i64)
+			},
+			Expr::Binary(BinaryOp::LogicalOr, lhs, rhs) => {
+				Ok((lhs.evaluate(resolver)? != 0 || rhs.evaluate(resolver)? != 0) as i64)
+			},
+			Expr::Binary(op, lhs, rhs) => {
+				let a = lhs.evaluate(resolver)?;
+				let b = rhs.evaluate(resolver)?;
+				match op {
+					BinaryOp::Add => Ok(a.wrapping_add(b)),
+					BinaryOp::Subtract => Ok(a.wrapping_sub(b)),
+					BinaryOp::Multiply => Ok(a.wrapping_mul(b)),
+					BinaryOp::Divide if b == 0 => Err(String::from("Division by zero")),
+					BinaryOp::Modulo if b == 0 => Err(String::from("Division by zero")),
+					BinaryOp::Divide => Ok(a.wrapping_div(b)),
+					BinaryOp::Modulo => Ok(a.wrapping_rem(b)),
+					BinaryOp::And => Ok(a & b),
+					BinaryOp::Or => Ok(a | b),
+					BinaryOp::Xor => Ok(a ^ b),
+					BinaryOp::ShiftLeft => Ok(a.wrapping_shl(b as u32)),
+					BinaryOp::ShiftRight => Ok(a.wrapping_shr(b as u32)),
+					BinaryOp::Equal => Ok((a == b) as i64),
+					BinaryOp::NotEqual => Ok((a != b) as i64),
+					BinaryOp::Less => Ok((a < b) as i64),
+					BinaryOp::LessEqual => Ok((a <= b) as i64),
+					BinaryOp::Greater => Ok((a > b) as i64),
+					BinaryOp::GreaterEqual => Ok((a >= b) as i64),
+					BinaryOp::LogicalAnd | BinaryOp::LogicalOr => unreachable!(),
+				}
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::collections::HashMap;
+
+	struct TestResolver {
+		symbols: HashMap<&'static str, i64>,
+	}
+
+	impl Resolver for TestResolver {
+		fn resolve(&self, name: &str) -> Option<i64> {
+			self.symbols.get(name).copied()
+		}
+
+		fn memory(&self, address: u16) -> Option<u8> {
+			Some((address & 0xff) as u8 ^ 0xff)
+		}
+	}
+
+	fn eval(text: &str) -> Result<i64, String> {
+		let resolver = TestResolver{symbols: [("start", 0x1000), ("*", 0x0800), ("count", 3)].iter().copied().collect()};
+		parse(text)?.evaluate(&resolver)
+	}
+
+	#[test]
+	fn number_formats() {
+		assert_eq!(eval("42"), Ok(42));
+		assert_eq!(eval("$ff"), Ok(0xff));
+		assert_eq!(eval("0x1F"), Ok(0x1f));
+		assert_eq!(eval("%1010"), Ok(10));
+		assert_eq!(eval("'A'"), Ok(65));
+	}
+
+	#[test]
+	fn address_numbers() {
+		let resolver = TestResolver{symbols: [("start", 0x1000)].iter().copied().collect()};
+		let eval = |text: &str| parse_address(text)?.evaluate(&resolver);
+		assert_eq!(eval("10"), Ok(0x10));
+		assert_eq!(eval("10+1"), Ok(0x11));
+		assert_eq!(eval("1f0 - $10"), Ok(0x1e0));
+		assert_eq!(eval("start+%100"), Ok(0x1004));
+		assert_eq!(parse_address("ff"), Ok(Expr::Symbol(String::from("ff"))));
+	}
+
+	#[test]
+	fn precedence() {
+		assert_eq!(eval("2 + 3 * 4"), Ok(14));
+		assert_eq!(eval("(2 + 3) * 4"), Ok(20));
+		assert_eq!(eval("10 - 4 - 3"), Ok(3));
+		assert_eq!(eval("1 << 2 + 1"), Ok(8));
+		assert_eq!(eval("$f0 | $0f & $03"), Ok(0xf3));
+		assert_eq!(eval("1 + 1 == 2 && 3 > 2"), Ok(1));
+		assert_eq!(eval("0 || 2 < 1"), Ok(0));
+		assert_eq!(eval("7 % 4 * 2"), Ok(6));
+	}
+
+	#[test]
+	fn unary_operators() {
+		assert_eq!(eval("-5 + 2"), Ok(-3));
+		assert_eq!(eval("<$1234"), Ok(0x34));
+		assert_eq!(eval(">$1234"), Ok(0x12));
+		// < and > bind tighter than anything binary, as in most assemblers
+		assert_eq!(eval(">start+$180"), Ok(0x190));
+		assert_eq!(eval(">(start+$180)"), Ok(0x11));
+		assert_eq!(eval("!0"), Ok(1));
+		assert_eq!(eval("~0"), Ok(-1));
+	}
+
+	#[test]
+	fn overflow_wraps() {
+		assert_eq!(eval("-(-9223372036854775807 - 1)"), Ok(i64::MIN));
+		assert_eq!(eval("(-9223372036854775807 - 1) / -1"), Ok(i64::MIN));
+		assert_eq!(eval("(-9223372036854775807 - 1) % -1"), Ok(0));
+		assert_eq!(eval("9223372036854775807 + 1"), Ok(i64::MIN));
+	}
+
+	#[test]
+	fn symbols_and_memory() {
+		assert_eq!(eval("start + count"), Ok(0x1003));
+		assert_eq!(eval("* + 2"), Ok(0x0802));
+		assert_eq!(eval("mem[start + 1]"), Ok(0xfe));
+		assert_eq!(eval("nowhere"), Err(String::from("Undefined symbol \"nowhere\"")));
+		assert!(eval("mem[$10000]").is_err());
+	}
+
+	#[test]
+	fn errors() {
+		assert_eq!(eval("1 / 0"), Err(String::from("Division by zero")));
+		assert_eq!(eval("(1 + 2"), Err(String::from("Missing closing parenthesis")));
+		assert_eq!(eval("1 +"), Err(String::from("Unexpected end of expression")));
+		assert!(eval("1 2").is_err());
+		assert!(eval("'a").is_err());
+		assert!(eval("#1").is_err());
+	}
+}
@@ -0,0 +1,527 @@
+// gdb.rs
+
+// A GDB Remote Serial Protocol stub. The register layout follows MAME's 6502 stub:
+// a, x, y, p and sp are one byte each, then pc is two bytes, little-endian.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::program::Program;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+	<feature name="org.gnu.gdb.m6502.core">
+		<reg name="a" bitsize="8" type="int8"/>
+		<reg name="x" bitsize="8" type="int8"/>
+		<reg name="y" bitsize="8" type="int8"/>
+		<reg name="p" bitsize="8" type="int8"/>
+		<reg name="sp" bitsize="8" type="data_ptr"/>
+		<reg name="pc" bitsize="16" type="code_ptr"/>
+	</feature>
+</target>
+"#;
+
+// How many instructions run between checks for an interrupt from the client
+const INTERRUPT_CHECK_INTERVAL: u32 = 1024;
+
+// Advertised in qSupported. Memory reads are cut short so the hex reply, with its $ and #xx, fits in one.
+const PACKET_SIZE: usize = 0x4000;
+const MAX_MEMORY_READ: usize = (PACKET_SIZE - 4) / 2;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+struct Connection {
+	reader: BufReader<TcpStream>,
+	stream: TcpStream,
+	no_ack: bool,
+}
+
+impl Connection {
+	// Returns None when the client hangs up. The packet comes back with escapes and repeats decoded.
+	fn read_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+		let mut byte = [0u8];
+		loop {
+			if self.reader.read(&mut byte)? == 0 {
+				return Ok(None);
+			}
+
+			match byte[0] {
+				b'$' => {},
+				0x03 => { return Ok(Some(b"?".to_vec())); },
+				_ => { continue; }, // Acks, and anything else between packets
+			}
+
+			let mut data = Vec::new();
+			loop {
+				if self.reader.read(&mut byte)? == 0 {
+					return Ok(None);
+				}
+				if byte[0] == b'#' {
+					break;
+				}
+				data.push(byte[0]);
+
+				// The byte after an escape is data, whatever it is
+				if byte[0] == b'}' {
+					self.reader.read_exact(&mut byte)?;
+					data.push(byte[0]);
+				}
+			}
+
+			let mut checksum = [0u8; 2];
+			self.reader.read_exact(&mut checksum)?;
+			let expected = u8::from_str_radix(&String::from_utf8_lossy(&checksum), 16).ok();
+			let actual = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+
+			if !self.no_ack {
+				let valid = expected == Some(actual);
+				self.stream.write_all(if valid { b"+" } else { b"-" })?;
+				if !valid {
+					continue;
+				}
+			}
+
+			return Ok(Some(decode_packet(&data)));
+		}
+	}
+
+	fn send(&mut self, data: &str) -> io::Result<()> {
+		let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+		self.stream.write_all(format!("${}#{:02x}", data, checksum).as_bytes())?;
+		self.stream.flush()?;
+
+		// Wait for the ack, resending on a nak
+		while !self.no_ack {
+			let mut byte = [0u8];
+			if self.reader.read(&mut byte)? == 0 {
+				break;
+			}
+			match byte[0] {
+				b'+' => { break; },
+				b'-' => { self.stream.write_all(format!("${}#{:02x}", data, checksum).as_bytes())?; },
+				_ => {},
+			}
+		}
+		Ok(())
+	}
+
+	// Checks for a Ctrl-C from the client without waiting for one
+	fn interrupted(&mut self) -> bool {
+		// Anything else buffered is the start of the client's next packet, and has to stay put
+		if let Some(&first) = self.reader.buffer().first() {
+			if first == 0x03 {
+				self.reader.consume(1);
+			}
+			return first == 0x03;
+		}
+
+		let mut byte = [0u8];
+
+		if self.stream.set_nonblocking(true).is_err() {
+			return false;
+		}
+		let result = self.stream.peek(&mut byte);
+		self.stream.set_nonblocking(false).ok();
+
+		match result {
+			Ok(1) if byte[0] == 0x03 => {
+				self.reader.read_exact(&mut byte).ok();
+				true
+			},
+			_ => false,
+		}
+	}
+}
+
+// =============================================================
+
+// Binary data escapes #, $, } and * as } followed by the byte XORed with $20, and any packet can
+// abbreviate a run as the byte, *, then the repeat count plus 29
+fn decode_packet(data: &[u8]) -> Vec<u8> {
+	let mut decoded = Vec::new();
+	let mut bytes = data.iter().copied();
+	while let Some(byte) = bytes.next() {
+		match byte {
+			b'}' => decoded.extend(bytes.next().map(|b| b ^ 0x20)),
+			b'*' => {
+				let count = bytes.next().map(|n| n.saturating_sub(29) as usize).unwrap_or(0);
+				if let Some(&previous) = decoded.last() {
+					decoded.extend(std::iter::repeat_n(previous, count));
+				}
+			},
+			_ => decoded.push(byte),
+		}
+	}
+	decoded
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+	bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn parse_hex_bytes(text: &str) -> Option<Vec<u8>> {
+	if !text.len().is_multiple_of(2) {
+		return None;
+	}
+	(0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+	u32::from_str_radix(text, 16).ok()
+}
+
+// "addr,length" as sent with m, M, Z and z
+fn parse_range(text: &str) -> Option<(u16, usize)> {
+	let (address, length) = text.split_once(',')?;
+	let address = parse_hex(address)?;
+	if address > 0xffff {
+		return None;
+	}
+	Some((address as u16, parse_hex(length)? as usize))
+}
+
+fn read_registers(program: &Program) -> Vec<u8> {
+	let pc = program.program_counter;
+	vec![program.reg_a, program.reg_x, program.reg_y, program.get_status(), program.stack_pointer, pc as u8, (pc >> 8) as u8]
+}
+
+// The register numbers match the order of TARGET_XML
+fn write_register(program: &mut Program, number: u32, value: &[u8]) -> bool {
+	match (number, value) {
+		(0, [a]) => program.reg_a = *a,
+		(1, [x]) => program.reg_x = *x,
+		(2, [y]) => program.reg_y = *y,
+		(3, [p]) => program.set_status(*p),
+		(4, [sp]) => program.stack_pointer = *sp,
+		(5, [lo, hi]) => program.program_counter = ((*hi as u16) << 8) | *lo as u16,
+		_ => { return false; },
+	}
+	true
+}
+
+fn read_features(annex: &str) -> String {
+	let (name, range) = match annex.split_once(':') {
+		Some(parts) => parts,
+		None => { return String::from("E00"); },
+	};
+	let (offset, length) = match (name, range.split_once(',')) {
+		("target.xml", Some((offset, length))) => (parse_hex(offset).unwrap_or(0) as usize, parse_hex(length).unwrap_or(0) as usize),
+		_ => { return String::from("E00"); },
+	};
+
+	let start = offset.min(TARGET_XML.len());
+	let end = (offset + length).min(TARGET_XML.len());
+	format!("{}{}", if end == TARGET_XML.len() { "l" } else { "m" }, &TARGET_XML[start..end])
+}
+
+fn stop_reply(signal: u8) -> String {
+	format!("S{:02x}", signal)
+}
+
+// Runs until a breakpoint, watchpoint, BRK or an interrupt from the client, returning the stop reply
+fn continue_program(program: &mut Program, connection: &mut Connection) -> String {
+	let mut count = 0u32;
+	loop {
+		// The breakpoint the program is sitting on was reported when it stopped there
+		if count > 0 {
+			match program.check_breakpoints(program.program_counter) {
+				Ok(None) => {},
+				Ok(Some(_)) => { return stop_reply(SIGTRAP); },
+				Err(error) => {
+					print_error!("{}", error.message);
+					return stop_reply(SIGTRAP);
+				},
+			}
+		}
+
+		if let Some(reply) = step_program(program) {
+			return reply;
+		}
+
+		if !program.watch_hits.is_empty() {
+			return stop_reply(SIGTRAP);
+		}
+
+		count += 1;
+		if count.is_multiple_of(INTERRUPT_CHECK_INTERVAL) && connection.interrupted() {
+			return stop_reply(SIGINT);
+		}
+	}
+}
+
+// Executes one instruction, returning a reply if the program can't carry on
+fn step_program(program: &mut Program) -> Option<String> {
+	if program.step(false).is_err() {
+		return Some(stop_reply(SIGILL));
+	}
+
+	// BRK ends the program in the REPL, but an exit would tell gdb the inferior is gone, so it's a trap
+	if program.flag_break {
+		program.flag_break = false;
+		return Some(stop_reply(SIGTRAP));
+	}
+	None
+}
+
+// X addr,length:data, where the data is raw bytes rather than hex
+fn write_binary(program: &mut Program, args: &[u8]) -> String {
+	let Some(colon) = args.iter().position(|b| *b == b':') else { return String::from("E01"); };
+	let data = &args[colon + 1..];
+	match parse_range(&String::from_utf8_lossy(&args[..colon])) {
+		Some((address, length)) if data.len() == length => {
+			program.poke(address, data);
+			String::from("OK")
+		},
+		_ => String::from("E01"),
+	}
+}
+
+// Returns the reply, or None to close the connection
+fn handle_packet(program: &mut Program, connection: &mut Connection, packet: &[u8]) -> Option<String> {
+	if let Some(args) = packet.strip_prefix(b"X") {
+		return Some(write_binary(program, args));
+	}
+
+	let packet = String::from_utf8_lossy(packet);
+	let (command, args) = packet.split_at(packet.chars().next().map(|c| c.len_utf8()).unwrap_or(0));
+	let reply = match command {
+		"?" => stop_reply(SIGTRAP),
+
+		"g" => hex_bytes(&read_registers(program)),
+
+		"G" => match parse_hex_bytes(args) {
+			Some(bytes) if bytes.len() == 7 => {
+				for (number, value) in [(0, &bytes[0..1]), (1, &bytes[1..2]), (2, &bytes[2..3]), (3, &bytes[3..4]), (4, &bytes[4..5]), (5, &bytes[5..7])] {
+					write_register(program, number, value);
+				}
+				String::from("OK")
+			},
+			_ => String::from("E01"),
+		},
+
+		"p" => match parse_hex(args) {
+			Some(number @ 0..=4) => hex_bytes(&read_registers(program)[number as usize..number as usize + 1]),
+			Some(5) => hex_bytes(&read_registers(program)[5..7]),
+			_ => String::from("E01"),
+		},
+
+		"P" => {
+			let parsed = args.split_once('=').and_then(|(number, value)| Some((parse_hex(number)?, parse_hex_bytes(value)?)));
+			match parsed {
+				Some((number, value)) if write_register(program, number, &value) => String::from("OK"),
+				_ => String::from("E01"),
+			}
+		},
+
+		"m" => match parse_range(args) {
+			Some((address, length)) => {
+				// gdb reads the rest with another m if the reply comes back short
+				let bytes: Vec<u8> = (0..length.min(MAX_MEMORY_READ)).map(|i| program.get_memory(address.wrapping_add(i as u16))).collect();
+				hex_bytes(&bytes)
+			},
+			None => String::from("E01"),
+		},
+
+		"M" => {
+			let parsed = args.split_once(':').and_then(|(range, data)| Some((parse_range(range)?, parse_hex_bytes(data)?)));
+			match parsed {
+				Some(((address, length), bytes)) if bytes.len() == length => {
+					program.poke(address, &bytes);
+					String::from("OK")
+				},
+				_ => String::from("E01"),
+			}
+		},
+
+		// Software and hardware breakpoints both go in the breakpoint list, watchpoints aren't supported
+		"Z" | "z" => {
+			let kind = args.get(0..1).unwrap_or("");
+			let range = args.get(2..).and_then(parse_range);
+			match (kind, range) {
+				("0", Some((address, _))) | ("1", Some((address, _))) => {
+					if command == "Z" {
+						program.add_breakpoint(address, None, false);
+					}
+					else {
+						let ids: Vec<u32> = program.breakpoints.iter().filter(|b| b.address == address && b.condition.is_none()).map(|b| b.id).collect();
+						for id in ids {
+							program.remove_breakpoint(id);
+						}
+					}
+					String::from("OK")
+				},
+				_ => String::new(),
+			}
+		},
+
+		"s" => {
+			if let Some(address) = parse_hex(args) {
+				program.program_counter = address as u16;
+			}
+			step_program(program).unwrap_or_else(|| stop_reply(SIGTRAP))
+		},
+
+		"c" => {
+			if let Some(address) = parse_hex(args) {
+				program.program_counter = address as u16;
+			}
+			continue_program(program, connection)
+		},
+
+		"q" if args.starts_with("Supported") => format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+", PACKET_SIZE),
+		"q" if args.starts_with("Xfer:features:read:") => read_features(&args["Xfer:features:read:".len()..]),
+		"q" if args == "Attached" => String::from("1"),
+		"q" if args == "fThreadInfo" => String::from("m1"),
+		"q" if args == "sThreadInfo" => String::from("l"),
+		"q" if args == "C" => String::from("QC1"),
+
+		"Q" if args == "StartNoAckMode" => String::from("OK"),
+
+		"H" | "T" => String::from("OK"),
+
+		"D" => {
+			connection.send("OK").ok();
+			return None;
+		},
+
+		"k" => {
+			return None;
+		},
+
+		_ => String::new(),
+	};
+
+	Some(reply)
+}
+
+pub fn serve(program: &mut Program, address: &str) -> Result<(), String> {
+	// Only ever listen on localhost, the protocol has no authentication
+	let port = address.rsplit(':').next().and_then(|p| p.parse::<u16>().ok()).ok_or(format!("Invalid port \"{}\"", address))?;
+	let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!("Failed to listen on port {}: {}", port, e))?;
+	println!("Waiting for GDB on 127.0.0.1:{}", port);
+
+	let (stream, peer) = listener.accept().map_err(|e| e.to_string())?;
+	println!("GDB connected from {}", peer);
+
+	let reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+	let mut connection = Connection{reader, stream, no_ack: false};
+
+	while let Some(packet) = connection.read_packet().map_err(|e| e.to_string())? {
+		match handle_packet(program, &mut connection, &packet) {
+			Some(reply) => { connection.send(&reply).map_err(|e| e.to_string())?; },
+			None => { break; },
+		}
+
+		// The OK still gets acked, no-ack mode starts after it
+		if packet == b"QStartNoAckMode" {
+			connection.no_ack = true;
+		}
+	}
+
+	println!("GDB disconnected");
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// A connection to ourselves, the client end for the test to write to and the stub's end
+	fn connect() -> (TcpStream, Connection) {
+		let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+		let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let (stream, _) = listener.accept().unwrap();
+		let reader = BufReader::new(stream.try_clone().unwrap());
+		(client, Connection{reader, stream, no_ack: false})
+	}
+
+	fn framed(data: &[u8]) -> Vec<u8> {
+		let checksum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+		[b"$", data, format!("#{:02x}", checksum).as_bytes()].concat()
+	}
+
+	fn reply(program: &mut Program, packet: &[u8]) -> String {
+		let (_client, mut connection) = connect();
+		handle_packet(program, &mut connection, packet).unwrap()
+	}
+
+	#[test]
+	fn checksums_and_acks() {
+		let (mut client, mut connection) = connect();
+		client.write_all(b"+$m0,2#00").unwrap();
+		client.write_all(&framed(b"m0,2")).unwrap();
+		assert_eq!(connection.read_packet().unwrap(), Some(b"m0,2".to_vec()));
+
+		// The bad packet is nakked and skipped, the good one acked
+		let mut acks = [0u8; 2];
+		client.read_exact(&mut acks).unwrap();
+		assert_eq!(&acks, b"-+");
+
+		client.write_all(&[0x03]).unwrap();
+		assert_eq!(connection.read_packet().unwrap(), Some(b"?".to_vec()));
+		drop(client);
+		assert_eq!(connection.read_packet().unwrap(), None);
+	}
+
+	#[test]
+	fn escapes_and_repeats() {
+		assert_eq!(decode_packet(b"a}\x03b}\x04}]}\x0a"), b"a#b$}*");
+		assert_eq!(decode_packet(b"0* "), b"0000");
+		assert_eq!(decode_packet(b"*!"), b"");
+
+		// An escaped # doesn't end the packet early
+		let (mut client, mut connection) = connect();
+		connection.no_ack = true;
+		client.write_all(&framed(b"X1000,3:}\x03}\x04}]")).unwrap();
+		let packet = connection.read_packet().unwrap().unwrap();
+		let mut program = Program::new();
+		assert_eq!(handle_packet(&mut program, &mut connection, &packet), Some(String::from("OK")));
+		assert_eq!(program.memory[0x1000..0x1003], [b'#', b'$', b'}']);
+	}
+
+	#[test]
+	fn memory() {
+		let mut program = Program::new();
+		assert_eq!(reply(&mut program, b"M10,2:a9ff"), "OK");
+		assert_eq!(reply(&mut program, b"m10,3"), "a9ff00");
+		assert_eq!(reply(&mut program, b"X20,0:"), "OK");
+		assert_eq!(reply(&mut program, b"X20,2:a"), "E01");
+		assert_eq!(reply(&mut program, b"m0,10000").len(), MAX_MEMORY_READ * 2);
+		assert!(format!("${}#00", reply(&mut program, b"m0,10000")).len() <= PACKET_SIZE);
+	}
+
+	#[test]
+	fn registers() {
+		let mut program = Program::new();
+		assert_eq!(reply(&mut program, b"G0102030440fe12"), "OK");
+		assert_eq!((program.reg_a, program.reg_x, program.reg_y, program.stack_pointer, program.program_counter), (1, 2, 3, 0x40, 0x12fe));
+		assert_eq!(reply(&mut program, b"p5"), "fe12");
+		assert_eq!(reply(&mut program, b"P0=7f"), "OK");
+		assert_eq!(reply(&mut program, b"g"), "7f02030440fe12");
+		assert_eq!(reply(&mut program, b"p9"), "E01");
+	}
+
+	#[test]
+	fn stops() {
+		// lda #$01 / brk
+		let mut program = Program::new();
+		program.poke(0x0600, &[0xa9, 0x01, 0x00]);
+		program.program_counter = 0x0600;
+		assert_eq!(reply(&mut program, b"Z0,602,1"), "OK");
+		assert_eq!(reply(&mut program, b"c"), "S05");
+		assert_eq!(program.program_counter, 0x0602);
+		assert_eq!(reply(&mut program, b"z0,602,1"), "OK");
+		assert!(program.breakpoints.is_empty());
+
+		// BRK is a trap, not the program exiting
+		assert_eq!(reply(&mut program, b"s"), "S05");
+		assert_eq!(reply(&mut program, b"c600"), "S05");
+		assert_eq!(program.reg_a, 0x01);
+
+		program.poke(0x0700, &[0x02]);
+		assert_eq!(reply(&mut program, b"s700"), "S04");
+	}
+}
@@ -0,0 +1,69 @@
+// history.rs
+
+use std::collections::VecDeque;
+
+use crate::callstack::{Frame, StackWriter};
+
+// How many instructions back the debugger can reverse
+pub const HISTORY_LENGTH: usize = 10000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Registers {
+	pub pc: u16,
+	pub a: u8,
+	pub x: u8,
+	pub y: u8,
+	pub sp: u8,
+	pub status: u8,
+}
+
+// The machine state an instruction replaced: registers and the cycle count from before it ran, the old and new value
+// of every byte it wrote along with who had pushed the stack bytes among them, and the call stack frames it pushed or popped
+pub struct Step {
+	pub registers: Registers,
+	pub cycles: u64,
+	pub writes: Vec<(u16, u8, u8)>,
+	pub stack_writers: Vec<(u8, Option<StackWriter>)>,
+	pub pushed_frame: bool,
+	pub popped_frames: Vec<Frame>,
+}
+
+pub struct History {
+	steps: VecDeque<Step>,
+	capacity: usize,
+}
+
+impl History {
+	pub fn new(capacity: usize) -> Self {
+		History{steps: VecDeque::new(), capacity}
+	}
+
+	// Forgets the oldest step once the history is full
+	pub fn push(&mut self, step: Step) {
+		if self.steps.len() == self.capacity {
+			self.steps.pop_front();
+		}
+		self.steps.push_back(step);
+	}
+
+	pub fn pop(&mut self) -> Option<Step> {
+		self.steps.pop_back()
+	}
+
+	pub fn clear(&mut self) {
+		self.steps.clear();
+	}
+
+	pub fn len(&self) -> usize {
+		self.steps.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.steps.is_empty()
+	}
+
+	// Oldest first
+	pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Step> {
+		self.steps.iter()
+	}
+}
@@ -0,0 +1,274 @@
+// input.rs
+
+// Every prompt reads its lines through here. Command files given with -x or source are read first,
+// most recently sourced first, and then stdin. Lines from files are echoed after the prompt so the
+// output reads like a session. When stdin is a terminal it gets line editing, history kept in
+// ~/.fe6502_history, and tab completion; piped stdin is read plainly, without prompts.
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::FileHistory;
+use rustyline::validate::Validator;
+use rustyline::{CompletionType, Config, Context, Editor, Helper};
+
+use crate::console_colors::ERROR_REPORTED;
+use crate::symbols::SymbolTable;
+
+// Deep enough for any sensible nesting, shallow enough to catch a file that sources itself
+const MAX_SOURCE_DEPTH: usize = 16;
+
+static EXIT_ON_ERROR: AtomicBool = AtomicBool::new(false);
+
+const HISTORY_FILE: &str = ".fe6502_history";
+
+// Commands at both the fe6502> prompt and the debugger's
+const COMMANDS: &[&str] = &[
+	"a", "asm", "assemble", "backtrace", "bkpt", "breakpoint", "bt", "compare", "continue", "copy", "coverage", "db", "dbg", "debug", "exit",
+	"fill", "find", "finish", "gui", "help", "history", "irq", "load", "mem", "memory", "next", "nmi", "p", "poke", "print", "profile",
+	"rcontinue", "restore", "resume", "reverse-continue", "reverse-step", "rewind", "rstep", "run", "save",
+	"script", "set", "source", "stack", "step", "stop", "sym", "symbols", "tbreak", "until", "watch",
+];
+
+// Commands whose arguments are file names
+const FILE_COMMANDS: &[&str] = &["load", "asm", "source", "script", "save", "restore"];
+
+struct ReplHelper {
+	files: FilenameCompleter,
+	symbols: Vec<String>,
+}
+
+struct LineEditor {
+	editor: Editor<ReplHelper, FileHistory>,
+	history: Option<PathBuf>,
+}
+
+struct CommandFile {
+	lines: Vec<String>,
+	next: usize,
+}
+
+thread_local! {
+	static FILES: RefCell<Vec<CommandFile>> = const { RefCell::new(Vec::new()) };
+	static EDITOR: RefCell<Option<LineEditor>> = const { RefCell::new(None) };
+	static SYMBOLS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+	static SYMBOLS_GENERATION: RefCell<u64> = const { RefCell::new(0) };
+}
+
+// =============================================================
+
+fn is_symbol_char(c: char) -> bool {
+	c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '@'
+}
+
+impl Completer for ReplHelper {
+	type Candidate = Pair;
+
+	fn complete(&self, line: &str, pos: usize, context: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+		let before = &line[..pos];
+		let command = before.trim_start();
+		let words: Vec<&str> = command.split_whitespace().collect();
+
+		// Until there's whitespace after the first word, it's the command being completed
+		let (start, names): (usize, Vec<&str>) = match words.first() {
+			Some(first) if command.contains(char::is_whitespace) => {
+				if FILE_COMMANDS.contains(first) || words.starts_with(&["symbols", "load"]) {
+					return self.files.complete(line, pos, context);
+				}
+
+				let start = before.rfind(|c: char| !is_symbol_char(c)).map(|i| i + 1).unwrap_or(0);
+				let prefix = &before[start..];
+				(start, self.symbols.iter().map(|s| s.as_str()).filter(|s| s.starts_with(prefix)).collect())
+			},
+			_ => (before.len() - command.len(), COMMANDS.iter().copied().filter(|c| c.starts_with(command)).collect()),
+		};
+
+		Ok((start, names.into_iter().map(|name| Pair{display: name.to_string(), replacement: name.to_string()}).collect()))
+	}
+}
+
+impl Hinter for ReplHelper {
+	type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+fn history_path() -> Option<PathBuf> {
+	env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")).map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+fn new_editor() -> Option<LineEditor> {
+	let config = Config::builder().completion_type(CompletionType::List).auto_add_history(false).build();
+	let mut editor = Editor::with_config(config).ok()?;
+	editor.set_helper(Some(ReplHelper{files: FilenameCompleter::new(), symbols: Vec::new()}));
+
+	let history = history_path();
+	if let Some(path) = &history {
+		// There's no history file on the first run
+		editor.load_history(path).ok();
+	}
+	Some(LineEditor{editor, history})
+}
+
+// Reads a line with editing. Ctrl-C gives an empty line, like most shells.
+fn read_edited_line(prompt: &str) -> Option<String> {
+	EDITOR.with(|editor| {
+		let mut editor = editor.borrow_mut();
+		let line_editor = editor.as_mut()?;
+		if let Some(helper) = line_editor.editor.helper_mut() {
+			// Only a changed table is handed over, otherwise the helper keeps the names it has
+			if let Some(names) = SYMBOLS.with(|symbols| symbols.borrow_mut().take()) {
+				helper.symbols = names;
+			}
+		}
+
+		match line_editor.editor.readline(prompt) {
+			Ok(line) => {
+				if !line.trim().is_empty() && line_editor.editor.add_history_entry(line.as_str()).unwrap_or(false) {
+					if let Some(path) = &line_editor.history {
+						line_editor.editor.append_history(path).ok();
+					}
+				}
+				Some(line)
+			},
+			Err(ReadlineError::Interrupted) => Some(String::new()),
+			Err(_) => None,
+		}
+	})
+}
+
+// =============================================================
+
+// The names offered by tab completion, copied only when the table has changed since the last prompt
+pub fn update_symbols(table: &SymbolTable) {
+	SYMBOLS_GENERATION.with(|generation| {
+		if *generation.borrow() != table.generation() {
+			*generation.borrow_mut() = table.generation();
+			SYMBOLS.with(|symbols| *symbols.borrow_mut() = Some(table.names().cloned().collect()));
+		}
+	});
+}
+
+pub fn set_exit_on_error(exit: bool) {
+	EXIT_ON_ERROR.store(exit, Ordering::Relaxed);
+}
+
+// Queues a file of commands to run before anything else is read
+pub fn source_file(filename: &str) -> Result<(), String> {
+	let text = fs::read_to_string(filename).map_err(|e| format!("Failed to read {}: {}", filename, e))?;
+	FILES.with(|files| {
+		let mut files = files.borrow_mut();
+		if files.len() >= MAX_SOURCE_DEPTH {
+			return Err(format!("Files are sourced more than {} deep", MAX_SOURCE_DEPTH));
+		}
+		files.push(CommandFile{lines: text.lines().map(String::from).collect(), next: 0});
+		Ok(())
+	})
+}
+
+fn next_file_line() -> Option<String> {
+	FILES.with(|files| {
+		let mut files = files.borrow_mut();
+		while let Some(file) = files.last_mut() {
+			if let Some(line) = file.lines.get(file.next) {
+				file.next += 1;
+				return Some(line.clone());
+			}
+			files.pop();
+		}
+		None
+	})
+}
+
+// Stops the process once an error has been reported, if that was asked for
+pub fn check_exit_on_error() {
+	if EXIT_ON_ERROR.load(Ordering::Relaxed) && ERROR_REPORTED.load(Ordering::Relaxed) {
+		process::exit(1);
+	}
+}
+
+// Returns None at the end of input. Lines starting with # in command files are comments.
+pub fn read_line(prompt: &str) -> Option<String> {
+	check_exit_on_error();
+
+	while let Some(line) = next_file_line() {
+		if line.trim_start().starts_with('#') {
+			continue;
+		}
+		println!("{}{}", prompt, line);
+		return Some(line);
+	}
+
+	let stdin = io::stdin();
+	if stdin.is_terminal() {
+		let has_editor = EDITOR.with(|editor| {
+			let mut editor = editor.borrow_mut();
+			if editor.is_none() {
+				*editor = new_editor();
+			}
+			editor.is_some()
+		});
+		if has_editor {
+			return read_edited_line(prompt);
+		}
+
+		print!("{}", prompt);
+		io::stdout().flush().ok();
+	}
+
+	let mut line = String::new();
+	match stdin.read_line(&mut line) {
+		Ok(0) | Err(_) => None,
+		Ok(_) => Some(line),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rustyline::history::DefaultHistory;
+
+	fn complete(line: &str) -> (usize, Vec<String>) {
+		let helper = ReplHelper{files: FilenameCompleter::new(), symbols: vec![String::from("print_string"), String::from("start")]};
+		let history = DefaultHistory::new();
+		let (start, pairs) = helper.complete(line, line.len(), &Context::new(&history)).unwrap();
+		(start, pairs.into_iter().map(|p| p.replacement).collect())
+	}
+
+	#[test]
+	fn commands() {
+		assert_eq!(complete("").1.len(), COMMANDS.len());
+		assert_eq!(complete(" "), (1, COMMANDS.iter().map(|c| c.to_string()).collect()));
+		assert_eq!(complete("  rew"), (2, vec![String::from("rewind")]));
+		assert_eq!(complete("tb"), (0, vec![String::from("tbreak")]));
+	}
+
+	#[test]
+	fn symbols() {
+		assert_eq!(complete("bkpt pr"), (5, vec![String::from("print_string")]));
+		assert_eq!(complete("print start+s"), (12, vec![String::from("start")]));
+		assert_eq!(complete("step ").1.len(), 2);
+	}
+
+	#[test]
+	fn file_names() {
+		// Tests run in the crate directory
+		let (start, names) = complete("load ");
+		assert_eq!(start, 5);
+		assert!(names.iter().any(|n| n.starts_with("src")));
+		assert!(complete("symbols load Carg").1.contains(&String::from("Cargo.toml")));
+	}
+}
@@ -5,29 +5,71 @@ use crate::program::Program;
 use crate::addressing::AddressMode;
 use crate::addressing::make_u16;
 
+// Taking a branch costs a cycle, and another if it lands on a different page
 fn branch(program: &mut Program) {
 	program.abs_address = program.program_counter.wrapping_add(program.rel_address as u16);
+	program.cycles += if (program.abs_address & 0xff00) != (program.program_counter & 0xff00) { 2 } else { 1 };
 	program.program_counter = program.abs_address;
 }
 
+fn push_address(program: &mut Program, address: u16) {
+	program.stack_push((address >> 8) as u8);
+	program.stack_push(address as u8);
+}
+
+fn pull_address(program: &mut Program) -> u16 {
+	let lo = program.stack_pull();
+	let hi = program.stack_pull();
+	make_u16(lo, hi)
+}
+
+// Hardware interrupts push the status with the break flag clear, then jump through the vector
+pub fn enter_interrupt(program: &mut Program, vector: u16) {
+	push_address(program, program.program_counter);
+	program.stack_push(program.get_status() & !0x10);
+	program.flag_interrupt = true;
+	program.program_counter = make_u16(program.get_memory(vector), program.get_memory(vector.wrapping_add(1)));
+}
+
 // =============================================================
 
-pub fn ADC(program: &mut Program, _amode: &AddressMode) {
-	let prev = program.reg_a;
-	let result: u8;
-	if !program.flag_decimal {
-		result = program.reg_a.wrapping_add(program.fetched_byte + program.flag_carry as u8);
-	}
-	else {
-		result = program.reg_a.wrapping_add(program.fetched_byte + program.flag_carry as u8);
-	}
+// Binary addition with carry in, setting all four arithmetic flags. SBC adds the operand's complement.
+fn add_binary(program: &mut Program, value: u8) {
+	let a = program.reg_a;
+	let sum = a as u16 + value as u16 + program.flag_carry as u16;
+	let result = sum as u8;
 
 	program.reg_a = result;
 
-	program.flag_zero = program.reg_a == 0;
-	program.flag_negative = program.reg_a & 0x80 != 0;
-	program.flag_carry = result < prev;
-	program.flag_overflow = (result as i8).signum() != (prev as i8).signum();
+	program.flag_zero = result == 0;
+	program.flag_negative = result & 0x80 != 0;
+	program.flag_carry = sum > 0xff;
+	program.flag_overflow = (!(a ^ value) & (a ^ result) & 0x80) != 0;
+}
+
+pub fn ADC(program: &mut Program, _amode: &AddressMode) {
+	let a = program.reg_a;
+	let m = program.fetched_byte;
+	let carry = program.flag_carry as u16;
+	add_binary(program, m);
+
+	// Decimal mode adds two BCD digits. Like the NMOS 6502, Z still comes from the binary sum,
+	// and N and V from the sum before the high digit is adjusted.
+	if program.flag_decimal {
+		let mut low = (a & 0x0f) as u16 + (m & 0x0f) as u16 + carry;
+		if low >= 0x0a {
+			low = ((low + 0x06) & 0x0f) + 0x10;
+		}
+		let mut sum = (a & 0xf0) as u16 + (m & 0xf0) as u16 + low;
+
+		program.flag_negative = sum & 0x80 != 0;
+		program.flag_overflow = (!(a ^ m) & (a ^ sum as u8) & 0x80) != 0;
+		if sum >= 0xa0 {
+			sum += 0x60;
+		}
+		program.flag_carry = sum > 0xff;
+		program.reg_a = sum as u8;
+	}
 }
 
 pub fn AND(program: &mut Program, _amode: &AddressMode) {
@@ -110,8 +152,8 @@ pub fn BPL(program: &mut Program, _amode: &AddressMode) {
 }
 
 pub fn BRK(program: &mut Program, amode: &AddressMode) {
-	program.stack_push((program.program_counter + 2) as u8);
-	program.stack_push((program.program_counter + 3) as u8);
+	// BRK skips a padding byte, so the return address is two past the opcode
+	push_address(program, program.program_counter.wrapping_add(1));
 	PHP(program, amode);
 	program.flag_break = true;
 }
@@ -148,19 +190,19 @@ pub fn CLV(program: &mut Program, _amode: &AddressMode) {
 
 pub fn CMP(program: &mut Program, _amode: &AddressMode) {
 	program.flag_zero = program.reg_a == program.fetched_byte;
-	program.flag_carry = program.reg_a > program.fetched_byte || program.reg_a == program.fetched_byte;
+	program.flag_carry = program.reg_a >= program.fetched_byte;
 	program.flag_negative = (program.reg_a.wrapping_sub(program.fetched_byte) >> 7) == 1;
 }
 
 pub fn CPX(program: &mut Program, _amode: &AddressMode) {
 	program.flag_zero = program.reg_x == program.fetched_byte;
-	program.flag_carry = program.reg_x > program.fetched_byte || program.reg_x == program.fetched_byte;
+	program.flag_carry = program.reg_x >= program.fetched_byte;
 	program.flag_negative = (program.reg_x.wrapping_sub(program.fetched_byte) >> 7) == 1;
 }
 
 pub fn CPY(program: &mut Program, _amode: &AddressMode) {
 	program.flag_zero = program.reg_y == program.fetched_byte;
-	program.flag_carry = program.reg_y > program.fetched_byte || program.reg_y == program.fetched_byte;
+	program.flag_carry = program.reg_y >= program.fetched_byte;
 	program.flag_negative = (program.reg_y.wrapping_sub(program.fetched_byte) >> 7) == 1;
 }
 
@@ -223,8 +265,8 @@ pub fn JMP(program: &mut Program, _amode: &AddressMode) {
 }
 
 pub fn JSR(program: &mut Program, _amode: &AddressMode) {
-	program.stack_push((program.program_counter + 2) as u8);
-	program.stack_push((program.program_counter + 3) as u8);
+	// The pushed address is the last byte of the JSR, RTS adds one back
+	push_address(program, program.program_counter.wrapping_sub(1));
 	program.program_counter = program.abs_address;
 }
 
@@ -285,16 +327,8 @@ pub fn PHA(program: &mut Program, _amode: &AddressMode) {
 }
 
 pub fn PHP(program: &mut Program, _amode: &AddressMode) {
-	let mut result = 0u8;
-	result |= program.flag_carry as u8;
-	result |= (program.flag_zero as u8) << 1;
-	result |= (program.flag_interrupt as u8) << 2;
-	result |= (program.flag_decimal as u8) << 3;
-	result |= (program.flag_break as u8) << 4;
-	result |= (program.flag_overflow as u8) << 6;
-	result |= (program.flag_negative as u8) << 7;
-
-	program.stack_push(result);
+	let status = program.get_status();
+	program.stack_push(status);
 }
 
 pub fn PLA(program: &mut Program, _amode: &AddressMode) {
@@ -302,15 +336,11 @@ pub fn PLA(program: &mut Program, _amode: &AddressMode) {
 }
 
 pub fn PLP(program: &mut Program, _amode: &AddressMode) {
+	// The break flag isn't a real register bit, pulling it back shouldn't stop the program
+	let flag_break = program.flag_break;
 	let status = program.stack_pull();
-
-	program.flag_carry = (status & 1) == 1;
-	program.flag_zero = ((status >> 1) & 1) == 1;
-	program.flag_interrupt = ((status >> 2) & 1) == 1;
-	program.flag_decimal = ((status >> 3) & 1) == 1;
-	program.flag_break = ((status >> 4) & 1) == 1;
-	program.flag_overflow = ((status >> 6) & 1) == 1;
-	program.flag_negative = ((status >> 7) & 1) == 1;
+	program.set_status(status);
+	program.flag_break = flag_break;
 }
 
 pub fn ROL(program: &mut Program, amode: &AddressMode) {
@@ -353,33 +383,31 @@ pub fn ROR(program: &mut Program, amode: &AddressMode) {
 
 pub fn RTI(program: &mut Program, amode: &AddressMode) {
 	PLP(program, amode);
-	let hi = program.stack_pull();
-	let lo = program.stack_pull();
-	program.program_counter = make_u16(lo, hi);
+	program.program_counter = pull_address(program);
 }
 
 pub fn RTS(program: &mut Program, _amode: &AddressMode) {
-	let hi = program.stack_pull();
-	let lo = program.stack_pull();
-	program.program_counter = make_u16(lo, hi);
+	program.program_counter = pull_address(program).wrapping_add(1);
 }
 
 pub fn SBC(program: &mut Program, _amode: &AddressMode) {
-	let prev = program.reg_a;
-	let result: u8;
-	if !program.flag_decimal {
-		result = program.reg_a.wrapping_sub(program.fetched_byte + !program.flag_carry as u8);
-	}
-	else {
-		result = program.reg_a.wrapping_sub(program.fetched_byte + !program.flag_carry as u8);
+	let a = program.reg_a;
+	let m = program.fetched_byte;
+	let borrow = !program.flag_carry as i16;
+	add_binary(program, !m);
+
+	// In decimal mode only the result is adjusted to BCD, the flags are the binary ones
+	if program.flag_decimal {
+		let mut low = (a & 0x0f) as i16 - (m & 0x0f) as i16 - borrow;
+		if low < 0 {
+			low = ((low - 0x06) & 0x0f) - 0x10;
+		}
+		let mut difference = (a & 0xf0) as i16 - (m & 0xf0) as i16 + low;
+		if difference < 0 {
+			difference -= 0x60;
+		}
+		program.reg_a = difference as u8;
 	}
-
-	program.reg_a = result;
-
-	program.flag_zero = program.reg_a == 0;
-	program.flag_negative = program.reg_a & 0x80 != 0;
-	program.flag_carry = result < prev;
-	program.flag_overflow = (result as i8).signum() != (prev as i8).signum();
 }
 
 pub fn SEC(program: &mut Program, _amode: &AddressMode) {
@@ -444,3 +472,83 @@ pub fn TYA(program: &mut Program, _amode: &AddressMode) {
 	program.flag_negative = program.reg_a & 0x80 != 0;
 	program.flag_zero = program.reg_a == 0;
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn arithmetic(func: fn(&mut Program, &AddressMode), decimal: bool, a: u8, m: u8, carry: bool) -> Program {
+		let mut program = Program::new();
+		program.flag_decimal = decimal;
+		program.reg_a = a;
+		program.fetched_byte = m;
+		program.flag_carry = carry;
+		func(&mut program, &AddressMode::Immediate);
+		program
+	}
+
+	// Result, then N, V, Z and C
+	fn flags(program: &Program) -> (u8, bool, bool, bool, bool) {
+		(program.reg_a, program.flag_negative, program.flag_overflow, program.flag_zero, program.flag_carry)
+	}
+
+	#[test]
+	fn adc() {
+		assert_eq!(flags(&arithmetic(ADC, false, 0x00, 0xff, true)), (0x00, false, false, true, true));
+		assert_eq!(flags(&arithmetic(ADC, false, 0x01, 0xff, false)), (0x00, false, false, true, true));
+		assert_eq!(flags(&arithmetic(ADC, false, 0x50, 0x50, false)), (0xa0, true, true, false, false));
+		assert_eq!(flags(&arithmetic(ADC, false, 0xd0, 0x90, false)), (0x60, false, true, false, true));
+		assert_eq!(flags(&arithmetic(ADC, false, 0x7f, 0x00, true)), (0x80, true, true, false, false));
+		assert_eq!(flags(&arithmetic(ADC, false, 0xff, 0xff, true)), (0xff, true, false, false, true));
+	}
+
+	#[test]
+	fn sbc() {
+		assert_eq!(flags(&arithmetic(SBC, false, 0x00, 0x00, true)), (0x00, false, false, true, true));
+		assert_eq!(flags(&arithmetic(SBC, false, 0x00, 0x00, false)), (0xff, true, false, false, false));
+		assert_eq!(flags(&arithmetic(SBC, false, 0x00, 0x01, true)), (0xff, true, false, false, false));
+		assert_eq!(flags(&arithmetic(SBC, false, 0x50, 0xb0, true)), (0xa0, true, true, false, false));
+		assert_eq!(flags(&arithmetic(SBC, false, 0xd0, 0x70, true)), (0x60, false, true, false, true));
+		assert_eq!(flags(&arithmetic(SBC, false, 0x80, 0xff, true)), (0x81, true, false, false, false));
+	}
+
+	#[test]
+	fn decimal() {
+		assert_eq!(arithmetic(ADC, true, 0x12, 0x34, false).reg_a, 0x46);
+		assert_eq!(flags(&arithmetic(ADC, true, 0x58, 0x46, true)), (0x05, true, true, false, true));
+		assert_eq!(flags(&arithmetic(ADC, true, 0x99, 0x01, false)), (0x00, true, false, false, true));
+		assert_eq!(arithmetic(ADC, true, 0x09, 0x01, false).reg_a, 0x10);
+
+		assert_eq!(flags(&arithmetic(SBC, true, 0x46, 0x12, true)), (0x34, false, false, false, true));
+		assert_eq!(arithmetic(SBC, true, 0x40, 0x13, true).reg_a, 0x27);
+		assert_eq!(arithmetic(SBC, true, 0x32, 0x02, false).reg_a, 0x29);
+		assert_eq!(flags(&arithmetic(SBC, true, 0x00, 0x01, true)), (0x99, true, false, false, false));
+	}
+
+	#[test]
+	fn compare() {
+		let mut program = Program::new();
+		for (func, register) in [(CMP as fn(&mut Program, &AddressMode), 0), (CPX, 1), (CPY, 2)] {
+			for (value, m, negative, zero, carry) in [(0x10, 0x10, false, true, true), (0x10, 0x20, true, false, false), (0x20, 0x10, false, false, true), (0x00, 0xff, false, false, false)] {
+				*[&mut program.reg_a, &mut program.reg_x, &mut program.reg_y][register] = value;
+				program.fetched_byte = m;
+				func(&mut program, &AddressMode::Immediate);
+				assert_eq!((program.flag_negative, program.flag_zero, program.flag_carry), (negative, zero, carry));
+			}
+		}
+	}
+
+	#[test]
+	fn opcode_table() {
+		// ldy #$02 / ldx #$aa / stx $10,y / inc $12 / lda #$0f / sta $20 / lda #$30 / sta $21 / lda #$ff / eor ($20,x)
+		let mut program = Program::new();
+		program.poke(0x0200, &[0xa0, 0x02, 0xa2, 0xaa, 0x96, 0x10, 0xe6, 0x12, 0xa9, 0x0f, 0x85, 0x20, 0xa9, 0x30, 0x85, 0x21, 0xa2, 0x00, 0xa9, 0xff, 0x41, 0x20]);
+		program.memory[0x300f] = 0x0f;
+		program.program_counter = 0x0200;
+		for _ in 0..11 {
+			program.step(false).unwrap();
+		}
+		assert_eq!(program.memory[0x12], 0xab);
+		assert_eq!(program.reg_a, 0xf0);
+	}
+}
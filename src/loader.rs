@@ -0,0 +1,328 @@
+// loader.rs
+
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileFormat {
+	Prg,
+	IntelHex,
+	SRecord,
+	// Assembled by Program::load rather than read here
+	Assembly,
+}
+
+pub struct Segment {
+	pub address: u16,
+	pub data: Vec<u8>,
+}
+
+pub struct LoadedImage {
+	pub segments: Vec<Segment>,
+	pub entry: Option<u16>,
+}
+
+impl FileFormat {
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name.to_lowercase().as_str() {
+			"prg" => Some(FileFormat::Prg),
+			"ihex" | "hex" | "intel" => Some(FileFormat::IntelHex),
+			"srec" | "s19" | "s28" | "s37" | "motorola" => Some(FileFormat::SRecord),
+			"asm" | "source" => Some(FileFormat::Assembly),
+			_ => None,
+		}
+	}
+
+	pub fn from_extension(filename: &str) -> Self {
+		let extension = Path::new(filename).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+		match extension.as_str() {
+			"hex" | "ihex" | "ihx" => FileFormat::IntelHex,
+			"srec" | "s19" | "s28" | "s37" | "mot" => FileFormat::SRecord,
+			"s" | "asm" | "a65" => FileFormat::Assembly,
+			_ => FileFormat::Prg,
+		}
+	}
+}
+
+impl LoadedImage {
+	fn new() -> Self {
+		LoadedImage{segments: Vec::new(), entry: None}
+	}
+
+	pub fn byte_count(&self) -> usize {
+		self.segments.iter().map(|s| s.data.len()).sum()
+	}
+
+	// Appends to the last segment if the data follows on directly, otherwise starts a new one
+	fn add_data(&mut self, address: u32, data: &[u8]) -> Result<(), String> {
+		if address as usize + data.len() > 0x10000 {
+			return Err(format!("Data at ${:x} does not fit in 64K of memory", address));
+		}
+
+		if let Some(last) = self.segments.last_mut() {
+			if last.address as usize + last.data.len() == address as usize {
+				last.data.extend_from_slice(data);
+				return Ok(());
+			}
+		}
+
+		self.segments.push(Segment{address: address as u16, data: data.to_vec()});
+		Ok(())
+	}
+
+	fn set_entry(&mut self, address: u32) -> Result<(), String> {
+		if address > 0xffff {
+			return Err(format!("Start address ${:x} is outside of memory", address));
+		}
+
+		self.entry = Some(address as u16);
+		Ok(())
+	}
+}
+
+// =============================================================
+
+fn parse_hex_bytes(text: &str) -> Result<Vec<u8>, String> {
+	if !text.is_ascii() || !text.len().is_multiple_of(2) {
+		return Err(String::from("Record is not an even number of hex digits"));
+	}
+
+	// from_str_radix would also take a sign, so the digits are checked first
+	text.as_bytes().chunks(2)
+		.map(|pair| {
+			let digits = String::from_utf8_lossy(pair);
+			if !pair.iter().all(u8::is_ascii_hexdigit) {
+				return Err(format!("Invalid hex digits \"{}\"", digits));
+			}
+			u8::from_str_radix(&digits, 16).map_err(|_| format!("Invalid hex digits \"{}\"", digits))
+		})
+		.collect()
+}
+
+fn parse_prg(bytes: &[u8]) -> Result<LoadedImage, String> {
+	let mut cursor = Cursor::new(bytes);
+	let origin = cursor.read_u16::<LittleEndian>().map_err(|_| String::from("File is too short to contain an origin"))?;
+	let mut data = Vec::new();
+	cursor.read_to_end(&mut data).map_err(|e| e.to_string())?;
+
+	let mut image = LoadedImage::new();
+	image.add_data(origin as u32, &data)?;
+	image.entry = Some(origin);
+	Ok(image)
+}
+
+fn parse_intel_hex(text: &str) -> Result<LoadedImage, String> {
+	let mut image = LoadedImage::new();
+	let mut base: u32 = 0;
+
+	for (i, line) in text.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() {
+			continue;
+		}
+
+		let error = |message: &str| format!("line {}: {}", i + 1, message);
+		if !line.is_ascii() {
+			return Err(error("Record contains non-ASCII characters"));
+		}
+
+		let record = line.strip_prefix(':').ok_or_else(|| error("Record does not start with ':'"))?;
+		let bytes = parse_hex_bytes(record).map_err(|e| error(&e))?;
+		if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+			return Err(error("Record length does not match its byte count"));
+		}
+
+		if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+			return Err(error("Checksum mismatch"));
+		}
+
+		let offset = ((bytes[1] as u32) << 8) | bytes[2] as u32;
+		let data = &bytes[4..bytes.len() - 1];
+		match bytes[3] {
+			0x00 => image.add_data(base + offset, data).map_err(|e| error(&e))?,
+			0x01 => { return Ok(image); },
+			0x02 if data.len() == 2 => { base = (((data[0] as u32) << 8) | data[1] as u32) << 4; },
+			0x04 if data.len() == 2 => { base = (((data[0] as u32) << 8) | data[1] as u32) << 16; },
+			0x03 if data.len() == 4 => {
+				let segment = ((data[0] as u32) << 8) | data[1] as u32;
+				let offset = ((data[2] as u32) << 8) | data[3] as u32;
+				image.set_entry((segment << 4) + offset).map_err(|e| error(&e))?;
+			},
+			0x05 if data.len() == 4 => {
+				image.set_entry(data.iter().fold(0u32, |acc, b| (acc << 8) | *b as u32)).map_err(|e| error(&e))?;
+			},
+			kind => { return Err(error(&format!("Invalid record type {:02x}", kind))); },
+		}
+	}
+
+	Err(String::from("Missing end of file record"))
+}
+
+fn parse_srecord(text: &str) -> Result<LoadedImage, String> {
+	let mut image = LoadedImage::new();
+
+	for (i, line) in text.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() {
+			continue;
+		}
+
+		let error = |message: &str| format!("line {}: {}", i + 1, message);
+		if !line.is_ascii() {
+			return Err(error("Record contains non-ASCII characters"));
+		}
+
+		if line.len() < 2 || !line.starts_with('S') {
+			return Err(error("Record does not start with 'S'"));
+		}
+
+		let kind = &line[1..2];
+		let bytes = parse_hex_bytes(&line[2..]).map_err(|e| error(&e))?;
+		if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+			return Err(error("Record length does not match its byte count"));
+		}
+
+		let sum = bytes[..bytes.len() - 1].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+		if !sum != bytes[bytes.len() - 1] {
+			return Err(error("Checksum mismatch"));
+		}
+
+		let address_len = match kind {
+			"0" | "1" | "5" | "9" => 2,
+			"2" | "6" | "8" => 3,
+			"3" | "7" => 4,
+			_ => { return Err(error(&format!("Invalid record type S{}", kind))); },
+		};
+
+		if bytes.len() < address_len + 2 {
+			return Err(error("Record is too short for its address"));
+		}
+
+		let address = bytes[1..=address_len].iter().fold(0u32, |acc, b| (acc << 8) | *b as u32);
+		let data = &bytes[address_len + 1..bytes.len() - 1];
+		match kind {
+			"1" | "2" | "3" => image.add_data(address, data).map_err(|e| error(&e))?,
+			"7" | "8" | "9" => image.set_entry(address).map_err(|e| error(&e))?,
+			_ => {}, // Header and record counts carry nothing to load
+		}
+	}
+
+	Ok(image)
+}
+
+pub fn load_file(filename: &str, format: FileFormat) -> Result<LoadedImage, String> {
+	let bytes = fs::read(filename).map_err(|e| format!("Failed to open file: {}", e))?;
+	match format {
+		FileFormat::Prg => parse_prg(&bytes),
+		FileFormat::IntelHex => parse_intel_hex(&String::from_utf8_lossy(&bytes)),
+		FileFormat::SRecord => parse_srecord(&String::from_utf8_lossy(&bytes)),
+		FileFormat::Assembly => Err(String::from("Source files have to be assembled")),
+	}
+}
+
+pub fn load_binary(filename: &str, address: u16) -> Result<LoadedImage, String> {
+	let bytes = fs::read(filename).map_err(|e| format!("Failed to open file: {}", e))?;
+	let mut image = LoadedImage::new();
+	image.add_data(address as u32, &bytes)?;
+	Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ihex(kind: u8, address: u16, data: &[u8]) -> String {
+		let mut bytes = vec![data.len() as u8, (address >> 8) as u8, address as u8, kind];
+		bytes.extend_from_slice(data);
+		let checksum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg();
+		bytes.push(checksum);
+		format!(":{}\n", bytes.iter().map(|b| format!("{:02X}", b)).collect::<String>())
+	}
+
+	fn srec(kind: char, address: u16, data: &[u8]) -> String {
+		let mut bytes = vec![data.len() as u8 + 3, (address >> 8) as u8, address as u8];
+		bytes.extend_from_slice(data);
+		let checksum = !bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+		bytes.push(checksum);
+		format!("S{}{}\n", kind, bytes.iter().map(|b| format!("{:02X}", b)).collect::<String>())
+	}
+
+	fn segments(image: &LoadedImage) -> Vec<(u16, Vec<u8>)> {
+		image.segments.iter().map(|s| (s.address, s.data.clone())).collect()
+	}
+
+	#[test]
+	fn prg() {
+		let image = parse_prg(&[0x00, 0x10, 0xa9, 0x01]).unwrap_or_else(|e| panic!("{}", e));
+		assert_eq!(segments(&image), [(0x1000, vec![0xa9, 0x01])]);
+		assert_eq!(image.entry, Some(0x1000));
+		assert!(parse_prg(&[0x00]).is_err());
+	}
+
+	#[test]
+	fn intel_hex_segments() {
+		let text = [ihex(0, 0x1000, &[1, 2]), ihex(0, 0x1002, &[3]), ihex(0, 0x2000, &[4]), ihex(1, 0, &[])].concat();
+		let image = parse_intel_hex(&text).unwrap_or_else(|e| panic!("{}", e));
+		assert_eq!(segments(&image), [(0x1000, vec![1, 2, 3]), (0x2000, vec![4])]);
+		assert_eq!(image.entry, None);
+	}
+
+	#[test]
+	fn intel_hex_addresses() {
+		// A segment base of $0100 puts offset $0010 at $1010, and the start address record gives the entry point
+		let text = [ihex(2, 0, &[0x01, 0x00]), ihex(0, 0x0010, &[0xea]), ihex(5, 0, &[0, 0, 0x10, 0x10]), ihex(1, 0, &[])].concat();
+		let image = parse_intel_hex(&text).unwrap_or_else(|e| panic!("{}", e));
+		assert_eq!(segments(&image), [(0x1010, vec![0xea])]);
+		assert_eq!(image.entry, Some(0x1010));
+
+		let text = [ihex(4, 0, &[0x00, 0x01]), ihex(0, 0, &[0xea]), ihex(1, 0, &[])].concat();
+		assert_eq!(parse_intel_hex(&text).err().as_deref(), Some("line 2: Data at $10000 does not fit in 64K of memory"));
+	}
+
+	#[test]
+	fn intel_hex_errors() {
+		let mut bad = ihex(0, 0x1000, &[1, 2]);
+		bad.replace_range(bad.len() - 3..bad.len() - 1, "00");
+		assert_eq!(parse_intel_hex(&[bad, ihex(1, 0, &[])].concat()).err().as_deref(), Some("line 1: Checksum mismatch"));
+
+		assert_eq!(parse_intel_hex(&ihex(0, 0x1000, &[1])).err().as_deref(), Some("Missing end of file record"));
+		assert_eq!(parse_intel_hex("1000\n").err().as_deref(), Some("line 1: Record does not start with ':'"));
+		assert_eq!(parse_intel_hex(":0\n").err().as_deref(), Some("line 1: Record is not an even number of hex digits"));
+		assert_eq!(parse_intel_hex(":+1000000EF\n").err().as_deref(), Some("line 1: Invalid hex digits \"+1\""));
+		assert_eq!(parse_intel_hex(":é\n").err().as_deref(), Some("line 1: Record contains non-ASCII characters"));
+		assert_eq!(parse_intel_hex(":0200000000FE\n").err().as_deref(), Some("line 1: Record length does not match its byte count"));
+	}
+
+	#[test]
+	fn srecord_segments() {
+		let text = [srec('0', 0, b"hdr"), srec('1', 0x0800, &[1, 2]), srec('1', 0x0900, &[3]), srec('9', 0x0800, &[])].concat();
+		let image = parse_srecord(&text).unwrap_or_else(|e| panic!("{}", e));
+		assert_eq!(segments(&image), [(0x0800, vec![1, 2]), (0x0900, vec![3])]);
+		assert_eq!(image.entry, Some(0x0800));
+	}
+
+	#[test]
+	fn srecord_errors() {
+		let mut bad = srec('1', 0x0800, &[1, 2]);
+		bad.replace_range(bad.len() - 3..bad.len() - 1, "00");
+		assert_eq!(parse_srecord(&bad).err().as_deref(), Some("line 1: Checksum mismatch"));
+
+		assert_eq!(parse_srecord("S\u{e9}\n").err().as_deref(), Some("line 1: Record contains non-ASCII characters"));
+		assert_eq!(parse_srecord("X1\n").err().as_deref(), Some("line 1: Record does not start with 'S'"));
+		assert_eq!(parse_srecord("S4030000FC\n").err().as_deref(), Some("line 1: Invalid record type S4"));
+		assert_eq!(parse_srecord("S10200FD\n").err().as_deref(), Some("line 1: Record is too short for its address"));
+	}
+
+	#[test]
+	fn format_names() {
+		assert_eq!(FileFormat::from_extension("game.HEX"), FileFormat::IntelHex);
+		assert_eq!(FileFormat::from_extension("rom.s19"), FileFormat::SRecord);
+		assert_eq!(FileFormat::from_extension("demo"), FileFormat::Prg);
+		assert_eq!(FileFormat::from_extension("hello.s"), FileFormat::Assembly);
+		assert_eq!(FileFormat::from_name("srec"), Some(FileFormat::SRecord));
+		assert_eq!(FileFormat::from_name("bin"), None);
+	}
+}
@@ -10,99 +10,640 @@ mod opcodes;
 mod instructions;
 mod program;
 mod debug;
+mod expression;
+mod assembler;
+mod loader;
+mod symbols;
+mod breakpoints;
+mod history;
+mod callstack;
+mod snapshot;
+mod gdb;
+mod dap;
+mod script;
+mod input;
+mod profiler;
+mod coverage;
+mod tui;
 
-use std::io::{self, Write};
-use std::fs::File;
-use std::{env, process};
-use std::u16;
+use std::{env, fs, process};
+use std::path::Path;
 
-use crate::program::Program;
-use crate::opcodes::{INSTRUCTION_DATA, Opcode};
-use crate::addressing::ADDRESS_FUNCS;
+use crate::program::{Program, StepMode};
+use crate::profiler::Profile;
+use crate::coverage::Coverage;
+use crate::loader::FileFormat;
+use crate::breakpoints::{Condition, Watchpoint, WatchKind};
 
-use byteorder::{LittleEndian, ReadBytesExt};
-use num::FromPrimitive;
 //use fltk::{app::*, window::*, button::*, frame::*};
 
 // =======================================================================
 
-fn get_input_args(input: &String) -> Vec<String> {
-	let trimmed = input.trim_start().trim_end().trim_matches('\n').to_lowercase();
-	let result = trimmed.as_str();
-	
+fn get_input_args(input: &str) -> Vec<String> {
 	let mut vec = Vec::<String>::new();
-	for st in result.split_whitespace() {
+	for st in input.split_whitespace() {
 		vec.push(String::from(st));
 	}
 
+	// Only the command itself is case-insensitive, file names are not
+	if let Some(cmd) = vec.first_mut() {
+		*cmd = cmd.to_lowercase();
+	}
+
 	vec
 }
 
+// The input after its first [count] words with the spacing kept, for arguments such as "quoted  text"
+fn raw_args(input: &str, count: usize) -> &str {
+	let mut rest = input.trim_start();
+	for _ in 0..count {
+		rest = rest.find(char::is_whitespace).map(|end| rest[end..].trim_start()).unwrap_or("");
+	}
+	rest
+}
+
 // =======================================================================
 
-fn load_program_file(filename: &String) -> Program {
-	let mut file = File::open(filename).expect(format!("Failed to open file {}", filename).as_str());
-	let mut program = Program::new();
+// With an address the file is loaded as a headerless binary, otherwise by format. Memory outside the file is
+// left alone, and so are breakpoints and the rest.
+fn load_program_file(program: &mut Program, filename: &str, format: Option<FileFormat>, address: Option<u16>) -> bool {
+	match program.load(filename, format, address) {
+		Ok(image) => {
+			for segment in image.segments.iter().filter(|s| !s.data.is_empty()) {
+				println!("Loaded ${:04x}-${:04x} ({} bytes)", segment.address, segment.address as usize + segment.data.len() - 1, segment.data.len());
+			}
+			if let Some(entry) = image.entry {
+				println!("Entry point ${:04x}", entry);
+			}
+			true
+		},
+		Err(message) => {
+			// Assembler errors come one per line
+			for line in message.lines() {
+				print_error!("{}", line);
+			}
+			false
+		},
+	}
+}
 
-	let origin = file.read_u16::<LittleEndian>().unwrap();
+fn assemble_file(filename: &str) -> Option<assembler::Assembly> {
+	let source = match fs::read_to_string(filename) {
+		Ok(source) => source,
+		Err(err) => {
+			print_error!("Failed to read {}: {}", filename, err);
+			return None;
+		},
+	};
+
+	match assembler::assemble(&source) {
+		Ok(assembly) => Some(assembly),
+		Err(errors) => {
+			for error in errors {
+				print_error!("{}:{}: {}", filename, error.line, error.message);
+			}
+			None
+		},
+	}
+}
+
+fn load_symbols(program: &mut Program, filename: &str) -> bool {
+	match symbols::load_symbol_file(filename) {
+		Ok(symbols) => {
+			for (name, address) in &symbols {
+				program.symbols.insert(name, *address);
+			}
+			println!("Loaded {} symbols", symbols.len());
+			true
+		},
+		Err(message) => {
+			print_error!("{}: {}", filename, message);
+			false
+		},
+	}
+}
+
+// symbols load [file] | symbols list [filter] | symbols clear
+fn symbols_command(program: &mut Program, cmd_args: &[String]) {
+	match (cmd_args.get(1).map(|s| s.as_str()), cmd_args.get(2)) {
+		(Some("load"), Some(filename)) => {
+			load_symbols(program, filename);
+		},
+
+		(Some("list"), filter) | (None, filter) => {
+			for (address, name) in program.symbols.iter() {
+				if filter.map(|f| name.contains(f.as_str())).unwrap_or(true) {
+					println!("${:04x}  {}", address, name);
+				}
+			}
+		},
+
+		(Some("clear"), None) => {
+			program.symbols.clear();
+		},
+
+		_ => {
+			print_error!("Usage: symbols [load file | list [filter] | clear]");
+		},
+	}
+}
+
+// fe6502 asm <source> [-o output]
+fn assemble_command(args: &[String]) {
+	let mut source = None;
+	let mut output = None;
 	let mut i = 0;
-	loop {
-		match file.read_u8() {
-			Ok(byte) => {
-				program.set_memory(origin + i, byte);
+	while i < args.len() {
+		match args[i].as_str() {
+			"-o" => {
+				output = args.get(i + 1).cloned();
 				i += 1;
 			},
 
-			Err(_) => { break; }
+			_ => {
+				source = Some(args[i].clone());
+			},
 		}
+
+		i += 1;
+	}
+
+	let source = match source {
+		Some(source) => source,
+		None => {
+			eprintln!("Usage: fe6502 asm <source> [-o output.prg]");
+			process::exit(1);
+		},
+	};
+
+	let output = output.unwrap_or_else(|| Path::new(&source).with_extension("prg").to_string_lossy().into_owned());
+	let assembly = match assemble_file(&source) {
+		Some(assembly) => assembly,
+		None => process::exit(1),
+	};
+
+	if let Err(err) = fs::write(&output, assembly.to_prg()) {
+		print_error!("Failed to write {}: {}", output, err);
+		process::exit(1);
 	}
 
-	program.program_counter = origin;
-	program.origin = origin;
-	program
+	println!("Assembled {} bytes at ${:04x} into {}", assembly.image.byte_count(), assembly.origin(), output);
 }
 
-fn run_program(program: &mut Program, debug_mode: bool) -> bool {
-	println!("Running program from ${:x}", program.origin);
-	program.program_counter = program.origin;
-	program.flag_break = false;
+// Assembles lines into memory starting at `address`, then keeps prompting for the next line until an empty one
+fn assemble_interactive(program: &mut Program, cmd_args: &[String]) {
+	let mut address = match cmd_args.get(1).and_then(|a| program.symbols.parse_address(a)) {
+		Some(address) => address,
+		_ => {
+			print_error!("Usage: a [address] [instruction]");
+			return;
+		},
+	};
+
+	let mut line = cmd_args[2..].join(" ");
 	loop {
-		let addr = program.program_counter;
+		if !line.trim().is_empty() {
+			match assembler::assemble_line(&line, address, &program.symbols) {
+				Ok(bytes) => {
+					for (i, byte) in bytes.iter().enumerate() {
+						program.set_memory(address.wrapping_add(i as u16), *byte);
+					}
+					debug::print_disassembly(program, address, 1);
+					address = address.wrapping_add(bytes.len() as u16);
+				},
+				Err(message) => {
+					print_error!("{}", message);
+				},
+			}
+		}
 
-		let byte = program.get_memory(program.program_counter);
-		program.advance_counter();
-		let opcode_test = FromPrimitive::from_u8(byte);
-		let opcode: Opcode;
-		match opcode_test {
-			None => {
-				eprintln!("{}Error:{} Invalid opcode (${:x})", con_red!(), con_reset!(), byte);
-				return false;
-			},
-			Some(oc) => {
-				opcode = oc;
+		line = match input::read_line(&format!("${:04x}: ", address)) {
+			Some(line) if !line.trim().is_empty() => line,
+			_ => { break; },
+		};
+	}
+}
+
+// breakpoint [address] [if condition], also used for tbreak
+fn set_breakpoint(program: &mut Program, cmd_args: &[String], temporary: bool) {
+	let addr = match program.evaluate_address(cmd_args.get(1).map(|a| a.as_str()).unwrap_or("")) {
+		Ok(addr) => addr,
+		Err(message) => {
+			print_error!("{}", message);
+			return;
+		},
+	};
+
+	let condition = match cmd_args.get(2).map(|s| s.as_str()) {
+		Some("if") => {
+			match Condition::parse(&cmd_args[3..].join(" ")) {
+				Ok(condition) => Some(condition),
+				Err(message) => {
+					print_error!("{}", message);
+					return;
+				},
+			}
+		},
+		Some(_) => {
+			print_error!("Expected \"if\" after the address");
+			return;
+		},
+		None => None,
+	};
+
+	let condition_text = condition.as_ref().map(|c| format!(" if {}", c.text)).unwrap_or_default();
+	match program.add_breakpoint(addr, condition, temporary) {
+		(id, true) => println!("Breakpoint {} set at {}${:x}{}{}", id, con_red!(), addr, con_reset!(), condition_text),
+		(id, false) => println!("Breakpoint {} is already set at {}${:x}{}{}", id, con_red!(), addr, con_reset!(), condition_text),
+	}
+}
+
+// breakpoint [list | del n | enable n | disable n | ignore n count], or an address to set one
+fn breakpoint_command(program: &mut Program, cmd_args: &[String]) {
+	let id = cmd_args.get(2).and_then(|n| n.parse::<u32>().ok());
+	match (cmd_args.get(1).map(|s| s.as_str()), id) {
+		(None, _) | (Some("list"), _) => {
+			if program.breakpoints.is_empty() {
+				println!("No breakpoints");
+			}
+			for breakpoint in &program.breakpoints {
+				println!("{}", breakpoint.describe());
 			}
+		},
+
+		(Some("del"), Some(id)) | (Some("delete"), Some(id)) => {
+			if !program.remove_breakpoint(id) {
+				print_error!("No breakpoint {}", id);
+			}
+		},
+
+		(Some("del"), None) | (Some("delete"), None) if cmd_args.get(2).map(|a| a == "all").unwrap_or(false) => {
+			program.breakpoints.clear();
+		},
+
+		(Some("enable"), Some(id)) | (Some("disable"), Some(id)) => {
+			let enable = cmd_args[1] == "enable";
+			match program.find_breakpoint(id) {
+				Some(breakpoint) => { breakpoint.enabled = enable; },
+				None => print_error!("No breakpoint {}", id),
+			}
+		},
+
+		(Some("ignore"), Some(id)) => {
+			let count = cmd_args.get(3).and_then(|n| n.parse::<u32>().ok());
+			match (program.find_breakpoint(id), count) {
+				(Some(breakpoint), Some(count)) => {
+					breakpoint.ignore_count = count;
+					println!("Breakpoint {} will stop after {} more hit{}", id, count, if count == 1 { "" } else { "s" });
+				},
+				(None, _) => print_error!("No breakpoint {}", id),
+				(_, None) => print_error!("Usage: breakpoint ignore [n] [count]"),
+			}
+		},
+
+		(Some("del"), _) | (Some("delete"), _) | (Some("enable"), _) | (Some("disable"), _) | (Some("ignore"), _) => {
+			print_error!("Expected a breakpoint number");
+		},
+
+		_ => {
+			set_breakpoint(program, cmd_args, false);
+		},
+	}
+}
+
+// Splits from-to at the first - outside of brackets that can't be a minus sign, so (end-1) is still a subtraction
+fn split_range(text: &str) -> (&str, Option<&str>) {
+	let mut depth = 0;
+	let mut after_operand = false;
+	for (i, c) in text.char_indices() {
+		match c {
+			'(' | '[' => { depth += 1; after_operand = false; },
+			')' | ']' => { depth -= 1; after_operand = true; },
+			'-' if depth == 0 && after_operand => { return (&text[..i], Some(&text[i + 1..])); },
+			c if c.is_whitespace() => {},
+			c => { after_operand = c.is_ascii_alphanumeric() || matches!(c, '$' | '_' | '.' | '@' | '%'); },
+		}
+	}
+	(text, None)
+}
+
+// watch [r|w|change] [address or from-to] | watch list | watch del [n]
+fn watch_command(program: &mut Program, cmd_args: &[String]) {
+	match cmd_args.get(1).map(|s| s.as_str()) {
+		None | Some("list") => {
+			for (i, watchpoint) in program.watchpoints.iter().enumerate() {
+				println!("{}: {} ${:04x}-${:04x}", i + 1, watchpoint.kind.name(), watchpoint.start, watchpoint.end);
+			}
+		},
+
+		Some("del") | Some("delete") => {
+			match cmd_args.get(2).and_then(|n| n.parse::<usize>().ok()) {
+				Some(n) if n >= 1 && n <= program.watchpoints.len() => {
+					program.watchpoints.remove(n - 1);
+				},
+				_ => {
+					print_error!("No such watchpoint");
+				},
+			}
+		},
+
+		Some(kind) => {
+			let kind = match WatchKind::from_name(kind) {
+				Some(kind) => kind,
+				None => {
+					print_error!("Usage: watch [r|w|change] [address or from-to]");
+					return;
+				},
+			};
+
+			let text = cmd_args[2..].join(" ");
+			let (from, to) = split_range(&text);
+			let range = program.evaluate_address(from).and_then(|start| Ok((start, program.evaluate_address(to.unwrap_or(from))?)));
+			match range {
+				Ok((start, end)) if start <= end => {
+					program.watchpoints.push(Watchpoint{kind, start, end});
+					println!("Watchpoint ({}) set at ${:04x}-${:04x}", kind.name(), start, end);
+				},
+				Ok(_) => {
+					print_error!("Invalid address range");
+				},
+				Err(message) => {
+					print_error!("{}", message);
+				},
+			}
+		},
+	}
+}
+
+// Hex bytes ($a9 or a9) and "quoted text", with ?? or * matching any byte if wildcards are allowed
+fn parse_byte_list(text: &str, wildcards: bool) -> Result<Vec<Option<u8>>, String> {
+	let mut bytes = Vec::new();
+	let mut rest = text.trim_start();
+	while !rest.is_empty() {
+		if let Some(quoted) = rest.strip_prefix('"') {
+			let end = quoted.find('"').ok_or(String::from("Unterminated string"))?;
+			bytes.extend(quoted[..end].bytes().map(Some));
+			rest = quoted[end + 1..].trim_start();
+			continue;
+		}
+
+		let (word, remainder) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+		if wildcards && (word == "??" || word == "*") {
+			bytes.push(None);
+		}
+		else {
+			let byte = u8::from_str_radix(word.trim_start_matches('$'), 16).map_err(|_| format!("Invalid byte \"{}\"", word))?;
+			bytes.push(Some(byte));
+		}
+		rest = remainder.trim_start();
+	}
+
+	Ok(bytes)
+}
+
+// Lengths are decimal unless given as $hex
+fn parse_length(text: &str) -> Option<usize> {
+	match text.strip_prefix('$') {
+		Some(hex) => usize::from_str_radix(hex, 16).ok(),
+		None => text.parse::<usize>().ok(),
+	}
+}
+
+// poke, fill, copy, compare and find, with the usage text shown when the arguments don't parse
+fn memory_command(program: &mut Program, cmd_args: &[String], input: &str) {
+	let address = |i: usize| cmd_args.get(i).and_then(|a| program.symbols.parse_address(a));
+	let rest = |i: usize| raw_args(input, i);
+
+	let usage = match cmd_args[0].as_str() {
+		"poke" => match (address(1), parse_byte_list(rest(2), false)) {
+			(Some(start), Ok(bytes)) if !bytes.is_empty() => {
+				let bytes: Vec<u8> = bytes.into_iter().flatten().collect();
+				program.poke(start, &bytes);
+				println!("Wrote {} bytes at ${:04x}", bytes.len(), start);
+				return;
+			},
+			(_, Err(message)) => message,
+			_ => String::from("Usage: poke [address] [bytes or \"text\"]"),
+		},
+
+		"fill" => match (address(1), address(2), cmd_args.get(3).and_then(|v| u8::from_str_radix(v.trim_start_matches('$'), 16).ok())) {
+			(Some(from), Some(to), Some(value)) if from <= to => {
+				program.fill(from, to, value);
+				println!("Filled ${:04x}-${:04x} with ${:02x}", from, to, value);
+				return;
+			},
+			_ => String::from("Usage: fill [from] [to] [byte]"),
+		},
+
+		"copy" => match (address(1), address(2), cmd_args.get(3).and_then(|l| parse_length(l))) {
+			(Some(source), Some(destination), Some(len)) => {
+				program.copy(source, destination, len);
+				println!("Copied {} bytes from ${:04x} to ${:04x}", len, source, destination);
+				return;
+			},
+			_ => String::from("Usage: copy [source] [destination] [length]"),
+		},
+
+		"compare" => match (address(1), address(2), cmd_args.get(3).and_then(|l| parse_length(l))) {
+			(Some(a), Some(b), Some(len)) => {
+				let differences = program.compare(a, b, len);
+				for (offset, x, y) in &differences {
+					println!("${:04x}: ${:02x}  ${:04x}: ${:02x}", a.wrapping_add(*offset as u16), x, b.wrapping_add(*offset as u16), y);
+				}
+				println!("{} of {} bytes differ", differences.len(), len);
+				return;
+			},
+			_ => String::from("Usage: compare [address] [address] [length]"),
+		},
+
+		_ => match (address(1), address(2), parse_byte_list(rest(3), true)) {
+			(Some(from), Some(to), Ok(pattern)) if from <= to && !pattern.is_empty() => {
+				let matches = program.find(from, to, &pattern);
+				for start in &matches {
+					println!("${:04x}", start);
+				}
+				println!("{} match{}", matches.len(), if matches.len() == 1 { "" } else { "es" });
+				return;
+			},
+			(_, _, Err(message)) => message,
+			_ => String::from("Usage: find [from] [to] [bytes, ?? or \"text\"]"),
+		},
+	};
+
+	print_error!("{}", usage);
+}
+
+fn print_expression(program: &Program, cmd_args: &[String]) {
+	let result = expression::parse(&cmd_args[1..].join(" ")).and_then(|expr| expr.evaluate(program));
+	match result {
+		Ok(value) => println!("${:x} ({})", value, value),
+		Err(message) => print_error!("{}", message),
+	}
+}
+
+// set [register] [expression] | set flags +c -z ...
+fn set_command(program: &mut Program, cmd_args: &[String]) -> bool {
+	let name = match cmd_args.get(1) {
+		Some(name) if cmd_args.len() > 2 => name.to_lowercase(),
+		_ => {
+			print_error!("Usage: set [a|x|y|sp|pc|origin|flags|flag] [value], or set flags +c -z");
+			return false;
+		},
+	};
+
+	let result = if name == "flags" && cmd_args[2].starts_with(['+', '-']) {
+		cmd_args[2..].iter().try_for_each(|change| {
+			let value = match change.chars().next() {
+				Some('+') => true,
+				Some('-') => false,
+				_ => { return Err(format!("Expected +flag or -flag, not \"{}\"", change)); },
+			};
+			change[1..].chars().try_for_each(|flag| program.set_flag(&flag.to_string(), value))
+		})
+	}
+	else {
+		expression::parse(&cmd_args[2..].join(" "))
+			.and_then(|expr| expr.evaluate(program))
+			.and_then(|value| program.set_register(&name, value))
+	};
+
+	match result {
+		Ok(()) => {
+			println!("PC ${:04x}  SP ${:02x}", program.program_counter, program.stack_pointer);
+			debug::print_status(program);
+			true
+		},
+		Err(message) => {
+			print_error!("{}", message);
+			false
+		},
+	}
+}
+
+fn save_command(program: &Program, cmd_args: &[String]) {
+	let filename = match cmd_args.get(1) {
+		Some(filename) => filename,
+		None => {
+			print_error!("Usage: save [filename]");
+			return;
+		},
+	};
+
+	match snapshot::save_snapshot(program, filename) {
+		Ok(()) => println!("Saved snapshot at ${:04x} to {}", program.program_counter, filename),
+		Err(message) => print_error!("{}: {}", filename, message),
+	}
+}
+
+fn restore_command(program: &mut Program, cmd_args: &[String]) -> bool {
+	let filename = match cmd_args.get(1) {
+		Some(filename) => filename,
+		None => {
+			print_error!("Usage: restore [filename]");
+			return false;
+		},
+	};
+
+	match snapshot::restore_snapshot(program, filename) {
+		Ok(()) => {
+			println!("Restored snapshot at ${:04x} after {} cycles", program.program_counter, program.cycles);
+			true
+		},
+		Err(message) => {
+			print_error!("{}: {}", filename, message);
+			false
+		},
+	}
+}
+
+fn rewind(program: &mut Program, cmd_args: &[String]) {
+	let count = match cmd_args.get(1).map(|c| c.parse::<usize>()) {
+		Some(Ok(count)) => count,
+		None => 1,
+		Some(Err(_)) => {
+			print_error!("Invalid instruction count");
+			return;
+		},
+	};
+
+	let mut rewound = 0;
+	while rewound < count && program.reverse_step() {
+		rewound += 1;
+	}
+
+	println!("Rewound {} instruction{}", rewound, if rewound == 1 { "" } else { "s" });
+	if rewound < count {
+		println!("Reached the start of the recorded history");
+	}
+	debug::print_disassembly(program, program.program_counter, 1);
+}
+
+// Steps back until the program is about to execute an instruction with a breakpoint on it
+fn reverse_continue(program: &mut Program) {
+	loop {
+		if !program.reverse_step() {
+			println!("Reached the start of the recorded history");
+			break;
 		}
-		
-		let instr_data = &INSTRUCTION_DATA[&opcode];
-		let addr_func = ADDRESS_FUNCS[&instr_data.amode];
-		addr_func(program);
 
-		debug::print_instruction(program, addr, byte, &opcode, instr_data);
+		let hit = program.breakpoint_at(program.program_counter).unwrap_or_else(|error| {
+			print_error!("{}", error.message);
+			Some(error.id)
+		});
+		if let Some(id) = hit {
+			println!("BREAKPOINT {} at ${:x}", id, program.program_counter);
+			break;
+		}
+	}
+	debug::print_disassembly(program, program.program_counter, 1);
+}
+
+// Starts from the origin with a fresh cycle count, or with `resume` carries on from wherever the program counter is
+fn run_program(program: &mut Program, debug_mode: bool, resume: bool) -> bool {
+	if resume {
+		println!("Resuming program at ${:x}", program.program_counter);
+	}
+	else {
+		println!("Running program from ${:x}", program.origin);
+		program.program_counter = program.origin;
+		program.cycles = 0;
+		program.history.clear();
+		program.call_stack.clear();
+	}
+	program.flag_break = false;
+	let mut step_mode = StepMode::Single;
+	loop {
+		let addr = program.program_counter;
 
-		let contains = program.breakpoints.contains(&addr);
-		if debug_mode && (contains || program.broken) {
-			if contains {
-				println!("BREAKPOINT at ${:x}", addr);
+		let hit = if debug_mode {
+			program.check_breakpoints(addr).unwrap_or_else(|error| {
+				print_error!("{}", error.message);
+				Some(error.id)
+			})
+		}
+		else {
+			None
+		};
+		if debug_mode && (hit.is_some() || program.broken) {
+			if let Some(id) = hit {
+				println!("BREAKPOINT {} at ${:x}", id, addr);
 			}
 			
 			program.broken = true;
-			let mut input = String::new();
+			step_mode = StepMode::Single;
+			debug::print_disassembly(program, addr, 1);
 			loop {
-				input.clear();
-				io::stdout().flush().unwrap();
-				io::stdin().read_line(&mut input).unwrap();
+				// The end of input stops the program, as if stop was typed
+				input::update_symbols(&program.symbols);
+				let input = match input::read_line("") {
+					Some(input) => input,
+					None => { return true; },
+				};
 
 				let cmd_args = get_input_args(&input);
+				if cmd_args.is_empty() {
+					continue;
+				}
 
 				match cmd_args[0].as_str() {
 					"continue" => {
@@ -114,24 +655,165 @@ fn run_program(program: &mut Program, debug_mode: bool) -> bool {
 						break;
 					},
 
+					"next" => {
+						if let Some(mode) = StepMode::over(program) {
+							step_mode = mode;
+							program.broken = false;
+						}
+						break;
+					},
+
+					"finish" => {
+						step_mode = StepMode::Out{stack_pointer: program.stack_pointer};
+						program.broken = false;
+						break;
+					},
+
+					"until" => {
+						match cmd_args.get(1).and_then(|a| program.symbols.parse_address(a)) {
+							Some(target) => {
+								step_mode = StepMode::Until(target);
+								program.broken = false;
+								break;
+							},
+							None => {
+								print_error!("Invalid address");
+							},
+						}
+					},
+
 					"stop" => {
 						return true;
 					}
 
+					"reverse-step" | "rstep" => {
+						if program.reverse_step() {
+							debug::print_disassembly(program, program.program_counter, 1);
+						}
+						else {
+							print_error!("No history to step back through");
+						}
+					},
+
+					"reverse-continue" | "rcontinue" => {
+						reverse_continue(program);
+					},
+
+					"rewind" => {
+						rewind(program, &cmd_args);
+					},
+
+					"history" => {
+						debug::print_history(program, &cmd_args);
+					},
+
+					"profile" => {
+						profile_command(program, &cmd_args);
+					},
+
+					"bt" | "backtrace" => {
+						debug::print_backtrace(program);
+					},
+
+					"stack" => {
+						stack_command(program, &cmd_args);
+					},
+
+					"irq" | "nmi" => {
+						interrupt_command(program, &cmd_args, true);
+					},
+
+					"save" => {
+						save_command(program, &cmd_args);
+					},
+
+					"poke" | "fill" | "copy" | "compare" | "find" => {
+						memory_command(program, &cmd_args, &input);
+					},
+
+					"set" => {
+						if set_command(program, &cmd_args) {
+							debug::print_disassembly(program, program.program_counter, 1);
+						}
+					},
+
+					"restore" => {
+						if restore_command(program, &cmd_args) {
+							debug::print_disassembly(program, program.program_counter, 1);
+						}
+					},
+
 					"memory" | "mem" => {
-						debug::print_memory(&program, &cmd_args);
+						debug::print_memory(program, &cmd_args);
+					},
+
+					"a" | "assemble" => {
+						assemble_interactive(program, &cmd_args);
+						debug::print_disassembly(program, program.program_counter, 1);
+					},
+
+					"print" | "p" => {
+						print_expression(program, &cmd_args);
+					},
+
+					"watch" => {
+						watch_command(program, &cmd_args);
+					},
+
+					"breakpoint" | "bkpt" => {
+						breakpoint_command(program, &cmd_args);
+					},
+
+					"tbreak" => {
+						set_breakpoint(program, &cmd_args, true);
+					},
+
+					"help" => {
+						print_help_debugger();
 					},
 
 					_ => {
-						eprintln!("{}Error:{} Invalid command", con_red!(), con_reset!());
+						print_error!("Invalid command");
 					},
 				}
 			}
 		}
 
-		(instr_data.func)(program, &instr_data.amode);
+		// Reverse stepping at the prompt may have moved the program counter
+		let addr = program.program_counter;
+		let cycles_before = program.cycles;
+		let opcode = match program.step(true) {
+			Ok(opcode) => opcode,
+			Err(byte) => {
+				print_error!("{}", debug::describe_invalid_opcode(byte, addr));
+				return false;
+			},
+		};
 
-		debug::print_status(program);
+		if let Some(profile) = program.profile.as_mut() {
+			profile.record(addr, program.cycles - cycles_before, &program.call_stack, program.cycles);
+		}
+
+		if debug_mode && !program.stack_anomalies.is_empty() {
+			debug::print_stack_anomalies(program);
+		}
+
+		// Wrapping is reported in any run, but only a debug run can stop for it
+		if !program.stack_faults.is_empty() {
+			debug::print_stack_faults(program);
+			if debug_mode {
+				program.broken = true;
+			}
+		}
+
+		if debug_mode && !program.watch_hits.is_empty() {
+			debug::print_watch_hits(program);
+			program.broken = true;
+		}
+
+		if step_mode.is_done(program, &opcode) {
+			program.broken = true;
+		}
 
 		if program.flag_break {
 			println!("BREAK at ${:x}", addr);
@@ -140,19 +822,238 @@ fn run_program(program: &mut Program, debug_mode: bool) -> bool {
 	}
 }
 
+// run, debug and resume with --profile record where the cycles go and report it when the program stops.
+// A resumed run carries on with the profile it already has. --coverage turns coverage on.
+fn run_command(program: &mut Program, cmd_args: &[String], debug_mode: bool, resume: bool) {
+	let covering = cmd_args.iter().any(|a| a == "--coverage");
+	if covering && program.coverage.is_none() {
+		program.coverage = Some(Coverage::new());
+	}
+
+	let profiling = cmd_args.iter().any(|a| a == "--profile");
+	if profiling && (!resume || program.profile.is_none()) {
+		program.profile = Some(Profile::new());
+	}
+	else if !profiling && !resume {
+		program.profile = None;
+	}
+
+	run_program(program, debug_mode, resume);
+
+	if profiling {
+		debug::print_profile(program, &[]);
+	}
+	if let (true, Some(coverage_data)) = (covering, &program.coverage) {
+		print!("\n{}", coverage::summary(program, coverage_data));
+	}
+}
+
+// stack to dump it, or stack guard on|off
+fn stack_command(program: &mut Program, cmd_args: &[String]) {
+	match (cmd_args.get(1).map(|a| a.as_str()), cmd_args.get(2).map(|a| a.as_str())) {
+		(None, _) => debug::print_stack(program),
+		(Some("guard"), Some("on")) => { program.stack_guard = true; },
+		(Some("guard"), Some("off")) => { program.stack_guard = false; },
+		(Some("guard"), None) => println!("The stack guard is {}", if program.stack_guard { "on" } else { "off" }),
+		_ => print_error!("Usage: stack, or stack guard [on|off]"),
+	}
+}
+
+// irq or nmi, entered before the next instruction runs
+fn interrupt_command(program: &mut Program, cmd_args: &[String], debug_mode: bool) {
+	let nmi = cmd_args[0] == "nmi";
+	let name = if nmi { "NMI" } else { "IRQ" };
+	if program.interrupt(nmi) {
+		println!("{} taken, handler at ${:04x}", name, program.program_counter);
+
+		// Pushing the return address and status can wrap the stack too, as in run_program
+		debug::print_stack_faults(program);
+		if debug_mode && !program.stack_faults.is_empty() {
+			program.broken = true;
+		}
+	}
+	else {
+		println!("IRQ is masked by the I flag");
+	}
+}
+
+// coverage on|off|clear, or coverage [text|html|lcov] [filename] to report it, text going to the terminal without a filename
+fn coverage_command(program: &mut Program, cmd_args: &[String]) {
+	let subcommand = cmd_args.get(1).map(|a| a.as_str());
+	match subcommand {
+		Some("on") => {
+			if program.coverage.is_none() {
+				program.coverage = Some(Coverage::new());
+			}
+			return;
+		},
+		Some("off") => {
+			program.coverage = None;
+			return;
+		},
+		_ => {},
+	}
+
+	let coverage_data = match &program.coverage {
+		Some(_) if subcommand == Some("clear") => {
+			program.coverage = Some(Coverage::new());
+			return;
+		},
+		Some(coverage_data) => coverage_data,
+		None => {
+			print_error!("Coverage is off, turn it on with coverage on or run --coverage");
+			return;
+		},
+	};
+
+	let report = match subcommand {
+		None => Ok(coverage::summary(program, coverage_data)),
+		Some("text") => Ok(coverage::text_report(program, coverage_data)),
+		Some("html") => Ok(coverage::html_report(program, coverage_data)),
+		Some("lcov") => coverage::lcov_report(program, coverage_data),
+		Some(_) => Err(String::from("Usage: coverage [on|off|clear] or coverage [text|html|lcov] [filename]")),
+	};
+
+	match (report, cmd_args.get(2)) {
+		(Err(message), _) => print_error!("{}", message),
+		(Ok(text), None) if subcommand.is_none() || subcommand == Some("text") => print!("{}", text),
+		(Ok(_), None) => print_error!("Usage: coverage {} [filename]", subcommand.unwrap_or_default()),
+		(Ok(text), Some(filename)) => match fs::write(filename, text) {
+			Ok(_) => println!("Wrote {}", filename),
+			Err(err) => print_error!("Failed to write {}: {}", filename, err),
+		},
+	}
+}
+
+// profile [count], profile clear to start counting again, or profile folded|trace [filename] to export it
+fn profile_command(program: &mut Program, cmd_args: &[String]) {
+	let subcommand = cmd_args.get(1).map(|a| a.as_str());
+	if !matches!(subcommand, Some("clear") | Some("folded") | Some("trace")) {
+		debug::print_profile(program, cmd_args);
+		return;
+	}
+
+	let profile = match &mut program.profile {
+		Some(profile) => profile,
+		None => {
+			print_error!("Not profiling, start a run with --profile");
+			return;
+		},
+	};
+
+	if subcommand == Some("clear") {
+		*profile = Profile::new();
+		return;
+	}
+
+	let filename = match cmd_args.get(2) {
+		Some(filename) => filename,
+		None => {
+			print_error!("Usage: profile folded|trace [filename]");
+			return;
+		},
+	};
+
+	// Code outside any subroutine is named after the entry point
+	let root = program.symbols.name_for(program.origin).unwrap_or("main").to_string();
+	let text = match subcommand {
+		Some("folded") => profile.folded_stacks(&program.symbols, &root),
+		_ => profile.chrome_trace(&program.symbols, &root, program.cycles),
+	};
+
+	match fs::write(filename, text) {
+		Ok(_) => {
+			println!("Wrote {}", filename);
+			if profile.calls_dropped > 0 && subcommand == Some("trace") {
+				println!("The trace is missing the last {} calls, the run made too many to keep", profile.calls_dropped);
+			}
+		},
+		Err(err) => print_error!("Failed to write {}: {}", filename, err),
+	}
+}
+
 fn print_help() {
-	println!("\n{0}load {1}[filename]    {2}Load a program file
-{0}breakpoint {1}[address]    {2}Set breakpoint at address
-{0}run    {2}Run program
-{0}debug    {2}Run program in debug mode, stopping at breakpoints
-{0}memory {1}[from] [range]    {2}Prints contents of memory at address and [range] addresses afterward
+	println!("\n{0}load {1}[filename] [format]    {2}Load a program file into memory (prg, ihex, srec or asm, picked by extension if not given)
+{0}load {1}[filename] [address]    {2}Load a headerless binary file into memory at address
+{0}asm {1}[filename]    {2}Assemble a source file and load it
+{0}symbols {1}load [filename]    {2}Load labels from a VICE, ca65 .dbg, 64tass or ACME symbol file
+{0}symbols {1}[list] [filter]    {2}List loaded symbols
+{0}symbols clear    {2}Forget all symbols
+{0}breakpoint {1}[address] [if condition]    {2}Set breakpoint at an address, optionally only when condition is true
+{0}tbreak {1}[address] [if condition]    {2}Set a breakpoint that is deleted once it stops the program
+{0}breakpoint {1}[list | del n | del all | enable n | disable n]    {2}Manage breakpoints by number
+{0}breakpoint ignore {1}[n] [count]    {2}Don't stop for the next [count] hits of breakpoint n
+{0}print {1}[expression]    {2}Evaluate an expression over registers (a, x, y, sp, pc), flags.c etc., mem[address] and symbols
+{0}watch {1}[r|w|change] [address or from-to]    {2}Stop in debug mode when memory is read, written or changed
+{0}watch {1}[list | del n]    {2}List or delete watchpoints
+    Breakpoint and watchpoint addresses can be expressions such as message+4. Numbers in them are hex
+    even without a $, so 10+1 is $11, and a name made of hex digits such as ff is $ff unless it's a symbol.
+{0}a {1}[address] [instruction]    {2}Assemble an instruction into memory, then prompt for the next one
+{0}run {1}[--profile] [--coverage]    {2}Run program, --profile reports the hottest instructions and subroutines when it stops
+{0}debug {1}[--profile] [--coverage]    {2}Run program in debug mode, stopping at breakpoints
+{0}profile {1}[count] or clear    {2}Show the profile from a run started with --profile, or reset it
+{0}profile folded {1}[filename]    {2}Export the profile as folded stacks for flame graph tools
+{0}profile trace {1}[filename]    {2}Export the calls as Chrome Trace Event JSON, with cycles as timestamps
+{0}bt    {2}Show the subroutine calls and interrupts the program is in, innermost first
+{0}stack    {2}Dump the stack from SP up to $01ff, marking return addresses and pushed status bytes
+{0}stack guard {1}[on|off]    {2}Stop debug runs when a push or pull wraps the stack pointer around
+{0}irq    {2}Take an IRQ through the vector at $fffe before the next instruction
+{0}nmi    {2}Take an NMI through the vector at $fffa before the next instruction
+{0}coverage {1}[on|off|clear]    {2}Count executed instructions and branch directions, across runs until cleared
+{0}coverage {1}[text|html|lcov] [filename]    {2}Report coverage as annotated disassembly, or lcov if the program was assembled here
+{0}set {1}[a|x|y|sp|pc|origin|flags] [value]    {2}Change a register, or where run starts from
+{0}set flags {1}[+flag] [-flag]    {2}Set or clear flags, e.g. set flags +c -z
+{0}save {1}[filename]    {2}Save registers, memory, breakpoints and the cycle count to a snapshot file
+{0}restore {1}[filename]    {2}Restore a snapshot saved here or from the debugger
+{0}resume {1}[debug] [--profile] [--coverage]    {2}Carry on running from the current program counter, e.g. after restore
+{0}memory {1}[from] [length] or [from]-[to]    {2}Hex dump of memory, -w for words, -b for binary, -n [count] for bytes per line
+{0}poke {1}[address] [bytes or \"text\"]    {2}Write bytes into memory
+{0}fill {1}[from] [to] [byte]    {2}Fill a range of memory with a byte
+{0}copy {1}[source] [destination] [length]    {2}Copy memory, overlapping ranges are fine
+{0}compare {1}[address] [address] [length]    {2}List the bytes that differ between two blocks
+{0}find {1}[from] [to] [bytes or \"text\"]    {2}Search memory, ?? matches any byte
+{0}source {1}[filename]    {2}Run the commands in a file, lines starting with # are comments
+{0}script {1}[filename]    {2}Run a Rhai script that can load, poke, run and hook the program
 {0}gui    {2}Launch GUI (Not yet implemented)
 {0}help    {2}Print this help text
 {0}exit    {2}Exit fe6502\n", con_green!(), con_yellow!(), con_reset!());
 }
 
 fn print_help_debugger() {
-	
+	println!("\n{0}continue    {2}Continue running until the next breakpoint
+{0}step    {2}Execute this instruction and stop at the next one
+{0}next    {2}Like step, but run a whole subroutine call as one step
+{0}finish    {2}Run until the current subroutine returns
+{0}until {1}[address]    {2}Run until the program reaches address
+{0}reverse-step    {2}Undo the last instruction
+{0}reverse-continue    {2}Run backwards to the previous breakpoint
+{0}rewind {1}[count]    {2}Undo the last [count] instructions
+{0}history {1}[count]    {2}List the last [count] instructions with the registers and memory from before each one
+{0}profile {1}[count] or clear    {2}Show the profile so far, if the run was started with --profile
+{0}bt    {2}Show the subroutine calls and interrupts the program is in, innermost first
+{0}stack    {2}Dump the stack from SP up to $01ff, marking return addresses and pushed status bytes
+{0}stack guard {1}[on|off]    {2}Break when a push or pull wraps the stack pointer around
+{0}irq    {2}Take an IRQ through the vector at $fffe, unless the I flag masks it
+{0}nmi    {2}Take an NMI through the vector at $fffa
+{0}set {1}[a|x|y|sp|pc|flags] [value]    {2}Change a register, e.g. set a $ff, set pc loop + 2
+{0}set flags {1}[+flag] [-flag]    {2}Set or clear flags, e.g. set flags +c -z
+{0}save {1}[filename]    {2}Save registers, memory, breakpoints and the cycle count to a snapshot file
+{0}restore {1}[filename]    {2}Restore a snapshot and carry on from where it was saved
+{0}stop    {2}Stop the program
+{0}memory {1}[from] [length] or [from]-[to]    {2}Hex dump of memory, -w for words, -b for binary, -n [count] for bytes per line
+{0}poke {1}[address] [bytes or \"text\"]    {2}Write bytes into memory
+{0}fill {1}[from] [to] [byte]    {2}Fill a range of memory with a byte
+{0}copy {1}[source] [destination] [length]    {2}Copy memory, overlapping ranges are fine
+{0}compare {1}[address] [address] [length]    {2}List the bytes that differ between two blocks
+{0}find {1}[from] [to] [bytes or \"text\"]    {2}Search memory, ?? matches any byte
+{0}a {1}[address] [instruction]    {2}Assemble an instruction into memory, then prompt for the next one
+{0}print {1}[expression]    {2}Evaluate an expression over registers, flags, memory and symbols
+{0}watch {1}[r|w|change] [address or from-to]    {2}Stop when memory is read, written or changed
+{0}breakpoint {1}[address] [if condition]    {2}Set a breakpoint (also list, del, enable, disable, ignore)
+{0}tbreak {1}[address] [if condition]    {2}Set a breakpoint that is deleted once it stops the program
+    Breakpoint and watchpoint addresses can be expressions such as message+4. Numbers in them are hex
+    even without a $, so 10+1 is $11, and a name made of hex digits such as ff is $ff unless it's a symbol.
+{0}help    {2}Print this help text\n", con_green!(), con_yellow!(), con_reset!());
 }
 
 // =======================================================================
@@ -162,16 +1063,95 @@ fn main() {
 
 	// Argument checks
 	let args: Vec<String> = env::args().collect();
+	if args.len() > 1 && args[1] == "asm" {
+		assemble_command(&args[2..]);
+		return;
+	}
+
+	let mut load_format = None;
+	let mut gdb_address = None;
+	let mut dap_mode = false;
+	let mut tui_mode = false;
+	let mut script_file = None;
+	let mut command_files = Vec::new();
 	let mut i = 1;
 	while i < args.len() {
 		match args[i].as_str() {
-			"-l" => { // Load file
-				program = load_program_file(&args[i + 1]);
+			"-l" => { // Load file, can be given more than once
+				if args.len() <= i + 1 || !load_program_file(&mut program, &args[i + 1], load_format, None) {
+					process::exit(1);
+				}
+				i += 1;
+			},
+
+			"-b" => { // Load headerless binary at address
+				let address = args.get(i + 2).and_then(|a| program.symbols.parse_address(a));
+				if address.is_none() || !load_program_file(&mut program, &args[i + 1], None, address) {
+					eprintln!("Usage: -b [filename] [address]");
+					process::exit(1);
+				}
+				i += 2;
+			},
+
+			"-s" => { // Load symbol file
+				if args.len() <= i + 1 || !load_symbols(&mut program, &args[i + 1]) {
+					process::exit(1);
+				}
+				i += 1;
+			},
+
+			"-f" => { // File format for the following -l options
+				load_format = args.get(i + 1).and_then(|f| FileFormat::from_name(f));
+				if load_format.is_none() {
+					print_error!("Unknown file format, expected prg, ihex, srec or asm");
+					process::exit(1);
+				}
+				i += 1;
+			},
+
+			"--gdb" => { // Serve the GDB remote protocol on localhost at :port
+				gdb_address = args.get(i + 1).cloned();
+				if gdb_address.is_none() {
+					eprintln!("Usage: --gdb :[port]");
+					process::exit(1);
+				}
+				i += 1;
+			},
+
+			"--dap" => { // Serve the Debug Adapter Protocol over stdin and stdout
+				dap_mode = true;
+			},
+
+			"--tui" => { // Debug in a full-screen terminal interface instead of the REPL
+				tui_mode = true;
+			},
+
+			"-x" => { // Run the REPL commands in a file before reading stdin, can be given more than once
+				match args.get(i + 1) {
+					Some(filename) => { command_files.push(filename.clone()); },
+					None => {
+						eprintln!("Usage: -x [filename]");
+						process::exit(1);
+					},
+				}
+				i += 1;
+			},
+
+			"--exit-on-error" => { // Exit with status 1 as soon as a command reports an error
+				input::set_exit_on_error(true);
+			},
+
+			"--script" => { // Run a Rhai script once the files are loaded, then exit
+				script_file = args.get(i + 1).cloned();
+				if script_file.is_none() {
+					eprintln!("Usage: --script [filename]");
+					process::exit(1);
+				}
 				i += 1;
 			},
 
 			"-g" => { // Launch with GUI
-				eprintln!("{}Error:{} GUI not yet supported", con_red!(), con_reset!());
+				print_error!("GUI not yet supported");
 				process::exit(1);
 			},
 
@@ -183,40 +1163,181 @@ fn main() {
 		i += 1;
 	}
 
-	let mut input = String::new();
+	if dap_mode {
+		dap::serve(&mut program);
+		return;
+	}
+
+	if let Some(filename) = script_file {
+		if let Err(message) = script::run_script(&mut program, &filename) {
+			print_error!("{}", message);
+			process::exit(1);
+		}
+		return;
+	}
+
+	if tui_mode {
+		if let Err(message) = tui::run(&mut program) {
+			print_error!("{}", message);
+			process::exit(1);
+		}
+		return;
+	}
+
+	if let Some(address) = gdb_address {
+		if let Err(message) = gdb::serve(&mut program, &address) {
+			print_error!("{}", message);
+			process::exit(1);
+		}
+		return;
+	}
+
+	// Sourcing pushes onto a stack, so the first file goes on last to be read first
+	for filename in command_files.iter().rev() {
+		if let Err(message) = input::source_file(filename) {
+			print_error!("{}", message);
+			process::exit(1);
+		}
+	}
+
+	let prompt = format!("{}fe6502{}> ", con_green!(), con_reset!());
 	loop {
-		print!("{}fe6502{}> ", con_green!(), con_reset!());
-		input.clear();
-		io::stdout().flush().unwrap();
-		io::stdin().read_line(&mut input).unwrap();
+		input::update_symbols(&program.symbols);
+		let input = match input::read_line(&prompt) {
+			Some(input) => input,
+			None => { break; },
+		};
 
 		let cmd_args = get_input_args(&input);
+		if cmd_args.is_empty() {
+			continue;
+		}
+
 		match cmd_args[0].as_str() {
+			"load" if cmd_args.len() < 2 => {
+				print_error!("Usage: load [filename] [format or address]");
+			},
+
 			"load" => {
-				program = load_program_file(&cmd_args[1]);
+				let address = cmd_args.get(2).and_then(|a| program.symbols.parse_address(a));
+				let format = cmd_args.get(2).and_then(|f| FileFormat::from_name(f));
+				if cmd_args.len() > 2 && address.is_none() && format.is_none() {
+					print_error!("Expected a load address or a file format (prg, ihex, srec or asm)");
+				}
+				else {
+					load_program_file(&mut program, &cmd_args[1], format, address);
+				}
+			},
+
+			"asm" if cmd_args.len() < 2 => {
+				print_error!("Usage: asm [filename]");
+			},
+
+			"asm" => {
+				load_program_file(&mut program, &cmd_args[1], Some(FileFormat::Assembly), None);
 			},
 
 			"breakpoint" | "bkpt" => {
-				let addr_str = &cmd_args[1];
-				let addr = u16::from_str_radix(addr_str.trim_start_matches('$'), 16).unwrap();
-				program.add_breakpoint(addr);
-				println!("Breakpoint set at {}${:x}{}", con_red!(), addr, con_reset!());
+				breakpoint_command(&mut program, &cmd_args);
+			},
+
+			"tbreak" => {
+				set_breakpoint(&mut program, &cmd_args, true);
+			},
+
+			"symbols" | "sym" => {
+				symbols_command(&mut program, &cmd_args);
 			},
 
 			"run" => {
-				run_program(&mut program, false);
+				run_command(&mut program, &cmd_args, false, false);
+			},
+
+			"resume" => {
+				let debug_mode = cmd_args[1..].iter().any(|a| matches!(a.as_str(), "debug" | "db" | "dbg"));
+				run_command(&mut program, &cmd_args, debug_mode, true);
+			},
+
+			"profile" => {
+				profile_command(&mut program, &cmd_args);
+			},
+
+			"coverage" => {
+				coverage_command(&mut program, &cmd_args);
+			},
+
+			"bt" | "backtrace" => {
+				debug::print_backtrace(&program);
+			},
+
+			"stack" => {
+				stack_command(&mut program, &cmd_args);
+			},
+
+			"irq" | "nmi" => {
+				interrupt_command(&mut program, &cmd_args, false);
+			},
+
+			"save" => {
+				save_command(&program, &cmd_args);
+			},
+
+			"set" => {
+				set_command(&mut program, &cmd_args);
+			},
+
+			"poke" | "fill" | "copy" | "compare" | "find" => {
+				memory_command(&mut program, &cmd_args, &input);
+			},
+
+			"restore" => {
+				restore_command(&mut program, &cmd_args);
+			},
+
+			"source" => {
+				match cmd_args.get(1) {
+					Some(filename) => {
+						if let Err(message) = input::source_file(filename) {
+							print_error!("{}", message);
+						}
+					},
+					None => { print_error!("Usage: source [filename]"); },
+				}
+			},
+
+			"script" => {
+				match cmd_args.get(1) {
+					Some(filename) => {
+						if let Err(message) = script::run_script(&mut program, filename) {
+							print_error!("{}", message);
+						}
+					},
+					None => { print_error!("Usage: script [filename]"); },
+				}
 			},
 
 			"debug" | "db" | "dbg" => {
-				run_program(&mut program, true);
+				run_command(&mut program, &cmd_args, true, false);
 			},
 
 			"memory" | "mem" => {
 				debug::print_memory(&program, &cmd_args);
 			},
 
+			"a" | "assemble" => {
+				assemble_interactive(&mut program, &cmd_args);
+			},
+
+			"print" | "p" => {
+				print_expression(&program, &cmd_args);
+			},
+
+			"watch" => {
+				watch_command(&mut program, &cmd_args);
+			},
+
 			"gui" => {
-				eprintln!("{}Error:{} GUI not yet supported", con_red!(), con_reset!());
+				print_error!("GUI not yet supported");
 			},
 
 			"help" => {
@@ -228,8 +1349,29 @@ fn main() {
 			},
 
 			_ => {
-				eprintln!("{}Error:{} Invalid command", con_red!(), con_reset!());
+				print_error!("Invalid command");
 			}
 		}
 	}
+
+	// An error in the last command still fails the run
+	input::check_exit_on_error();
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn byte_lists() {
+		assert_eq!(parse_byte_list("a9 $01  \"hi\"", false), Ok(vec![Some(0xa9), Some(0x01), Some(b'h'), Some(b'i')]));
+		assert_eq!(parse_byte_list("?? 20 *", true), Ok(vec![None, Some(0x20), None]));
+		assert_eq!(parse_byte_list("??", false), Err(String::from("Invalid byte \"??\"")));
+		assert_eq!(parse_byte_list("\"open", false), Err(String::from("Unterminated string")));
+
+		let input = "  find $1000  $1fff \"a  b\" ";
+		assert_eq!(raw_args(input, 3), "\"a  b\" ");
+		assert_eq!(parse_byte_list(raw_args(input, 3), true).map(|b| b.len()), Ok(4));
+		assert_eq!(raw_args(input, 5), "");
+	}
 }
@@ -2,17 +2,17 @@
 
 use std::collections::HashMap;
 //use std::string::ToString;
-use strum_macros;
 
 use lazy_static::lazy_static;
 
-use crate::num_derive::FromPrimitive;
+use num::FromPrimitive;
+use num_derive::FromPrimitive;
 use crate::addressing::AddressMode;
 use crate::program::Program;
 use crate::instructions;
 
 #[allow(non_camel_case_types)]
-#[derive(FromPrimitive, PartialEq, Eq, Hash, strum_macros::ToString)]
+#[derive(FromPrimitive, PartialEq, Eq, Hash, strum_macros::Display)]
 pub enum Opcode {
 	ADC_imm = 0x69,
 	ADC_zpg = 0x65,
@@ -202,7 +202,7 @@ pub enum Opcode {
 	STA_idy = 0x91,
 
 	STX_zpg = 0x86,
-	STX_zpx = 0x96,
+	STX_zpy = 0x96,
 	STX_abs = 0x8e,
 
 	STY_zpg = 0x84,
@@ -227,217 +227,253 @@ type InstrFunc = fn(&mut Program, &AddressMode) -> ();
 pub struct InstructionData {
 	pub amode: AddressMode,
 	pub func: InstrFunc,
+	// Before page crossing and taken branch penalties
+	pub cycles: u8,
 }
 
 lazy_static! {
 	pub static ref INSTRUCTION_DATA: HashMap<Opcode, InstructionData> = {
 		let mut map = HashMap::new();
 		
-		map.insert(Opcode::ADC_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::ADC as InstrFunc});
-		map.insert(Opcode::ADC_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::ADC as InstrFunc});
-		map.insert(Opcode::ADC_zpx ,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::ADC as InstrFunc});
-		map.insert(Opcode::ADC_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::ADC as InstrFunc});
-		map.insert(Opcode::ADC_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::ADC as InstrFunc});
-		map.insert(Opcode::ADC_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::ADC as InstrFunc});
-		map.insert(Opcode::ADC_idx,  InstructionData{amode: AddressMode::IndirectX, func: instructions::ADC as InstrFunc});
-		map.insert(Opcode::ADC_idy,  InstructionData{amode: AddressMode::IndirectY, func: instructions::ADC as InstrFunc});
+		map.insert(Opcode::ADC_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::ADC as InstrFunc, cycles: 2});
+		map.insert(Opcode::ADC_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::ADC as InstrFunc, cycles: 3});
+		map.insert(Opcode::ADC_zpx ,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::ADC as InstrFunc, cycles: 4});
+		map.insert(Opcode::ADC_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::ADC as InstrFunc, cycles: 4});
+		map.insert(Opcode::ADC_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::ADC as InstrFunc, cycles: 4});
+		map.insert(Opcode::ADC_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::ADC as InstrFunc, cycles: 4});
+		map.insert(Opcode::ADC_idx,  InstructionData{amode: AddressMode::IndirectX, func: instructions::ADC as InstrFunc, cycles: 6});
+		map.insert(Opcode::ADC_idy,  InstructionData{amode: AddressMode::IndirectY, func: instructions::ADC as InstrFunc, cycles: 5});
 
-		map.insert(Opcode::AND_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::AND as InstrFunc});
-		map.insert(Opcode::AND_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::AND as InstrFunc});
-		map.insert(Opcode::AND_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::AND as InstrFunc});
-		map.insert(Opcode::AND_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::AND as InstrFunc});
-		map.insert(Opcode::AND_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::AND as InstrFunc});
-		map.insert(Opcode::AND_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::AND as InstrFunc});
-		map.insert(Opcode::AND_idx,  InstructionData{amode: AddressMode::IndirectX, func: instructions::AND as InstrFunc});
-		map.insert(Opcode::AND_idy,  InstructionData{amode: AddressMode::IndirectY, func: instructions::AND as InstrFunc});
+		map.insert(Opcode::AND_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::AND as InstrFunc, cycles: 2});
+		map.insert(Opcode::AND_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::AND as InstrFunc, cycles: 3});
+		map.insert(Opcode::AND_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::AND as InstrFunc, cycles: 4});
+		map.insert(Opcode::AND_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::AND as InstrFunc, cycles: 4});
+		map.insert(Opcode::AND_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::AND as InstrFunc, cycles: 4});
+		map.insert(Opcode::AND_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::AND as InstrFunc, cycles: 4});
+		map.insert(Opcode::AND_idx,  InstructionData{amode: AddressMode::IndirectX, func: instructions::AND as InstrFunc, cycles: 6});
+		map.insert(Opcode::AND_idy,  InstructionData{amode: AddressMode::IndirectY, func: instructions::AND as InstrFunc, cycles: 5});
 
-		map.insert(Opcode::ASL_acc,  InstructionData{amode: AddressMode::Accumulator, func: instructions::ASL as InstrFunc});
-		map.insert(Opcode::ASL_zpg , InstructionData{amode: AddressMode::Zeropage, func: instructions::ASL as InstrFunc});
-		map.insert(Opcode::ASL_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::ASL as InstrFunc});
-		map.insert(Opcode::ASL_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::ASL as InstrFunc});
-		map.insert(Opcode::ASL_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::ASL as InstrFunc});
+		map.insert(Opcode::ASL_acc,  InstructionData{amode: AddressMode::Accumulator, func: instructions::ASL as InstrFunc, cycles: 2});
+		map.insert(Opcode::ASL_zpg , InstructionData{amode: AddressMode::Zeropage, func: instructions::ASL as InstrFunc, cycles: 5});
+		map.insert(Opcode::ASL_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::ASL as InstrFunc, cycles: 6});
+		map.insert(Opcode::ASL_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::ASL as InstrFunc, cycles: 6});
+		map.insert(Opcode::ASL_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::ASL as InstrFunc, cycles: 7});
 
-		map.insert(Opcode::BCC_rel,  InstructionData{amode: AddressMode::Relative, func: instructions::BCC as InstrFunc});
+		map.insert(Opcode::BCC_rel,  InstructionData{amode: AddressMode::Relative, func: instructions::BCC as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::BCS_rel,  InstructionData{amode: AddressMode::Relative, func: instructions::BCS as InstrFunc});
+		map.insert(Opcode::BCS_rel,  InstructionData{amode: AddressMode::Relative, func: instructions::BCS as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::BEQ_rel,  InstructionData{amode: AddressMode::Relative, func: instructions::BEQ as InstrFunc});
+		map.insert(Opcode::BEQ_rel,  InstructionData{amode: AddressMode::Relative, func: instructions::BEQ as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::BIT_zpg , InstructionData{amode: AddressMode::Zeropage, func: instructions::BIT as InstrFunc});
-		map.insert(Opcode::BIT_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::BIT as InstrFunc});
+		map.insert(Opcode::BIT_zpg , InstructionData{amode: AddressMode::Zeropage, func: instructions::BIT as InstrFunc, cycles: 3});
+		map.insert(Opcode::BIT_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::BIT as InstrFunc, cycles: 4});
 
-		map.insert(Opcode::BMI_rel,  InstructionData{amode: AddressMode::Relative, func: instructions::BMI as InstrFunc});
+		map.insert(Opcode::BMI_rel,  InstructionData{amode: AddressMode::Relative, func: instructions::BMI as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::BNE_rel,  InstructionData{amode: AddressMode::Relative, func: instructions::BNE as InstrFunc});
+		map.insert(Opcode::BNE_rel,  InstructionData{amode: AddressMode::Relative, func: instructions::BNE as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::BPL_rel,  InstructionData{amode: AddressMode::Relative, func: instructions::BPL as InstrFunc});
+		map.insert(Opcode::BPL_rel,  InstructionData{amode: AddressMode::Relative, func: instructions::BPL as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::BRK_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::BRK as InstrFunc});
+		map.insert(Opcode::BRK_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::BRK as InstrFunc, cycles: 7});
 
-		map.insert(Opcode::BVC_rel,  InstructionData{amode: AddressMode::Relative, func: instructions::BVC as InstrFunc});
+		map.insert(Opcode::BVC_rel,  InstructionData{amode: AddressMode::Relative, func: instructions::BVC as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::BVS_rel,  InstructionData{amode: AddressMode::Relative, func: instructions::BVS as InstrFunc});
+		map.insert(Opcode::BVS_rel,  InstructionData{amode: AddressMode::Relative, func: instructions::BVS as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::CLC_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::CLC as InstrFunc});
+		map.insert(Opcode::CLC_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::CLC as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::CLD_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::CLD as InstrFunc});
+		map.insert(Opcode::CLD_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::CLD as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::CLI_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::CLI as InstrFunc});
+		map.insert(Opcode::CLI_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::CLI as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::CLV_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::CLV as InstrFunc});
+		map.insert(Opcode::CLV_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::CLV as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::CMP_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::CMP as InstrFunc});
-		map.insert(Opcode::CMP_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::CMP as InstrFunc});
-		map.insert(Opcode::CMP_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::CMP as InstrFunc});
-		map.insert(Opcode::CMP_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::CMP as InstrFunc});
-		map.insert(Opcode::CMP_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::CMP as InstrFunc});
-		map.insert(Opcode::CMP_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::CMP as InstrFunc});
-		map.insert(Opcode::CMP_idx,  InstructionData{amode: AddressMode::IndirectX, func: instructions::CMP as InstrFunc});
-		map.insert(Opcode::CMP_idy,  InstructionData{amode: AddressMode::IndirectY, func: instructions::CMP as InstrFunc});
+		map.insert(Opcode::CMP_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::CMP as InstrFunc, cycles: 2});
+		map.insert(Opcode::CMP_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::CMP as InstrFunc, cycles: 3});
+		map.insert(Opcode::CMP_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::CMP as InstrFunc, cycles: 4});
+		map.insert(Opcode::CMP_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::CMP as InstrFunc, cycles: 4});
+		map.insert(Opcode::CMP_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::CMP as InstrFunc, cycles: 4});
+		map.insert(Opcode::CMP_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::CMP as InstrFunc, cycles: 4});
+		map.insert(Opcode::CMP_idx,  InstructionData{amode: AddressMode::IndirectX, func: instructions::CMP as InstrFunc, cycles: 6});
+		map.insert(Opcode::CMP_idy,  InstructionData{amode: AddressMode::IndirectY, func: instructions::CMP as InstrFunc, cycles: 5});
 
-		map.insert(Opcode::CPX_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::CPX as InstrFunc});
-		map.insert(Opcode::CPX_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::CPX as InstrFunc});
-		map.insert(Opcode::CPX_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::CPX as InstrFunc});
+		map.insert(Opcode::CPX_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::CPX as InstrFunc, cycles: 2});
+		map.insert(Opcode::CPX_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::CPX as InstrFunc, cycles: 3});
+		map.insert(Opcode::CPX_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::CPX as InstrFunc, cycles: 4});
 
-		map.insert(Opcode::CPY_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::CPY as InstrFunc});
-		map.insert(Opcode::CPY_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::CPY as InstrFunc});
-		map.insert(Opcode::CPY_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::CPY as InstrFunc});
+		map.insert(Opcode::CPY_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::CPY as InstrFunc, cycles: 2});
+		map.insert(Opcode::CPY_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::CPY as InstrFunc, cycles: 3});
+		map.insert(Opcode::CPY_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::CPY as InstrFunc, cycles: 4});
 		
-		map.insert(Opcode::DEC_zpg , InstructionData{amode: AddressMode::Zeropage, func: instructions::DEC as InstrFunc});
-		map.insert(Opcode::DEC_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::DEC as InstrFunc});
-		map.insert(Opcode::DEC_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::DEC as InstrFunc});
-		map.insert(Opcode::DEC_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::DEC as InstrFunc});
+		map.insert(Opcode::DEC_zpg , InstructionData{amode: AddressMode::Zeropage, func: instructions::DEC as InstrFunc, cycles: 5});
+		map.insert(Opcode::DEC_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::DEC as InstrFunc, cycles: 6});
+		map.insert(Opcode::DEC_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::DEC as InstrFunc, cycles: 6});
+		map.insert(Opcode::DEC_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::DEC as InstrFunc, cycles: 7});
+
+		map.insert(Opcode::DEX_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::DEX as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::DEX_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::DEX as InstrFunc});
+		map.insert(Opcode::DEY_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::DEY as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::DEY_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::DEY as InstrFunc});
+		map.insert(Opcode::EOR_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::EOR as InstrFunc, cycles: 2});
+		map.insert(Opcode::EOR_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::EOR as InstrFunc, cycles: 3});
+		map.insert(Opcode::EOR_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::EOR as InstrFunc, cycles: 4});
+		map.insert(Opcode::EOR_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::EOR as InstrFunc, cycles: 4});
+		map.insert(Opcode::EOR_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::EOR as InstrFunc, cycles: 4});
+		map.insert(Opcode::EOR_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::EOR as InstrFunc, cycles: 4});
+		map.insert(Opcode::EOR_idx,  InstructionData{amode: AddressMode::IndirectX, func: instructions::EOR as InstrFunc, cycles: 6});
+		map.insert(Opcode::EOR_idy,  InstructionData{amode: AddressMode::IndirectY, func: instructions::EOR as InstrFunc, cycles: 5});
+
+		map.insert(Opcode::INC_zpg , InstructionData{amode: AddressMode::Zeropage, func: instructions::INC as InstrFunc, cycles: 5});
+		map.insert(Opcode::INC_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::INC as InstrFunc, cycles: 6});
+		map.insert(Opcode::INC_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::INC as InstrFunc, cycles: 6});
+		map.insert(Opcode::INC_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::INC as InstrFunc, cycles: 7});
+
+		map.insert(Opcode::INX_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::INX as InstrFunc, cycles: 2});
+
+		map.insert(Opcode::INY_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::INY as InstrFunc, cycles: 2});
+
+		map.insert(Opcode::JMP_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::JMP as InstrFunc, cycles: 3});
+		map.insert(Opcode::JMP_ind ,  InstructionData{amode: AddressMode::Indirect, func: instructions::JMP as InstrFunc, cycles: 5});
+
+		map.insert(Opcode::JSR_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::JSR as InstrFunc, cycles: 6});
+
+		map.insert(Opcode::LDA_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::LDA as InstrFunc, cycles: 2});
+		map.insert(Opcode::LDA_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::LDA as InstrFunc, cycles: 3});
+		map.insert(Opcode::LDA_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::LDA as InstrFunc, cycles: 4});
+		map.insert(Opcode::LDA_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::LDA as InstrFunc, cycles: 4});
+		map.insert(Opcode::LDA_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::LDA as InstrFunc, cycles: 4});
+		map.insert(Opcode::LDA_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::LDA as InstrFunc, cycles: 4});
+		map.insert(Opcode::LDA_idx,  InstructionData{amode: AddressMode::IndirectX, func: instructions::LDA as InstrFunc, cycles: 6});
+		map.insert(Opcode::LDA_idy,  InstructionData{amode: AddressMode::IndirectY, func: instructions::LDA as InstrFunc, cycles: 5});
+
+		map.insert(Opcode::LDX_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::LDX as InstrFunc, cycles: 2});
+		map.insert(Opcode::LDX_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::LDX as InstrFunc, cycles: 3});
+		map.insert(Opcode::LDX_zpy,  InstructionData{amode: AddressMode::ZeropageY, func: instructions::LDX as InstrFunc, cycles: 4});
+		map.insert(Opcode::LDX_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::LDX as InstrFunc, cycles: 4});
+		map.insert(Opcode::LDX_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::LDX as InstrFunc, cycles: 4});
+
+		map.insert(Opcode::LDY_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::LDY as InstrFunc, cycles: 2});
+		map.insert(Opcode::LDY_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::LDY as InstrFunc, cycles: 3});
+		map.insert(Opcode::LDY_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::LDY as InstrFunc, cycles: 4});
+		map.insert(Opcode::LDY_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::LDY as InstrFunc, cycles: 4});
+		map.insert(Opcode::LDY_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::LDY as InstrFunc, cycles: 4});
+
+		map.insert(Opcode::LSR_acc,  InstructionData{amode: AddressMode::Accumulator, func: instructions::LSR as InstrFunc, cycles: 2});
+		map.insert(Opcode::LSR_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::LSR as InstrFunc, cycles: 5});
+		map.insert(Opcode::LSR_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::LSR as InstrFunc, cycles: 6});
+		map.insert(Opcode::LSR_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::LSR as InstrFunc, cycles: 6});
+		map.insert(Opcode::LSR_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::LSR as InstrFunc, cycles: 7});
+
+		map.insert(Opcode::NOP_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::NOP as InstrFunc, cycles: 2});
+
+		map.insert(Opcode::ORA_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::ORA as InstrFunc, cycles: 2});
+		map.insert(Opcode::ORA_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::ORA as InstrFunc, cycles: 3});
+		map.insert(Opcode::ORA_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::ORA as InstrFunc, cycles: 4});
+		map.insert(Opcode::ORA_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::ORA as InstrFunc, cycles: 4});
+		map.insert(Opcode::ORA_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::ORA as InstrFunc, cycles: 4});
+		map.insert(Opcode::ORA_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::ORA as InstrFunc, cycles: 4});
+		map.insert(Opcode::ORA_idx,  InstructionData{amode: AddressMode::IndirectX, func: instructions::ORA as InstrFunc, cycles: 6});
+		map.insert(Opcode::ORA_idy,  InstructionData{amode: AddressMode::IndirectY, func: instructions::ORA as InstrFunc, cycles: 5});
+
+		map.insert(Opcode::PHA_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::PHA as InstrFunc, cycles: 3});
+
+		map.insert(Opcode::PHP_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::PHP as InstrFunc, cycles: 3});
+
+		map.insert(Opcode::PLA_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::PLA as InstrFunc, cycles: 4});
+
+		map.insert(Opcode::PLP_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::PLP as InstrFunc, cycles: 4});
+
+		map.insert(Opcode::ROL_acc,  InstructionData{amode: AddressMode::Accumulator, func: instructions::ROL as InstrFunc, cycles: 2});
+		map.insert(Opcode::ROL_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::ROL as InstrFunc, cycles: 5});
+		map.insert(Opcode::ROL_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::ROL as InstrFunc, cycles: 6});
+		map.insert(Opcode::ROL_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::ROL as InstrFunc, cycles: 6});
+		map.insert(Opcode::ROL_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::ROL as InstrFunc, cycles: 7});
+
+		map.insert(Opcode::ROR_acc,  InstructionData{amode: AddressMode::Accumulator, func: instructions::ROR as InstrFunc, cycles: 2});
+		map.insert(Opcode::ROR_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::ROR as InstrFunc, cycles: 5});
+		map.insert(Opcode::ROR_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::ROR as InstrFunc, cycles: 6});
+		map.insert(Opcode::ROR_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::ROR as InstrFunc, cycles: 6});
+		map.insert(Opcode::ROR_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::ROR as InstrFunc, cycles: 7});
+
+		map.insert(Opcode::RTI_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::RTI as InstrFunc, cycles: 6});
+
+		map.insert(Opcode::RTS_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::RTS as InstrFunc, cycles: 6});
+
+		map.insert(Opcode::SBC_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::SBC as InstrFunc, cycles: 2});
+		map.insert(Opcode::SBC_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::SBC as InstrFunc, cycles: 3});
+		map.insert(Opcode::SBC_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::SBC as InstrFunc, cycles: 4});
+		map.insert(Opcode::SBC_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::SBC as InstrFunc, cycles: 4});
+		map.insert(Opcode::SBC_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::SBC as InstrFunc, cycles: 4});
+		map.insert(Opcode::SBC_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::SBC as InstrFunc, cycles: 4});
+		map.insert(Opcode::SBC_idx,  InstructionData{amode: AddressMode::IndirectX, func: instructions::SBC as InstrFunc, cycles: 6});
+		map.insert(Opcode::SBC_idy,  InstructionData{amode: AddressMode::IndirectY, func: instructions::SBC as InstrFunc, cycles: 5});
+
+		map.insert(Opcode::SEC_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::SEC as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::EOR_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::EOR as InstrFunc});
-		map.insert(Opcode::EOR_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::EOR as InstrFunc});
-		map.insert(Opcode::EOR_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::EOR as InstrFunc});
-		map.insert(Opcode::EOR_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::EOR as InstrFunc});
-		map.insert(Opcode::EOR_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::EOR as InstrFunc});
-		map.insert(Opcode::EOR_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::EOR as InstrFunc});
-
-		map.insert(Opcode::INC_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::INC as InstrFunc});
-		map.insert(Opcode::INC_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::INC as InstrFunc});
-		map.insert(Opcode::INC_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::INC as InstrFunc});
-
-		map.insert(Opcode::INX_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::INX as InstrFunc});
+		map.insert(Opcode::SED_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::SED as InstrFunc, cycles: 2});
+
+		map.insert(Opcode::SEI_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::SEI as InstrFunc, cycles: 2});
+
+		map.insert(Opcode::STA_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::STA as InstrFunc, cycles: 3});
+		map.insert(Opcode::STA_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::STA as InstrFunc, cycles: 4});
+		map.insert(Opcode::STA_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::STA as InstrFunc, cycles: 4});
+		map.insert(Opcode::STA_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::STA as InstrFunc, cycles: 5});
+		map.insert(Opcode::STA_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::STA as InstrFunc, cycles: 5});
+		map.insert(Opcode::STA_idx,  InstructionData{amode: AddressMode::IndirectX, func: instructions::STA as InstrFunc, cycles: 6});
+		map.insert(Opcode::STA_idy,  InstructionData{amode: AddressMode::IndirectY, func: instructions::STA as InstrFunc, cycles: 6});
 
-		map.insert(Opcode::INY_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::INY as InstrFunc});
+		map.insert(Opcode::STX_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::STX as InstrFunc, cycles: 3});
+		map.insert(Opcode::STX_zpy,  InstructionData{amode: AddressMode::ZeropageY, func: instructions::STX as InstrFunc, cycles: 4});
+		map.insert(Opcode::STX_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::STX as InstrFunc, cycles: 4});
 
-		map.insert(Opcode::JMP_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::JMP as InstrFunc});
-		map.insert(Opcode::JMP_ind ,  InstructionData{amode: AddressMode::Indirect, func: instructions::JMP as InstrFunc});
+		map.insert(Opcode::STY_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::STY as InstrFunc, cycles: 3});
+		map.insert(Opcode::STY_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::STY as InstrFunc, cycles: 4});
+		map.insert(Opcode::STY_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::STY as InstrFunc, cycles: 4});
 
-		map.insert(Opcode::JSR_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::JSR as InstrFunc});
-
-		map.insert(Opcode::LDA_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::LDA as InstrFunc});
-		map.insert(Opcode::LDA_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::LDA as InstrFunc});
-		map.insert(Opcode::LDA_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::LDA as InstrFunc});
-		map.insert(Opcode::LDA_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::LDA as InstrFunc});
-		map.insert(Opcode::LDA_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::LDA as InstrFunc});
-		map.insert(Opcode::LDA_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::LDA as InstrFunc});
-		map.insert(Opcode::LDA_idx,  InstructionData{amode: AddressMode::IndirectX, func: instructions::LDA as InstrFunc});
-		map.insert(Opcode::LDA_idy,  InstructionData{amode: AddressMode::IndirectY, func: instructions::LDA as InstrFunc});
-
-		map.insert(Opcode::LDX_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::LDX as InstrFunc});
-		map.insert(Opcode::LDX_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::LDX as InstrFunc});
-		map.insert(Opcode::LDX_zpy,  InstructionData{amode: AddressMode::ZeropageY, func: instructions::LDX as InstrFunc});
-		map.insert(Opcode::LDX_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::LDX as InstrFunc});
-		map.insert(Opcode::LDX_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::LDX as InstrFunc});
-
-		map.insert(Opcode::LDY_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::LDY as InstrFunc});
-		map.insert(Opcode::LDY_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::LDY as InstrFunc});
-		map.insert(Opcode::LDY_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::LDY as InstrFunc});
-		map.insert(Opcode::LDY_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::LDY as InstrFunc});
-		map.insert(Opcode::LDY_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::LDY as InstrFunc});
-
-		map.insert(Opcode::LSR_acc,  InstructionData{amode: AddressMode::Accumulator, func: instructions::LSR as InstrFunc});
-		map.insert(Opcode::LSR_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::LSR as InstrFunc});
-		map.insert(Opcode::LSR_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::LSR as InstrFunc});
-		map.insert(Opcode::LSR_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::LSR as InstrFunc});
-		map.insert(Opcode::LSR_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::LSR as InstrFunc});
-
-		map.insert(Opcode::NOP_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::NOP as InstrFunc});
-
-		map.insert(Opcode::ORA_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::ORA as InstrFunc});
-		map.insert(Opcode::ORA_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::ORA as InstrFunc});
-		map.insert(Opcode::ORA_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::ORA as InstrFunc});
-		map.insert(Opcode::ORA_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::ORA as InstrFunc});
-		map.insert(Opcode::ORA_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::ORA as InstrFunc});
-		map.insert(Opcode::ORA_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::ORA as InstrFunc});
-		map.insert(Opcode::ORA_idx,  InstructionData{amode: AddressMode::IndirectX, func: instructions::ORA as InstrFunc});
-		map.insert(Opcode::ORA_idy,  InstructionData{amode: AddressMode::IndirectY, func: instructions::ORA as InstrFunc});
-
-		map.insert(Opcode::PHA_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::PHA as InstrFunc});
-
-		map.insert(Opcode::PHP_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::PHP as InstrFunc});
-
-		map.insert(Opcode::PLA_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::PLA as InstrFunc});
-
-		map.insert(Opcode::PLP_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::PLP as InstrFunc});
-
-		map.insert(Opcode::ROL_acc,  InstructionData{amode: AddressMode::Accumulator, func: instructions::ROL as InstrFunc});
-		map.insert(Opcode::ROL_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::ROL as InstrFunc});
-		map.insert(Opcode::ROL_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::ROL as InstrFunc});
-		map.insert(Opcode::ROL_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::ROL as InstrFunc});
-		map.insert(Opcode::ROL_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::ROL as InstrFunc});
-
-		map.insert(Opcode::ROR_acc,  InstructionData{amode: AddressMode::Accumulator, func: instructions::ROR as InstrFunc});
-		map.insert(Opcode::ROR_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::ROR as InstrFunc});
-		map.insert(Opcode::ROR_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::ROR as InstrFunc});
-		map.insert(Opcode::ROR_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::ROR as InstrFunc});
-		map.insert(Opcode::ROR_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::ROR as InstrFunc});
-
-		map.insert(Opcode::RTI_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::RTI as InstrFunc});
-
-		map.insert(Opcode::RTS_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::RTS as InstrFunc});
-
-		map.insert(Opcode::SBC_imm ,  InstructionData{amode: AddressMode::Immediate, func: instructions::SBC as InstrFunc});
-		map.insert(Opcode::SBC_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::SBC as InstrFunc});
-		map.insert(Opcode::SBC_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::SBC as InstrFunc});
-		map.insert(Opcode::SBC_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::SBC as InstrFunc});
-		map.insert(Opcode::SBC_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::SBC as InstrFunc});
-		map.insert(Opcode::SBC_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::SBC as InstrFunc});
-		map.insert(Opcode::SBC_idx,  InstructionData{amode: AddressMode::IndirectX, func: instructions::SBC as InstrFunc});
-		map.insert(Opcode::SBC_idy,  InstructionData{amode: AddressMode::IndirectY, func: instructions::SBC as InstrFunc});
-
-		map.insert(Opcode::SEC_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::SEC as InstrFunc});
-
-		map.insert(Opcode::SED_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::SED as InstrFunc});
-
-		map.insert(Opcode::SEI_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::SEI as InstrFunc});
+		map.insert(Opcode::TAX_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::TAX as InstrFunc, cycles: 2});
+		
+		map.insert(Opcode::TAY_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::TAY as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::STA_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::STA as InstrFunc});
-		map.insert(Opcode::STA_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::STA as InstrFunc});
-		map.insert(Opcode::STA_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::STA as InstrFunc});
-		map.insert(Opcode::STA_abx,  InstructionData{amode: AddressMode::AbsoluteX, func: instructions::STA as InstrFunc});
-		map.insert(Opcode::STA_aby,  InstructionData{amode: AddressMode::AbsoluteY, func: instructions::STA as InstrFunc});
-		map.insert(Opcode::STA_idx,  InstructionData{amode: AddressMode::IndirectX, func: instructions::STA as InstrFunc});
-		map.insert(Opcode::STA_idy,  InstructionData{amode: AddressMode::IndirectY, func: instructions::STA as InstrFunc});
+		map.insert(Opcode::TSX_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::TSX as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::STX_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::STX as InstrFunc});
-		map.insert(Opcode::STX_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::STX as InstrFunc});
-		map.insert(Opcode::STX_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::STX as InstrFunc});
+		map.insert(Opcode::TXA_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::TXA as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::STY_zpg ,  InstructionData{amode: AddressMode::Zeropage, func: instructions::STY as InstrFunc});
-		map.insert(Opcode::STY_zpx,  InstructionData{amode: AddressMode::ZeropageX, func: instructions::STY as InstrFunc});
-		map.insert(Opcode::STY_abs,  InstructionData{amode: AddressMode::Absolute, func: instructions::STY as InstrFunc});
+		map.insert(Opcode::TXS_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::TXS as InstrFunc, cycles: 2});
 
-		map.insert(Opcode::TAX_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::TAX as InstrFunc});
+		map.insert(Opcode::TYA_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::TYA as InstrFunc, cycles: 2});
 		
-		map.insert(Opcode::TAY_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::TAY as InstrFunc});
+		map
+	};
+}
 
-		map.insert(Opcode::TSX_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::TSX as InstrFunc});
 
-		map.insert(Opcode::TXA_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::TXA as InstrFunc});
+lazy_static! {
+	pub static ref OPCODE_LOOKUP: HashMap<(String, AddressMode), u8> = {
+		let mut map = HashMap::new();
 
-		map.insert(Opcode::TXS_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::TXS as InstrFunc});
+		for byte in 0..=255u8 {
+			if let Some(opcode) = Opcode::from_u8(byte) {
+				if let Some(data) = INSTRUCTION_DATA.get(&opcode) {
+					map.insert((opcode.mnemonic(), data.amode), byte);
+				}
+			}
+		}
 
-		map.insert(Opcode::TYA_imp,  InstructionData{amode: AddressMode::Implied, func: instructions::TYA as InstrFunc});
-		
 		map
 	};
 }
 
+impl Opcode {
+	pub fn mnemonic(&self) -> String {
+		self.to_string()[0..3].to_string()
+	}
+}
+
+pub fn find_opcode(mnemonic: &str, amode: AddressMode) -> Option<u8> {
+	OPCODE_LOOKUP.get(&(mnemonic.to_uppercase(), amode)).copied()
+}
+
+pub fn is_mnemonic(name: &str) -> bool {
+	let upper = name.to_uppercase();
+	OPCODE_LOOKUP.keys().any(|(mnemonic, _)| *mnemonic == upper)
+}
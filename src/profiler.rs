@@ -0,0 +1,217 @@
+// profiler.rs
+
+use std::collections::HashMap;
+
+use serde_json::json;
+
+use crate::callstack::Frame;
+use crate::symbols::SymbolTable;
+
+// Long runs make a lot of calls, past this many the trace stops growing
+const MAX_TRACE_CALLS: usize = 1_000_000;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AddressCounts {
+	pub executions: u64,
+	pub cycles: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SubroutineCounts {
+	pub calls: u64,
+	// Cycles spent in the subroutine's own instructions
+	pub self_cycles: u64,
+	// Cycles from entering the subroutine until it returned, including everything it called
+	pub total_cycles: u64,
+}
+
+// One finished call, for the trace timeline
+#[derive(Clone, Copy, Debug)]
+struct Call {
+	subroutine: u16,
+	entered: u64,
+	returned: u64,
+	depth: usize,
+}
+
+pub struct Profile {
+	pub addresses: HashMap<u16, AddressCounts>,
+	subroutines: HashMap<u16, SubroutineCounts>,
+	// Calls that haven't returned yet, with the cycle count when each was entered. Kept in step with the call stack.
+	open_calls: Vec<(u16, u64)>,
+	// Self cycles for each chain of subroutines, outermost first, as flame graphs want them
+	stacks: HashMap<Vec<u16>, u64>,
+	path: Vec<u16>,
+	calls: Vec<Call>,
+	pub calls_dropped: u64,
+	// The cycle count when profiling started
+	started: Option<u64>,
+	pub top_level_cycles: u64,
+	pub instructions: u64,
+	pub cycles: u64,
+}
+
+impl Profile {
+	pub fn new() -> Self {
+		Profile{
+			addresses: HashMap::new(),
+			subroutines: HashMap::new(),
+			open_calls: Vec::new(),
+			stacks: HashMap::new(),
+			path: Vec::new(),
+			calls: Vec::new(),
+			calls_dropped: 0,
+			started: None,
+			top_level_cycles: 0,
+			instructions: 0,
+			cycles: 0,
+		}
+	}
+
+	// Called after each instruction, with the call stack as the instruction left it and the cycle count after it
+	pub fn record(&mut self, address: u16, cycles: u64, call_stack: &[Frame], now: u64) {
+		let counts = self.addresses.entry(address).or_default();
+		counts.executions += 1;
+		counts.cycles += cycles;
+		self.instructions += 1;
+		self.cycles += cycles;
+		self.started.get_or_insert(now - cycles);
+
+		match self.stacks.get_mut(self.path.as_slice()) {
+			Some(stack_cycles) => { *stack_cycles += cycles; },
+			None => { self.stacks.insert(self.path.clone(), cycles); },
+		}
+
+		// The instruction belongs to whichever call was innermost before it ran, so a JSR counts for its caller
+		// and an RTS for the subroutine it returns from
+		match self.open_calls.last() {
+			Some((subroutine, _)) => { self.subroutines.entry(*subroutine).or_default().self_cycles += cycles; },
+			None => { self.top_level_cycles += cycles; },
+		}
+
+		while self.open_calls.len() > call_stack.len() {
+			if let Some((subroutine, entered)) = self.open_calls.pop() {
+				self.subroutines.entry(subroutine).or_default().total_cycles += now - entered;
+				self.path.pop();
+				self.push_call(Call{subroutine, entered, returned: now, depth: self.open_calls.len()});
+			}
+		}
+
+		for frame in &call_stack[self.open_calls.len()..] {
+			self.subroutines.entry(frame.subroutine).or_default().calls += 1;
+			self.open_calls.push((frame.subroutine, now));
+			self.path.push(frame.subroutine);
+		}
+	}
+
+	fn push_call(&mut self, call: Call) {
+		if self.calls.len() < MAX_TRACE_CALLS {
+			self.calls.push(call);
+		}
+		else {
+			self.calls_dropped += 1;
+		}
+	}
+
+	// Calls still running count up to the cycle count given
+	pub fn subroutines(&self, now: u64) -> HashMap<u16, SubroutineCounts> {
+		let mut subroutines = self.subroutines.clone();
+		for (subroutine, entered) in &self.open_calls {
+			subroutines.entry(*subroutine).or_default().total_cycles += now.saturating_sub(*entered);
+		}
+		subroutines
+	}
+
+	// Folded stacks, one "root;outer;inner cycles" line per chain of calls, for flamegraph.pl, inferno and speedscope
+	pub fn folded_stacks(&self, symbols: &SymbolTable, root: &str) -> String {
+		let mut lines: Vec<String> = self.stacks.iter().filter(|(_, cycles)| **cycles > 0).map(|(path, cycles)| {
+			let mut names = vec![root.to_string()];
+			names.extend(path.iter().map(|address| symbols.format_address(*address, 4)));
+			format!("{} {}", names.join(";"), cycles)
+		}).collect();
+		lines.sort();
+		lines.join("\n") + "\n"
+	}
+
+	// Chrome Trace Event JSON, for chrome://tracing and Perfetto. Timestamps are emulated cycles, which the
+	// viewers show as microseconds. Calls still running end at the cycle count given.
+	pub fn chrome_trace(&self, symbols: &SymbolTable, root: &str, now: u64) -> String {
+		let started = self.started.unwrap_or(now);
+		let open = self.open_calls.iter().enumerate().map(|(depth, (subroutine, entered))| Call{subroutine: *subroutine, entered: *entered, returned: now, depth});
+
+		let mut events = vec![json!({
+			"name": root, "cat": "run", "ph": "X", "ts": started, "dur": now.saturating_sub(started), "pid": 1, "tid": 1,
+		})];
+		for call in self.calls.iter().copied().chain(open) {
+			events.push(json!({
+				"name": symbols.format_address(call.subroutine, 4),
+				"cat": "subroutine",
+				"ph": "X",
+				"ts": call.entered,
+				"dur": call.returned - call.entered,
+				"pid": 1,
+				"tid": 1,
+				"args": {"address": format!("${:04x}", call.subroutine), "depth": call.depth + 1},
+			}));
+		}
+
+		json!({"traceEvents": events, "otherData": {"clock": "6502 cycles"}}).to_string()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::program::Program;
+
+	// main: jsr sub / jsr sub / brk, sub: jsr leaf / rts, leaf: nop / rts
+	fn profile_calls() -> (Program, Profile) {
+		let mut program = Program::new();
+		program.poke(0x0600, &[0x20, 0x10, 0x06, 0x20, 0x10, 0x06, 0x00]);
+		program.poke(0x0610, &[0x20, 0x20, 0x06, 0x60]);
+		program.poke(0x0620, &[0xea, 0x60]);
+		program.symbols.insert("sub", 0x0610);
+		program.symbols.insert("leaf", 0x0620);
+		program.program_counter = 0x0600;
+
+		let mut profile = Profile::new();
+		while !program.flag_break {
+			let (address, cycles_before) = (program.program_counter, program.cycles);
+			program.step(false).unwrap();
+			profile.record(address, program.cycles - cycles_before, &program.call_stack, program.cycles);
+		}
+		(program, profile)
+	}
+
+	#[test]
+	fn counts() {
+		let (program, profile) = profile_calls();
+		assert_eq!((profile.instructions, profile.cycles, profile.top_level_cycles), (11, 59, 19));
+		assert_eq!(profile.addresses[&0x0620].executions, 2);
+		assert_eq!(profile.addresses[&0x0600].cycles, 6);
+
+		let subroutines = profile.subroutines(program.cycles);
+		let counts = |address: u16| subroutines.get(&address).map(|s| (s.calls, s.self_cycles, s.total_cycles));
+		assert_eq!(counts(0x0610), Some((2, 24, 40)));
+		assert_eq!(counts(0x0620), Some((2, 16, 16)));
+	}
+
+	#[test]
+	fn folded_stacks() {
+		let (program, profile) = profile_calls();
+		assert_eq!(profile.folded_stacks(&program.symbols, "main"), "main 19\nmain;sub 24\nmain;sub;leaf 16\n");
+		assert_eq!(Profile::new().folded_stacks(&program.symbols, "main"), "\n");
+	}
+
+	#[test]
+	fn chrome_trace() {
+		let (program, profile) = profile_calls();
+		let trace: serde_json::Value = serde_json::from_str(&profile.chrome_trace(&program.symbols, "main", program.cycles)).unwrap();
+		let events: Vec<(&str, u64, u64, u64)> = trace["traceEvents"].as_array().unwrap().iter()
+			.map(|e| (e["name"].as_str().unwrap(), e["ts"].as_u64().unwrap(), e["dur"].as_u64().unwrap(), e["args"]["depth"].as_u64().unwrap_or(0)))
+			.collect();
+		assert_eq!(events[..5], [("main", 0, 59, 0), ("leaf", 12, 8, 2), ("sub", 6, 20, 1), ("leaf", 38, 8, 2), ("sub", 32, 20, 1)]);
+	}
+
+}
@@ -1,5 +1,21 @@
 // program.rs
 
+use crate::addressing::{AddressMode, ADDRESS_FUNCS};
+use crate::assembler;
+use crate::breakpoints::{Breakpoint, Condition, ConditionError, Watchpoint, WatchHit, WatchKind};
+use crate::callstack::{Frame, FrameKind, StackAnomaly, StackFault, StackWriter};
+use crate::coverage::Coverage;
+use crate::debug;
+use crate::expression::{self, Resolver};
+use crate::instructions;
+use crate::history::{History, Registers, Step, HISTORY_LENGTH};
+use crate::loader::{self, FileFormat, LoadedImage};
+use crate::opcodes::{Opcode, INSTRUCTION_DATA};
+use crate::profiler::Profile;
+use crate::symbols::{SourceMap, SymbolTable};
+
+use num::FromPrimitive;
+
 pub struct Program {
 	pub program_counter: u16,
 	pub reg_a: u8,
@@ -19,14 +35,76 @@ pub struct Program {
 	pub abs_address: u16,
 	pub rel_address: i8,
 	pub ind_address: u16,
+	pub page_crossed: bool,
+
+	pub cycles: u64,
 
 	pub origin: u16,
 	pub memory: Vec<u8>,
 
-	pub breakpoints: Vec<u16>,
+	pub breakpoints: Vec<Breakpoint>,
+	pub next_breakpoint_id: u32,
 	pub broken: bool,
+
+	pub watchpoints: Vec<Watchpoint>,
+	pub watch_hits: Vec<WatchHit>,
+	pub instruction_address: u16,
+
+	pub symbols: SymbolTable,
+	pub source: Option<SourceMap>,
+
+	pub history: History,
+	pub pending_writes: Vec<(u16, u8, u8)>,
+	pub pending_stack_writers: Vec<(u8, Option<StackWriter>)>,
+
+	pub call_stack: Vec<Frame>,
+	// What the last instruction did to the stack that the call stack couldn't follow
+	pub stack_anomalies: Vec<StackAnomaly>,
+	// The instruction that pushed each byte of page 1, for annotating the stack
+	pub stack_writers: Vec<Option<StackWriter>>,
+	// When set, pushes and pulls that wrap the stack pointer are recorded in stack_faults
+	pub stack_guard: bool,
+	pub stack_faults: Vec<StackFault>,
+
+	// Recorded by run_program when a run is started with --profile
+	pub profile: Option<Profile>,
+	// Recorded by every instruction while coverage is on, across runs until it's cleared
+	pub coverage: Option<Coverage>,
+}
+
+
+// Where a multi-instruction step (next, finish, until) should stop, breakpoints still stop it earlier
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StepMode {
+	Single,
+	Over{return_address: u16, stack_pointer: u8},
+	Out{stack_pointer: u8},
+	Until(u16),
 }
 
+impl StepMode {
+	// Stepping over anything but a JSR is just a single step
+	pub fn over(program: &Program) -> Option<Self> {
+		let pc = program.program_counter;
+		if program.get_memory(pc) == Opcode::JSR_abs as u8 {
+			Some(StepMode::Over{return_address: pc.wrapping_add(3), stack_pointer: program.stack_pointer})
+		}
+		else {
+			None
+		}
+	}
+
+	// Checked after each instruction, with the opcode that just ran
+	pub fn is_done(&self, program: &Program, opcode: &Opcode) -> bool {
+		let pc = program.program_counter;
+		match *self {
+			StepMode::Single => false,
+			StepMode::Over{return_address, stack_pointer} => pc == return_address && program.stack_pointer >= stack_pointer,
+			StepMode::Out{stack_pointer} => matches!(opcode, Opcode::RTS_imp | Opcode::RTI_imp) && program.stack_pointer > stack_pointer,
+			StepMode::Until(target) => pc == target,
+		}
+	}
+}
 
 impl Program {
 	pub fn new() -> Self {
@@ -41,6 +119,9 @@ impl Program {
 			abs_address: 0,
 			rel_address: 0,
 			ind_address: 0,
+			page_crossed: false,
+
+			cycles: 0,
 
 			flag_negative: false,
 			flag_overflow: false,
@@ -51,37 +132,816 @@ impl Program {
 			flag_break: false,
 
 			origin: 0,
-			memory: vec![0; std::u16::MAX as usize + 1],
+			memory: vec![0; u16::MAX as usize + 1],
 
 			breakpoints: Vec::new(),
+			next_breakpoint_id: 1,
 			broken: false,
+
+			watchpoints: Vec::new(),
+			watch_hits: Vec::new(),
+			instruction_address: 0,
+
+			symbols: SymbolTable::new(),
+			source: None,
+
+			history: History::new(HISTORY_LENGTH),
+			pending_writes: Vec::new(),
+			pending_stack_writers: Vec::new(),
+
+			call_stack: Vec::new(),
+			stack_anomalies: Vec::new(),
+			stack_writers: vec![None; 256],
+			stack_guard: false,
+			stack_faults: Vec::new(),
+
+			profile: None,
+			coverage: None,
+		}
+	}
+
+	// Executes the instruction at the program counter, printing it and the registers afterward if `trace` is set.
+	// An invalid opcode comes back as the error, with the program counter left on it.
+	pub fn step(&mut self, trace: bool) -> Result<Opcode, u8> {
+		let addr = self.program_counter;
+		let byte = self.get_memory(addr);
+		let opcode: Opcode = FromPrimitive::from_u8(byte).ok_or(byte)?;
+
+		self.instruction_address = addr;
+		self.watch_hits.clear();
+		self.stack_anomalies.clear();
+		self.stack_faults.clear();
+		self.pending_writes.clear();
+		self.pending_stack_writers.clear();
+		let registers = self.registers();
+		let cycles = self.cycles;
+		self.advance_counter();
+
+		let instr_data = &INSTRUCTION_DATA[&opcode];
+		let addr_func = ADDRESS_FUNCS[&instr_data.amode];
+		self.page_crossed = false;
+		addr_func(self);
+
+		// The addressing functions always fetch the operand, but stores and jumps never actually read it
+		let mnemonic = opcode.mnemonic();
+		if matches!(mnemonic.as_str(), "STA" | "STX" | "STY" | "JMP" | "JSR") {
+			let target = self.abs_address;
+			self.watch_hits.retain(|hit| hit.kind != WatchKind::Read || hit.address != target);
+		}
+
+		if trace {
+			debug::print_instruction(self, addr, byte, &opcode, instr_data);
+		}
+
+		(instr_data.func)(self, &instr_data.amode);
+
+		// A frame is gone once the stack pointer is back above where it was before the JSR, however that happened
+		let mut popped_frames = Vec::new();
+		while self.call_stack.last().map(|f| self.stack_pointer >= f.stack_pointer).unwrap_or(false) {
+			popped_frames.push(self.call_stack.pop().unwrap());
+		}
+
+		self.check_returns(addr, &mnemonic, &popped_frames);
+
+		// BRK is a software interrupt, its return address skips the padding byte after it
+		let pushed_frame = match opcode {
+			Opcode::JSR_abs => Some((FrameKind::Call, addr.wrapping_add(3))),
+			Opcode::BRK_imp => Some((FrameKind::Interrupt, addr.wrapping_add(2))),
+			_ => None,
+		};
+		if let Some((kind, return_address)) = pushed_frame {
+			self.call_stack.push(Frame{kind, call_site: addr, subroutine: self.program_counter, return_address, stack_pointer: registers.sp});
+		}
+		let pushed_frame = pushed_frame.is_some();
+
+		let writes = std::mem::take(&mut self.pending_writes);
+		let stack_writers = std::mem::take(&mut self.pending_stack_writers);
+		self.history.push(Step{registers, cycles, writes, stack_writers, pushed_frame, popped_frames});
+
+		// Indexed reads take an extra cycle to fix up the high byte when they cross a page
+		self.cycles += instr_data.cycles as u64;
+		if self.page_crossed && matches!(mnemonic.as_str(), "ADC" | "AND" | "CMP" | "EOR" | "LDA" | "LDX" | "LDY" | "ORA" | "SBC") {
+			self.cycles += 1;
+		}
+
+		let branch_taken = (instr_data.amode == AddressMode::Relative).then(|| self.program_counter != addr.wrapping_add(2));
+		if let Some(coverage) = self.coverage.as_mut() {
+			coverage.record(addr, branch_taken);
+		}
+
+		if trace {
+			debug::print_status(self);
+		}
+
+		Ok(opcode)
+	}
+
+	// Notes where the call stack and the real stack disagree, popped_frames being innermost first
+	fn check_returns(&mut self, address: u16, mnemonic: &str, popped_frames: &[Frame]) {
+		let target = self.program_counter;
+		let mut discarded = popped_frames;
+		if matches!(mnemonic, "RTS" | "RTI") {
+			match popped_frames.first() {
+				None => { self.stack_anomalies.push(StackAnomaly::UnmatchedReturn{address, target}); },
+				Some(frame) if frame.return_address != target => {
+					self.stack_anomalies.push(StackAnomaly::Redirected{frame: *frame, address, target});
+				},
+				_ => {},
+			}
+			discarded = popped_frames.get(1..).unwrap_or(&[]);
+		}
+
+		for frame in discarded {
+			self.stack_anomalies.push(StackAnomaly::Discarded{frame: *frame, address});
+		}
+	}
+
+	// Takes an IRQ or NMI before the next instruction, returning false if an IRQ is masked by the I flag
+	pub fn interrupt(&mut self, nmi: bool) -> bool {
+		if !nmi && self.flag_interrupt {
+			return false;
 		}
+
+		self.pending_writes.clear();
+		self.pending_stack_writers.clear();
+		self.stack_faults.clear();
+		let registers = self.registers();
+
+		// Faults and watch hits from the pushes belong to the instruction that was interrupted
+		self.instruction_address = registers.pc;
+		instructions::enter_interrupt(self, if nmi { 0xfffa } else { 0xfffe });
+		for i in 0..3u8 {
+			self.set_stack_writer(registers.sp.wrapping_sub(i), Some(StackWriter::Interrupt));
+		}
+		self.call_stack.push(Frame{
+			kind: FrameKind::Interrupt,
+			call_site: registers.pc,
+			subroutine: self.program_counter,
+			return_address: registers.pc,
+			stack_pointer: registers.sp,
+		});
+
+		let writes = std::mem::take(&mut self.pending_writes);
+		let stack_writers = std::mem::take(&mut self.pending_stack_writers);
+		self.history.push(Step{registers, cycles: self.cycles, writes, stack_writers, pushed_frame: true, popped_frames: Vec::new()});
+		self.cycles += 7;
+		true
 	}
 
 	pub fn get_memory(&self, address: u16) -> u8 {
 		self.memory[address as usize]
 	}
 
+	// Reads made by instructions go through here so watchpoints see them, get_memory is for inspecting
+	pub fn read_memory(&mut self, address: u16) -> u8 {
+		let value = self.memory[address as usize];
+		if !self.watchpoints.is_empty() {
+			self.check_watchpoints(WatchKind::Read, address, value, value);
+		}
+		value
+	}
+
 	pub fn set_memory(&mut self, address: u16, value: u8) {
+		let old_value = self.memory[address as usize];
+		self.pending_writes.push((address, old_value, value));
+		if !self.watchpoints.is_empty() {
+			self.check_watchpoints(WatchKind::Write, address, old_value, value);
+		}
 		self.memory[address as usize] = value;
 	}
 
+	// Loads a .prg, hex or S-record file, a headerless binary at `address`, or assembles a source file so its lines
+	// can be mapped to addresses. Without a format it's picked by extension. Every front end loads through here:
+	// the image is merged into memory without firing watchpoints, and its entry point, if it has one, becomes the
+	// origin and program counter. Nothing is printed, the image comes back for the front end to report.
+	pub fn load(&mut self, filename: &str, format: Option<FileFormat>, address: Option<u16>) -> Result<LoadedImage, String> {
+		let format = format.unwrap_or_else(|| FileFormat::from_extension(filename));
+		let image = match (address, format) {
+			(Some(address), _) => loader::load_binary(filename, address).map_err(|e| format!("{}: {}", filename, e))?,
+			(None, FileFormat::Assembly) => {
+				let source = std::fs::read_to_string(filename).map_err(|e| format!("Failed to read {}: {}", filename, e))?;
+				let assembly = assembler::assemble(&source).map_err(|errors| {
+					errors.iter().map(|e| format!("{}:{}: {}", filename, e.line, e.message)).collect::<Vec<String>>().join("\n")
+				})?;
+
+				for (name, address) in &assembly.symbols {
+					self.symbols.insert(name, *address);
+				}
+				self.source = Some(SourceMap{file: filename.to_string(), lines: assembly.lines});
+				assembly.image
+			},
+			(None, format) => loader::load_file(filename, format).map_err(|e| format!("{}: {}", filename, e))?,
+		};
+
+		for segment in &image.segments {
+			self.poke(segment.address, &segment.data);
+		}
+		if let Some(entry) = image.entry {
+			self.origin = entry;
+			self.program_counter = entry;
+		}
+		Ok(image)
+	}
+
+	// The monitor-style memory commands below write memory directly, so watchpoints don't fire for them.
+	// Addresses wrap around at $ffff.
+
+	pub fn poke(&mut self, address: u16, bytes: &[u8]) {
+		for (i, byte) in bytes.iter().enumerate() {
+			self.memory[address.wrapping_add(i as u16) as usize] = *byte;
+		}
+	}
+
+	// Both ends are included
+	pub fn fill(&mut self, from: u16, to: u16, value: u8) {
+		let mut address = from;
+		loop {
+			self.memory[address as usize] = value;
+			if address == to {
+				break;
+			}
+			address = address.wrapping_add(1);
+		}
+	}
+
+	// Overlapping ranges copy as if through a buffer
+	pub fn copy(&mut self, source: u16, destination: u16, len: usize) {
+		let bytes: Vec<u8> = (0..len).map(|i| self.get_memory(source.wrapping_add(i as u16))).collect();
+		self.poke(destination, &bytes);
+	}
+
+	// Each difference as (offset, byte at a, byte at b)
+	pub fn compare(&self, a: u16, b: u16, len: usize) -> Vec<(usize, u8, u8)> {
+		(0..len)
+			.map(|i| (i, self.get_memory(a.wrapping_add(i as u16)), self.get_memory(b.wrapping_add(i as u16))))
+			.filter(|(_, x, y)| x != y)
+			.collect()
+	}
+
+	// Start addresses of every match between from and to inclusive, None in the pattern matches any byte
+	pub fn find(&self, from: u16, to: u16, pattern: &[Option<u8>]) -> Vec<u16> {
+		if pattern.is_empty() {
+			return Vec::new();
+		}
+
+		let count = to.wrapping_sub(from) as usize + 1;
+		(0..count)
+			.map(|i| from.wrapping_add(i as u16))
+			.filter(|start| pattern.iter().enumerate().all(|(j, byte)| {
+				byte.map(|b| self.get_memory(start.wrapping_add(j as u16)) == b).unwrap_or(true)
+			}))
+			.collect()
+	}
+
+	fn check_watchpoints(&mut self, kind: WatchKind, address: u16, old_value: u8, new_value: u8) {
+		if let Some(watchpoint) = self.watchpoints.iter().find(|w| w.matches(kind, address, old_value, new_value)) {
+			self.watch_hits.push(WatchHit{kind: watchpoint.kind, address, old_value, new_value, pc: self.instruction_address});
+		}
+	}
+
 	pub fn advance_counter(&mut self) {
-		self.program_counter += 1;
+		self.program_counter = self.program_counter.wrapping_add(1);
 	}
 
 	pub fn stack_push(&mut self, value: u8) {
-		self.memory[self.stack_pointer as usize] = value;
+		if self.stack_guard && self.stack_pointer == 0x00 {
+			self.stack_faults.push(StackFault::Overflow{address: self.instruction_address});
+		}
+		self.set_memory(0x100 | self.stack_pointer as u16, value);
+		self.set_stack_writer(self.stack_pointer, Some(StackWriter::Instruction(self.instruction_address)));
 		self.stack_pointer = self.stack_pointer.wrapping_sub(1);
 	}
 
+	// Keeps the old writer for the history, so stepping back can put it back
+	fn set_stack_writer(&mut self, slot: u8, writer: Option<StackWriter>) {
+		self.pending_stack_writers.push((slot, self.stack_writers[slot as usize]));
+		self.stack_writers[slot as usize] = writer;
+	}
+
 	pub fn stack_pull(&mut self) -> u8 {
-		let ptr = self.stack_pointer;
+		if self.stack_guard && self.stack_pointer == 0xff {
+			self.stack_faults.push(StackFault::Underflow{address: self.instruction_address});
+		}
 		self.stack_pointer = self.stack_pointer.wrapping_add(1);
-		self.memory[ptr as usize]
+		self.read_memory(0x100 | self.stack_pointer as u16)
+	}
+
+	pub fn get_status(&self) -> u8 {
+		let mut result = 0u8;
+		result |= self.flag_carry as u8;
+		result |= (self.flag_zero as u8) << 1;
+		result |= (self.flag_interrupt as u8) << 2;
+		result |= (self.flag_decimal as u8) << 3;
+		result |= (self.flag_break as u8) << 4;
+		result |= (self.flag_overflow as u8) << 6;
+		result |= (self.flag_negative as u8) << 7;
+		result
+	}
+
+	pub fn set_status(&mut self, status: u8) {
+		self.flag_carry = (status & 1) == 1;
+		self.flag_zero = ((status >> 1) & 1) == 1;
+		self.flag_interrupt = ((status >> 2) & 1) == 1;
+		self.flag_decimal = ((status >> 3) & 1) == 1;
+		self.flag_break = ((status >> 4) & 1) == 1;
+		self.flag_overflow = ((status >> 6) & 1) == 1;
+		self.flag_negative = ((status >> 7) & 1) == 1;
+	}
+
+	pub fn registers(&self) -> Registers {
+		Registers{
+			pc: self.program_counter,
+			a: self.reg_a,
+			x: self.reg_x,
+			y: self.reg_y,
+			sp: self.stack_pointer,
+			status: self.get_status(),
+		}
+	}
+
+	pub fn set_registers(&mut self, registers: &Registers) {
+		self.program_counter = registers.pc;
+		self.reg_a = registers.a;
+		self.reg_x = registers.x;
+		self.reg_y = registers.y;
+		self.stack_pointer = registers.sp;
+		self.set_status(registers.status);
+	}
+
+	// Sets a register, the whole status byte or a single flag (flags.c, or just c) by the names expressions use
+	pub fn set_register(&mut self, name: &str, value: i64) -> Result<(), String> {
+		let name = name.to_lowercase();
+		let limit = match name.as_str() {
+			"pc" | "origin" => 0xffff,
+			"a" | "x" | "y" | "sp" | "p" | "status" | "flags" => 0xff,
+			flag if self.flag_mut(flag.trim_start_matches("flags.")).is_some() => 1,
+			_ => { return Err(format!("Unknown register or flag \"{}\"", name)); },
+		};
+		if !(0..=limit).contains(&value) {
+			let value = if value < 0 { value.to_string() } else { format!("${:x}", value) };
+			return Err(format!("{} doesn't fit in {}", value, name));
+		}
+
+		match name.as_str() {
+			"a" => self.reg_a = value as u8,
+			"x" => self.reg_x = value as u8,
+			"y" => self.reg_y = value as u8,
+			"sp" => self.stack_pointer = value as u8,
+			"pc" => self.program_counter = value as u16,
+			"origin" => self.origin = value as u16,
+			"p" | "status" | "flags" => self.set_status(value as u8),
+			flag => { self.set_flag(flag.trim_start_matches("flags."), value != 0)?; },
+		}
+		Ok(())
 	}
 
-	pub fn add_breakpoint(&mut self, breakpoint: u16) {
-		self.breakpoints.push(breakpoint);
+	pub fn set_flag(&mut self, name: &str, value: bool) -> Result<(), String> {
+		let flag = self.flag_mut(name).ok_or(format!("Unknown register or flag \"{}\"", name))?;
+		*flag = value;
+		Ok(())
 	}
+
+	fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+		match name.to_lowercase().as_str() {
+			"n" => Some(&mut self.flag_negative),
+			"v" => Some(&mut self.flag_overflow),
+			"b" => Some(&mut self.flag_break),
+			"d" => Some(&mut self.flag_decimal),
+			"i" => Some(&mut self.flag_interrupt),
+			"z" => Some(&mut self.flag_zero),
+			"c" => Some(&mut self.flag_carry),
+			_ => None,
+		}
+	}
+
+	// Undoes the last recorded instruction, false if the history has run out
+	pub fn reverse_step(&mut self) -> bool {
+		match self.history.pop() {
+			Some(step) => {
+				for (address, old_value, _) in step.writes.iter().rev() {
+					self.memory[*address as usize] = *old_value;
+				}
+				for (slot, writer) in step.stack_writers.iter().rev() {
+					self.stack_writers[*slot as usize] = *writer;
+				}
+				if step.pushed_frame {
+					self.call_stack.pop();
+				}
+				self.call_stack.extend(step.popped_frames.iter().rev());
+				self.set_registers(&step.registers);
+				self.cycles = step.cycles;
+				true
+			},
+			None => false,
+		}
+	}
+
+	// A breakpoint or watchpoint address, which can be an expression such as message+4. Bare numbers are
+	// hex wherever they appear, so 10 and 10+0 are the same address.
+	pub fn evaluate_address(&self, text: &str) -> Result<u16, String> {
+		if text.trim().is_empty() {
+			return Err(String::from("Expected an address"));
+		}
+
+		let value = expression::parse_address(text)?.evaluate(&AddressResolver(self))?;
+		if !(0..=0xffff).contains(&value) {
+			return Err(format!("Address ${:x} is out of range", value));
+		}
+		Ok(value as u16)
+	}
+
+	// Whether an enabled breakpoint would stop at the address, without counting it as a hit
+	pub fn breakpoint_at(&self, address: u16) -> Result<Option<u32>, ConditionError> {
+		for breakpoint in self.breakpoints.iter().filter(|b| b.address == address) {
+			match breakpoint.is_triggered(self) {
+				Ok(true) => { return Ok(Some(breakpoint.id)); },
+				Ok(false) => {},
+				Err(message) => { return Err(ConditionError{id: breakpoint.id, message}); },
+			}
+		}
+		Ok(None)
+	}
+
+	// Returns the new breakpoint's number, or the existing one's if the same breakpoint is already set
+	pub fn add_breakpoint(&mut self, address: u16, condition: Option<Condition>, temporary: bool) -> (u32, bool) {
+		let condition_text = condition.as_ref().map(|c| c.text.as_str());
+		if let Some(existing) = self.breakpoints.iter().find(|b| b.address == address && b.condition.as_ref().map(|c| c.text.as_str()) == condition_text) {
+			return (existing.id, false);
+		}
+
+		let id = self.next_breakpoint_id;
+		self.next_breakpoint_id += 1;
+		self.breakpoints.push(Breakpoint::new(id, address, condition, temporary));
+		(id, true)
+	}
+
+	pub fn find_breakpoint(&mut self, id: u32) -> Option<&mut Breakpoint> {
+		self.breakpoints.iter_mut().find(|b| b.id == id)
+	}
+
+	pub fn remove_breakpoint(&mut self, id: u32) -> bool {
+		let count = self.breakpoints.len();
+		self.breakpoints.retain(|b| b.id != id);
+		self.breakpoints.len() != count
+	}
+
+	// Counts hits for every breakpoint triggered at the address, returning the one to stop at, if any. A condition
+	// that can't be evaluated counts as true and always stops the program, with the error to show.
+	pub fn check_breakpoints(&mut self, address: u16) -> Result<Option<u32>, ConditionError> {
+		let mut error = None;
+		let mut triggered = Vec::new();
+		for (i, breakpoint) in self.breakpoints.iter().enumerate().filter(|(_, b)| b.address == address) {
+			match breakpoint.is_triggered(self) {
+				Ok(true) => { triggered.push(i); },
+				Ok(false) => {},
+				Err(message) => {
+					triggered.push(i);
+					error.get_or_insert(ConditionError{id: breakpoint.id, message});
+				},
+			}
+		}
+
+		let mut stop = None;
+		for i in triggered {
+			let breakpoint = &mut self.breakpoints[i];
+			breakpoint.hits += 1;
+			if breakpoint.ignore_count > 0 {
+				breakpoint.ignore_count -= 1;
+			}
+			else if stop.is_none() {
+				stop = Some(breakpoint.id);
+			}
+		}
+
+		// One-shot breakpoints go away once they've stopped the program
+		let stop = stop.or(error.as_ref().map(|e| e.id));
+		if let Some(id) = stop {
+			if self.breakpoints.iter().any(|b| b.id == id && b.temporary) {
+				self.remove_breakpoint(id);
+			}
+		}
+
+		match error {
+			Some(error) => Err(ConditionError{id: stop.unwrap_or(error.id), message: error.message}),
+			None => Ok(stop),
+		}
+	}
+}
+
+// Symbols first, then names made of hex digits, then registers, so a is $a as it is for SymbolTable::parse_address
+struct AddressResolver<'a>(&'a Program);
+
+impl Resolver for AddressResolver<'_> {
+	fn resolve(&self, name: &str) -> Option<i64> {
+		self.0.symbols.lookup(name)
+			.map(|a| a as i64)
+			.or_else(|| i64::from_str_radix(name, 16).ok())
+			.or_else(|| self.0.resolve(name))
+	}
+
+	fn memory(&self, address: u16) -> Option<u8> {
+		self.0.memory(address)
+	}
+}
+
+// Registers, flags and symbols for debugger expressions
+impl Resolver for Program {
+	fn resolve(&self, name: &str) -> Option<i64> {
+		let value = match name.to_lowercase().as_str() {
+			"a" => self.reg_a as i64,
+			"x" => self.reg_x as i64,
+			"y" => self.reg_y as i64,
+			"sp" => self.stack_pointer as i64,
+			"pc" => self.program_counter as i64,
+			"cycles" => self.cycles as i64,
+			"p" | "status" | "flags" => self.get_status() as i64,
+			"flags.n" => self.flag_negative as i64,
+			"flags.v" => self.flag_overflow as i64,
+			"flags.b" => self.flag_break as i64,
+			"flags.d" => self.flag_decimal as i64,
+			"flags.i" => self.flag_interrupt as i64,
+			"flags.z" => self.flag_zero as i64,
+			"flags.c" => self.flag_carry as i64,
+			_ => { return self.symbols.lookup(name).map(|a| a as i64); },
+		};
+
+		Some(value)
+	}
+
+	fn memory(&self, address: u16) -> Option<u8> {
+		Some(self.get_memory(address))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::env;
+	use std::fs;
+
+	#[test]
+	fn load_merges_into_memory() {
+		let path = env::temp_dir().join(format!("fe6502-load-{}.s", std::process::id()));
+		fs::write(&path, "\t.org $1000\nstart:\tnop\n\t.org $1004\n\trts\n").unwrap();
+
+		let mut program = Program::new();
+		program.fill(0x1000, 0x1010, 0xff);
+		program.watchpoints.push(Watchpoint{kind: WatchKind::Write, start: 0x1000, end: 0x1010});
+		let image = program.load(path.to_str().unwrap(), None, None);
+		fs::remove_file(&path).ok();
+
+		assert_eq!(image.map(|i| i.segments.len()), Ok(2));
+		assert_eq!(program.memory[0x1000..0x1006], [0xea, 0xff, 0xff, 0xff, 0x60, 0xff]);
+		assert_eq!((program.origin, program.program_counter), (0x1000, 0x1000));
+		assert_eq!(program.symbols.lookup("start"), Some(0x1000));
+		assert!(program.pending_writes.is_empty());
+		assert!(program.watch_hits.is_empty());
+	}
+
+	#[test]
+	fn ignore_counts_and_temporary_breakpoints() {
+		let mut program = Program::new();
+		let (id, added) = program.add_breakpoint(0x0600, None, false);
+		assert!(added);
+		assert_eq!(program.add_breakpoint(0x0600, None, false), (id, false));
+
+		program.find_breakpoint(id).unwrap().ignore_count = 2;
+		assert_eq!(program.check_breakpoints(0x0600).ok(), Some(None));
+		assert_eq!(program.check_breakpoints(0x0600).ok(), Some(None));
+		assert_eq!(program.check_breakpoints(0x0600).ok(), Some(Some(id)));
+		assert_eq!(program.check_breakpoints(0x0601).ok(), Some(None));
+		assert_eq!(program.find_breakpoint(id).map(|b| (b.hits, b.ignore_count)), Some((3, 0)));
+
+		program.find_breakpoint(id).unwrap().enabled = false;
+		let (temporary, _) = program.add_breakpoint(0x0600, Some(Condition::parse("a == 1").unwrap()), true);
+		assert_eq!(program.check_breakpoints(0x0600).ok(), Some(None));
+		program.reg_a = 1;
+		assert_eq!(program.check_breakpoints(0x0600).ok(), Some(Some(temporary)));
+		assert!(program.find_breakpoint(temporary).is_none());
+		assert_eq!(program.check_breakpoints(0x0600).ok(), Some(None));
+	}
+
+	#[test]
+	fn condition_errors_stop_the_program() {
+		let mut program = Program::new();
+		let (quiet, _) = program.add_breakpoint(0x0600, None, false);
+		program.find_breakpoint(quiet).unwrap().ignore_count = 1;
+		let (broken, _) = program.add_breakpoint(0x0600, Some(Condition::parse("mem[nowhere]").unwrap()), true);
+
+		// The error stops the program even though nothing else would, and takes the temporary breakpoint with it
+		let error = program.check_breakpoints(0x0600).err().unwrap();
+		assert_eq!(error.id, broken);
+		assert!(error.message.contains("nowhere"));
+		assert!(program.find_breakpoint(broken).is_none());
+		assert_eq!(program.find_breakpoint(quiet).map(|b| b.ignore_count), Some(0));
+	}
+
+	// Steps until the mode is done or the program ends, returning how many instructions ran
+	fn run_until_done(program: &mut Program, mode: StepMode) -> usize {
+		let mut count = 0;
+		loop {
+			let opcode = program.step(false).unwrap();
+			count += 1;
+			if mode.is_done(program, &opcode) || program.flag_break {
+				return count;
+			}
+		}
+	}
+
+	#[test]
+	fn next_and_finish() {
+		// jsr sub / lda #$01 / brk, sub: ldx #$05 / jsr leaf / rts, leaf: rts
+		let mut program = Program::new();
+		program.poke(0x0600, &[0x20, 0x10, 0x06, 0xa9, 0x01, 0x00]);
+		program.poke(0x0610, &[0xa2, 0x05, 0x20, 0x20, 0x06, 0x60]);
+		program.poke(0x0620, &[0x60]);
+		program.program_counter = 0x0600;
+
+		let mode = StepMode::over(&program).unwrap();
+		assert_eq!(run_until_done(&mut program, mode), 5);
+		assert_eq!((program.program_counter, program.stack_pointer, program.reg_x), (0x0603, 0xff, 0x05));
+		assert!(StepMode::over(&program).is_none());
+
+		// JSR pushes the address of its last byte, high byte first
+		program.program_counter = 0x0600;
+		program.step(false).unwrap();
+		assert_eq!((program.stack_pointer, program.memory[0x01ff], program.memory[0x01fe]), (0xfd, 0x06, 0x02));
+
+		let mode = StepMode::Out{stack_pointer: program.stack_pointer};
+		assert_eq!(run_until_done(&mut program, mode), 4);
+		assert_eq!((program.program_counter, program.stack_pointer), (0x0603, 0xff));
+
+		program.program_counter = 0x0600;
+		assert_eq!(run_until_done(&mut program, StepMode::Until(0x0620)), 3);
+	}
+
+
+	#[test]
+	fn reverse_step_restores_everything() {
+		// lda #$11 / pha / jsr sub / brk, sub: sta $10 / rts
+		let mut program = Program::new();
+		program.poke(0x0600, &[0xa9, 0x11, 0x48, 0x20, 0x10, 0x06, 0x00]);
+		program.poke(0x0610, &[0x85, 0x10, 0x60]);
+		program.poke(0x10, &[0x99]);
+		program.program_counter = 0x0600;
+
+		type State = (Registers, u64, Vec<u8>, Vec<Option<StackWriter>>, Vec<Frame>);
+		let state = |program: &Program| -> State {
+			(program.registers(), program.cycles, program.memory.to_vec(), program.stack_writers.clone(), program.call_stack.clone())
+		};
+
+		let mut states = Vec::new();
+		for _ in 0..4 {
+			states.push(state(&program));
+			program.step(false).unwrap();
+		}
+		states.push(state(&program));
+		assert!(program.interrupt(true));
+		assert_eq!(program.stack_writers[0xfb], Some(StackWriter::Interrupt));
+		assert_eq!(program.memory[0x10], 0x11);
+
+		for expected in states.iter().rev() {
+			assert!(program.reverse_step());
+			assert!(state(&program) == *expected);
+		}
+		assert_eq!(program.stack_writers[0xff], None);
+		assert_eq!(program.memory[0x10], 0x99);
+		assert!(!program.reverse_step());
+	}
+
+
+	#[test]
+	fn set_register_checks() {
+		let mut program = Program::new();
+		assert_eq!(program.set_register("PC", 0xc000), Ok(()));
+		assert_eq!(program.set_register("flags.c", 1), Ok(()));
+		assert_eq!(program.set_register("z", 1), Ok(()));
+		assert_eq!(program.set_register("status", 0x80), Ok(()));
+		assert_eq!((program.program_counter, program.flag_negative, program.flag_carry), (0xc000, true, false));
+
+		assert_eq!(program.set_register("foo", 300), Err(String::from("Unknown register or flag \"foo\"")));
+		assert_eq!(program.set_register("flags.q", 1), Err(String::from("Unknown register or flag \"flags.q\"")));
+		assert_eq!(program.set_register("a", 0x100), Err(String::from("$100 doesn't fit in a")));
+		assert_eq!(program.set_register("sp", -1), Err(String::from("-1 doesn't fit in sp")));
+		assert_eq!(program.set_register("c", 2), Err(String::from("$2 doesn't fit in c")));
+		assert_eq!(program.set_register("pc", 0x10000), Err(String::from("$10000 doesn't fit in pc")));
+		assert_eq!(program.program_counter, 0xc000);
+	}
+
+
+	#[test]
+	fn memory_commands() {
+		let mut program = Program::new();
+		program.fill(0xfffe, 0x0001, 0xaa);
+		assert_eq!([program.memory[0xfffe], program.memory[0xffff], program.memory[0x0000], program.memory[0x0001]], [0xaa; 4]);
+
+		program.poke(0x1000, b"hello, help");
+		program.copy(0x1000, 0x1002, 5);
+		assert_eq!(&program.memory[0x1000..0x1007], b"hehello");
+
+		program.poke(0x2000, b"hexlo");
+		assert_eq!(program.compare(0x1002, 0x2000, 5), [(2, b'l', b'x')]);
+		assert!(program.compare(0x1002, 0x1002, 0x10000).is_empty());
+
+		assert_eq!(program.find(0x1000, 0x100f, &[Some(b'h'), Some(b'e')]), [0x1000, 0x1002, 0x1007]);
+		assert_eq!(program.find(0x1000, 0x100f, &[Some(b'e'), None, Some(b'p')]), [0x1008]);
+		assert_eq!(program.find(0x1000, 0x1001, &[Some(b'h'), Some(b'e'), Some(b'h')]), [0x1000]);
+		assert_eq!(program.find(0xffff, 0x0000, &[Some(0xaa), Some(0xaa)]), [0xffff, 0x0000]);
+		assert!(program.find(0x1000, 0x100f, &[]).is_empty());
+	}
+
+
+	#[test]
+	fn call_stack_across_interrupts() {
+		// main: jsr sub / brk, sub: nop / nop / rts, irq: pha / pla / rti
+		let mut program = Program::new();
+		program.poke(0x0600, &[0x20, 0x10, 0x06, 0x00]);
+		program.poke(0x0610, &[0xea, 0xea, 0x60]);
+		program.poke(0x0700, &[0x48, 0x68, 0x40]);
+		program.poke(0xfffe, &[0x00, 0x07]);
+		program.program_counter = 0x0600;
+
+		program.step(false).unwrap();
+		program.step(false).unwrap();
+		assert!(program.interrupt(false));
+		let kinds: Vec<(FrameKind, u16, u16)> = program.call_stack.iter().map(|f| (f.kind, f.subroutine, f.return_address)).collect();
+		assert_eq!(kinds, [(FrameKind::Call, 0x0610, 0x0603), (FrameKind::Interrupt, 0x0700, 0x0611)]);
+
+		// The handler's own pushes don't end its frame, RTI does
+		program.step(false).unwrap();
+		program.step(false).unwrap();
+		assert_eq!(program.call_stack.len(), 2);
+		program.step(false).unwrap();
+		assert_eq!((program.program_counter, program.call_stack.len()), (0x0611, 1));
+		assert!(program.stack_anomalies.is_empty());
+
+		// A masked IRQ isn't taken
+		program.flag_interrupt = true;
+		assert!(!program.interrupt(false));
+		assert!(program.interrupt(true));
+		assert_eq!(program.call_stack.len(), 2);
+		program.program_counter = 0x0702;
+		program.step(false).unwrap();
+
+		program.step(false).unwrap();
+		program.step(false).unwrap();
+		assert_eq!((program.program_counter, program.stack_pointer), (0x0603, 0xff));
+		assert!(program.call_stack.is_empty());
+		assert!(program.stack_anomalies.is_empty());
+
+		// BRK is an interrupt too, whose return address skips its padding byte
+		program.step(false).unwrap();
+		assert_eq!(program.call_stack.last().map(|f| (f.kind, f.call_site, f.return_address)), Some((FrameKind::Interrupt, 0x0603, 0x0605)));
+	}
+
+	#[test]
+	fn discarded_return_address() {
+		// jsr sub / brk, sub: pla / pla / rts
+		let mut program = Program::new();
+		program.poke(0x0600, &[0x20, 0x10, 0x06, 0x00]);
+		program.poke(0x0610, &[0x68, 0x68, 0x60]);
+		program.program_counter = 0x0600;
+
+		program.step(false).unwrap();
+		program.step(false).unwrap();
+		assert!(program.stack_anomalies.is_empty());
+		program.step(false).unwrap();
+		assert!(matches!(program.stack_anomalies[..], [StackAnomaly::Discarded{address: 0x0611, ..}]));
+		assert!(program.call_stack.is_empty());
+		program.step(false).unwrap();
+		assert!(matches!(program.stack_anomalies[..], [StackAnomaly::UnmatchedReturn{address: 0x0612, ..}]));
+	}
+
+
+	#[test]
+	fn stack_guard_catches_wraparound() {
+		// pha / pla / pla
+		let mut program = Program::new();
+		program.poke(0x0600, &[0x48, 0x68, 0x68]);
+		program.program_counter = 0x0600;
+		program.stack_pointer = 0x00;
+
+		program.step(false).unwrap();
+		assert!(program.stack_faults.is_empty());
+
+		program.stack_guard = true;
+		program.program_counter = 0x0600;
+		program.stack_pointer = 0x00;
+		program.step(false).unwrap();
+		assert_eq!(program.stack_faults, [StackFault::Overflow{address: 0x0600}]);
+		assert_eq!(program.stack_pointer, 0xff);
+
+		program.step(false).unwrap();
+		assert_eq!(program.stack_faults, [StackFault::Underflow{address: 0x0601}]);
+		program.step(false).unwrap();
+		assert!(program.stack_faults.is_empty());
+
+		// Faults entering an interrupt belong to the instruction it interrupted
+		program.stack_pointer = 0x01;
+		assert!(program.interrupt(true));
+		assert_eq!(program.stack_faults, [StackFault::Overflow{address: 0x0603}]);
+		assert_eq!(program.stack_pointer, 0xfe);
+	}
+
 }
@@ -0,0 +1,250 @@
+// script.rs
+
+// Rhai scripting. A script gets the program through these functions:
+//
+//   load(file)                     Load a .prg, hex or S-record file, or assemble a source file
+//   reset()                        Move the program counter back to the origin and zero the cycle count
+//   reg(name), set_reg(name, v)    Read or change a register or flag, reg also looks up symbols and "cycles"
+//   peek(address), poke(address, byte or [bytes])
+//   evaluate(expression)           Evaluate a debugger expression, e.g. evaluate("mem[$10] + x")
+//   address(text)                  Parse an address or symbol name
+//   step()                         Execute one instruction, false once the program has ended
+//   run([max])                     Run until BRK, false if max instructions ran first
+//   run_until(condition, [max])    Run until a closure or expression is true before an instruction, false if it never was
+//   on_pc(address, || ...)         Call a function whenever the program is about to execute address
+//   on_write(address, |address, before, after| ...)   Call a function after each write to address with the byte either side of it
+//   clear_hooks()
+//   trace(bool)                    Print each instruction as it runs
+//
+// The program ends at BRK, and an invalid opcode stops the script with an error.
+
+use std::cell::{Cell, RefCell};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use rhai::{Array, Engine, EvalAltResult, FnPtr, NativeCallContext};
+
+use crate::debug;
+use crate::expression::{self, Expr, Resolver};
+use crate::program::Program;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+struct State {
+	program: RefCell<Program>,
+	pc_hooks: RefCell<Vec<(u16, FnPtr)>>,
+	write_hooks: RefCell<Vec<(u16, FnPtr)>>,
+	trace: Cell<bool>,
+}
+
+enum StopCondition {
+	Function(FnPtr),
+	Expression(Expr),
+}
+
+fn to_address(value: i64) -> ScriptResult<u16> {
+	if (0..=0xffff).contains(&value) {
+		Ok(value as u16)
+	}
+	else {
+		Err(format!("Address ${:x} is out of range", value).into())
+	}
+}
+
+fn to_byte(value: i64) -> ScriptResult<u8> {
+	if (0..=0xff).contains(&value) {
+		Ok(value as u8)
+	}
+	else {
+		Err(format!("Value ${:x} doesn't fit in a byte", value).into())
+	}
+}
+
+// =============================================================
+
+// Executes one instruction, calling any hooks on the way. Returns false once the program has hit BRK.
+fn step(state: &State, context: &NativeCallContext) -> ScriptResult<bool> {
+	let pc = state.program.borrow().program_counter;
+	let hooks: Vec<FnPtr> = state.pc_hooks.borrow().iter().filter(|(address, _)| *address == pc).map(|(_, f)| f.clone()).collect();
+	for hook in hooks {
+		hook.call_within_context::<()>(context, ())?;
+	}
+
+	let writes = {
+		let mut program = state.program.borrow_mut();
+		if let Err(byte) = program.step(state.trace.get()) {
+			return Err(debug::describe_invalid_opcode(byte, program.program_counter).into());
+		}
+		// Each write as it happened, so a byte written twice by one instruction calls its hooks with both values
+		let writes: Vec<(u16, u8, u8)> = program.history.iter().next_back().map(|step| step.writes.clone()).unwrap_or_default();
+		writes
+	};
+
+	for (address, old, new) in writes {
+		let hooks: Vec<FnPtr> = state.write_hooks.borrow().iter().filter(|(a, _)| *a == address).map(|(_, f)| f.clone()).collect();
+		for hook in hooks {
+			hook.call_within_context::<()>(context, (address as i64, old as i64, new as i64))?;
+		}
+	}
+
+	let mut program = state.program.borrow_mut();
+	if program.flag_break {
+		program.flag_break = false;
+		return Ok(false);
+	}
+	Ok(true)
+}
+
+// Returns true if the condition was met, false if the program ended or ran max instructions first
+fn run_until(state: &State, context: &NativeCallContext, condition: Option<&StopCondition>, max: Option<i64>) -> ScriptResult<bool> {
+	let mut count = 0;
+	loop {
+		let met = match condition {
+			Some(StopCondition::Function(f)) => f.call_within_context::<bool>(context, ())?,
+			Some(StopCondition::Expression(expr)) => expr.evaluate(&*state.program.borrow())? != 0,
+			None => false,
+		};
+		if met {
+			return Ok(true);
+		}
+
+		if max.is_some_and(|max| count >= max) {
+			return Ok(false);
+		}
+		count += 1;
+
+		if !step(state, context)? {
+			// Running to the end is what run() without a condition is for
+			return Ok(condition.is_none());
+		}
+	}
+}
+
+fn parse_condition(text: &str) -> ScriptResult<StopCondition> {
+	Ok(StopCondition::Expression(expression::parse(text)?))
+}
+
+// =============================================================
+
+fn register_functions(engine: &mut Engine, state: &Rc<State>) {
+	let s = state.clone();
+	engine.register_fn("load", move |filename: &str| -> ScriptResult<()> {
+		s.program.borrow_mut().load(filename, None, None)?;
+		Ok(())
+	});
+
+	let s = state.clone();
+	engine.register_fn("reset", move || {
+		let mut program = s.program.borrow_mut();
+		program.program_counter = program.origin;
+		program.cycles = 0;
+		program.history.clear();
+		program.call_stack.clear();
+	});
+
+	let s = state.clone();
+	engine.register_fn("reg", move |name: &str| -> ScriptResult<i64> {
+		s.program.borrow().resolve(name).ok_or_else(|| format!("Unknown register or symbol \"{}\"", name).into())
+	});
+
+	let s = state.clone();
+	engine.register_fn("set_reg", move |name: &str, value: i64| -> ScriptResult<()> {
+		Ok(s.program.borrow_mut().set_register(name, value)?)
+	});
+
+	let s = state.clone();
+	engine.register_fn("peek", move |address: i64| -> ScriptResult<i64> {
+		Ok(s.program.borrow().get_memory(to_address(address)?) as i64)
+	});
+
+	let s = state.clone();
+	engine.register_fn("poke", move |address: i64, value: i64| -> ScriptResult<()> {
+		s.program.borrow_mut().poke(to_address(address)?, &[to_byte(value)?]);
+		Ok(())
+	});
+
+	let s = state.clone();
+	engine.register_fn("poke", move |address: i64, values: Array| -> ScriptResult<()> {
+		let bytes = values.into_iter().map(|v| to_byte(v.as_int()?)).collect::<ScriptResult<Vec<u8>>>()?;
+		s.program.borrow_mut().poke(to_address(address)?, &bytes);
+		Ok(())
+	});
+
+	let s = state.clone();
+	engine.register_fn("evaluate", move |text: &str| -> ScriptResult<i64> {
+		Ok(expression::parse(text)?.evaluate(&*s.program.borrow())?)
+	});
+
+	let s = state.clone();
+	engine.register_fn("address", move |text: &str| -> ScriptResult<i64> {
+		s.program.borrow().symbols.parse_address(text).map(|a| a as i64).ok_or_else(|| format!("Invalid address \"{}\"", text).into())
+	});
+
+	let s = state.clone();
+	engine.register_fn("step", move |context: NativeCallContext| step(&s, &context));
+
+	let s = state.clone();
+	engine.register_fn("run", move |context: NativeCallContext| run_until(&s, &context, None, None));
+
+	let s = state.clone();
+	engine.register_fn("run", move |context: NativeCallContext, max: i64| run_until(&s, &context, None, Some(max)));
+
+	let s = state.clone();
+	engine.register_fn("run_until", move |context: NativeCallContext, f: FnPtr| {
+		run_until(&s, &context, Some(&StopCondition::Function(f)), None)
+	});
+
+	let s = state.clone();
+	engine.register_fn("run_until", move |context: NativeCallContext, f: FnPtr, max: i64| {
+		run_until(&s, &context, Some(&StopCondition::Function(f)), Some(max))
+	});
+
+	let s = state.clone();
+	engine.register_fn("run_until", move |context: NativeCallContext, text: &str| {
+		run_until(&s, &context, Some(&parse_condition(text)?), None)
+	});
+
+	let s = state.clone();
+	engine.register_fn("run_until", move |context: NativeCallContext, text: &str, max: i64| {
+		run_until(&s, &context, Some(&parse_condition(text)?), Some(max))
+	});
+
+	let s = state.clone();
+	engine.register_fn("on_pc", move |address: i64, f: FnPtr| -> ScriptResult<()> {
+		s.pc_hooks.borrow_mut().push((to_address(address)?, f));
+		Ok(())
+	});
+
+	let s = state.clone();
+	engine.register_fn("on_write", move |address: i64, f: FnPtr| -> ScriptResult<()> {
+		s.write_hooks.borrow_mut().push((to_address(address)?, f));
+		Ok(())
+	});
+
+	let s = state.clone();
+	engine.register_fn("clear_hooks", move || {
+		s.pc_hooks.borrow_mut().clear();
+		s.write_hooks.borrow_mut().clear();
+	});
+
+	let s = state.clone();
+	engine.register_fn("trace", move |on: bool| s.trace.set(on));
+}
+
+pub fn run_script(program: &mut Program, filename: &str) -> Result<(), String> {
+	// The script owns the program while it runs, and hands it back afterwards
+	let state = Rc::new(State{
+		program: RefCell::new(std::mem::replace(program, Program::new())),
+		pc_hooks: RefCell::new(Vec::new()),
+		write_hooks: RefCell::new(Vec::new()),
+		trace: Cell::new(false),
+	});
+
+	let mut engine = Engine::new();
+	register_functions(&mut engine, &state);
+	let result = engine.run_file(PathBuf::from(filename)).map_err(|e| format!("{}: {}", filename, e));
+
+	// Taking the program back out of the cell works however many owners the state still has
+	*program = state.program.replace(Program::new());
+	result
+}
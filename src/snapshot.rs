@@ -0,0 +1,232 @@
+// snapshot.rs
+
+// A snapshot is the magic bytes, a version number, then little-endian fields in the order
+// save_snapshot writes them. Bump SNAPSHOT_VERSION whenever that order changes, and keep
+// reading the older versions where it's cheap to.
+
+use std::fs;
+use std::io::{Cursor, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::breakpoints::{Breakpoint, Condition, Watchpoint, WatchKind};
+use crate::program::Program;
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"FE6502SS";
+const SNAPSHOT_VERSION: u16 = 1;
+
+fn write_string(out: &mut Vec<u8>, text: &str) -> std::io::Result<()> {
+	out.write_u16::<LittleEndian>(text.len() as u16)?;
+	out.write_all(text.as_bytes())
+}
+
+fn read_string(cursor: &mut Cursor<&[u8]>) -> std::io::Result<String> {
+	let len = cursor.read_u16::<LittleEndian>()?;
+	let mut bytes = vec![0; len as usize];
+	cursor.read_exact(&mut bytes)?;
+	Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn write_snapshot(program: &Program) -> std::io::Result<Vec<u8>> {
+	let mut out = Vec::new();
+	out.write_all(SNAPSHOT_MAGIC)?;
+	out.write_u16::<LittleEndian>(SNAPSHOT_VERSION)?;
+
+	out.write_u16::<LittleEndian>(program.program_counter)?;
+	out.write_u8(program.reg_a)?;
+	out.write_u8(program.reg_x)?;
+	out.write_u8(program.reg_y)?;
+	out.write_u8(program.stack_pointer)?;
+	out.write_u8(program.get_status())?;
+	out.write_u16::<LittleEndian>(program.origin)?;
+	out.write_u64::<LittleEndian>(program.cycles)?;
+	out.write_all(&program.memory)?;
+
+	out.write_u32::<LittleEndian>(program.next_breakpoint_id)?;
+	out.write_u16::<LittleEndian>(program.breakpoints.len() as u16)?;
+	for breakpoint in &program.breakpoints {
+		out.write_u32::<LittleEndian>(breakpoint.id)?;
+		out.write_u16::<LittleEndian>(breakpoint.address)?;
+		out.write_u8(breakpoint.enabled as u8 | (breakpoint.temporary as u8) << 1)?;
+		out.write_u32::<LittleEndian>(breakpoint.hits)?;
+		out.write_u32::<LittleEndian>(breakpoint.ignore_count)?;
+		write_string(&mut out, breakpoint.condition.as_ref().map(|c| c.text.as_str()).unwrap_or(""))?;
+	}
+
+	out.write_u16::<LittleEndian>(program.watchpoints.len() as u16)?;
+	for watchpoint in &program.watchpoints {
+		write_string(&mut out, watchpoint.kind.name())?;
+		out.write_u16::<LittleEndian>(watchpoint.start)?;
+		out.write_u16::<LittleEndian>(watchpoint.end)?;
+	}
+
+	// Nothing is attached to the bus yet, the count is here so devices can be added without a new version
+	out.write_u16::<LittleEndian>(0)?;
+
+	Ok(out)
+}
+
+fn read_snapshot(program: &mut Program, bytes: &[u8]) -> Result<(), String> {
+	let mut cursor = Cursor::new(bytes);
+	let truncated = |_| String::from("Snapshot file is truncated");
+
+	let mut magic = [0u8; 8];
+	cursor.read_exact(&mut magic).map_err(truncated)?;
+	if &magic != SNAPSHOT_MAGIC {
+		return Err(String::from("Not a snapshot file"));
+	}
+
+	let version = cursor.read_u16::<LittleEndian>().map_err(truncated)?;
+	if version == 0 || version > SNAPSHOT_VERSION {
+		return Err(format!("Snapshot version {} is not supported (newest is {})", version, SNAPSHOT_VERSION));
+	}
+
+	// Read everything before touching the program, so a bad file leaves it as it was
+	let pc = cursor.read_u16::<LittleEndian>().map_err(truncated)?;
+	let a = cursor.read_u8().map_err(truncated)?;
+	let x = cursor.read_u8().map_err(truncated)?;
+	let y = cursor.read_u8().map_err(truncated)?;
+	let sp = cursor.read_u8().map_err(truncated)?;
+	let status = cursor.read_u8().map_err(truncated)?;
+	let origin = cursor.read_u16::<LittleEndian>().map_err(truncated)?;
+	let cycles = cursor.read_u64::<LittleEndian>().map_err(truncated)?;
+	let mut memory = vec![0; u16::MAX as usize + 1];
+	cursor.read_exact(&mut memory).map_err(truncated)?;
+
+	let next_breakpoint_id = cursor.read_u32::<LittleEndian>().map_err(truncated)?;
+	let mut breakpoints = Vec::new();
+	for _ in 0..cursor.read_u16::<LittleEndian>().map_err(truncated)? {
+		let id = cursor.read_u32::<LittleEndian>().map_err(truncated)?;
+		let address = cursor.read_u16::<LittleEndian>().map_err(truncated)?;
+		let flags = cursor.read_u8().map_err(truncated)?;
+		let hits = cursor.read_u32::<LittleEndian>().map_err(truncated)?;
+		let ignore_count = cursor.read_u32::<LittleEndian>().map_err(truncated)?;
+		let condition_text = read_string(&mut cursor).map_err(truncated)?;
+		let condition = if condition_text.is_empty() { None } else { Some(Condition::parse(&condition_text)?) };
+
+		let mut breakpoint = Breakpoint::new(id, address, condition, flags & 2 != 0);
+		breakpoint.enabled = flags & 1 != 0;
+		breakpoint.hits = hits;
+		breakpoint.ignore_count = ignore_count;
+		breakpoints.push(breakpoint);
+	}
+
+	let mut watchpoints = Vec::new();
+	for _ in 0..cursor.read_u16::<LittleEndian>().map_err(truncated)? {
+		let name = read_string(&mut cursor).map_err(truncated)?;
+		let kind = WatchKind::from_name(&name).ok_or(format!("Invalid watchpoint kind \"{}\"", name))?;
+		let start = cursor.read_u16::<LittleEndian>().map_err(truncated)?;
+		let end = cursor.read_u16::<LittleEndian>().map_err(truncated)?;
+		watchpoints.push(Watchpoint{kind, start, end});
+	}
+
+	if cursor.read_u16::<LittleEndian>().map_err(truncated)? != 0 {
+		return Err(String::from("Snapshot has devices attached, which this version can't restore"));
+	}
+
+	program.program_counter = pc;
+	program.reg_a = a;
+	program.reg_x = x;
+	program.reg_y = y;
+	program.stack_pointer = sp;
+	program.set_status(status);
+	program.origin = origin;
+	program.cycles = cycles;
+	program.memory = memory;
+	program.next_breakpoint_id = next_breakpoint_id;
+	program.breakpoints = breakpoints;
+	program.watchpoints = watchpoints;
+
+	// The recorded history and calls belong to whatever was running before
+	program.history.clear();
+	program.call_stack.clear();
+	program.stack_writers = vec![None; 256];
+	Ok(())
+}
+
+pub fn save_snapshot(program: &Program, filename: &str) -> Result<(), String> {
+	let bytes = write_snapshot(program).map_err(|e| e.to_string())?;
+	fs::write(filename, bytes).map_err(|e| format!("Failed to write file: {}", e))
+}
+
+pub fn restore_snapshot(program: &mut Program, filename: &str) -> Result<(), String> {
+	let bytes = fs::read(filename).map_err(|e| format!("Failed to open file: {}", e))?;
+	read_snapshot(program, &bytes)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn sample_program() -> Program {
+		let mut program = Program::new();
+		program.program_counter = 0x1234;
+		program.reg_a = 0x01;
+		program.reg_x = 0x02;
+		program.reg_y = 0x03;
+		program.stack_pointer = 0xf0;
+		program.set_status(0xa5);
+		program.origin = 0x1000;
+		program.cycles = 123456789;
+		program.memory[0x1000] = 0xa9;
+		program.memory[0xffff] = 0x42;
+		program.add_breakpoint(0x1000, None, false);
+		program.add_breakpoint(0x1010, Some(Condition::parse("a == $10").unwrap()), true);
+		program.breakpoints[0].hits = 3;
+		program.breakpoints[0].ignore_count = 2;
+		program.breakpoints[0].enabled = false;
+		program.watchpoints.push(Watchpoint{kind: WatchKind::Change, start: 0x0200, end: 0x02ff});
+		program
+	}
+
+	#[test]
+	fn round_trip() {
+		let saved = sample_program();
+		let bytes = write_snapshot(&saved).unwrap();
+		let mut program = Program::new();
+		read_snapshot(&mut program, &bytes).unwrap();
+
+		assert_eq!((program.program_counter, program.reg_a, program.reg_x, program.reg_y), (0x1234, 0x01, 0x02, 0x03));
+		assert_eq!((program.stack_pointer, program.get_status(), program.origin, program.cycles), (0xf0, saved.get_status(), 0x1000, 123456789));
+		assert!(program.memory == saved.memory);
+		assert_eq!(program.next_breakpoint_id, saved.next_breakpoint_id);
+
+		let breakpoints: Vec<_> = program.breakpoints.iter()
+			.map(|b| (b.id, b.address, b.enabled, b.temporary, b.hits, b.ignore_count, b.condition.as_ref().map(|c| c.text.clone())))
+			.collect();
+		assert_eq!(breakpoints, [
+			(saved.breakpoints[0].id, 0x1000, false, false, 3, 2, None),
+			(saved.breakpoints[1].id, 0x1010, true, true, 0, 0, Some(String::from("a == $10"))),
+		]);
+
+		let watchpoints: Vec<_> = program.watchpoints.iter().map(|w| (w.kind.name(), w.start, w.end)).collect();
+		assert_eq!(watchpoints, [("change", 0x0200, 0x02ff)]);
+
+		// Saving the restored program gives back the same file
+		assert_eq!(write_snapshot(&program).unwrap(), bytes);
+	}
+
+	#[test]
+	fn rejects_bad_files() {
+		let bytes = write_snapshot(&sample_program()).unwrap();
+		let mut program = Program::new();
+
+		let mut unknown = bytes.clone();
+		unknown[8..10].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+		assert_eq!(read_snapshot(&mut program, &unknown).err(), Some(format!("Snapshot version {} is not supported (newest is {})", SNAPSHOT_VERSION + 1, SNAPSHOT_VERSION)));
+
+		let mut zero = bytes.clone();
+		zero[8..10].copy_from_slice(&[0, 0]);
+		assert!(read_snapshot(&mut program, &zero).is_err());
+
+		let mut magic = bytes.clone();
+		magic[0] = b'X';
+		assert_eq!(read_snapshot(&mut program, &magic).err().as_deref(), Some("Not a snapshot file"));
+
+		assert_eq!(read_snapshot(&mut program, &bytes[..bytes.len() - 1]).err().as_deref(), Some("Snapshot file is truncated"));
+
+		// None of the failed reads should have touched the program
+		assert_eq!(program.program_counter, 0);
+		assert!(program.breakpoints.is_empty());
+	}
+}
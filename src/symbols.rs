@@ -0,0 +1,253 @@
+// symbols.rs
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Shared by every table, so no two states of any table have the same generation
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+pub struct SymbolTable {
+	by_name: HashMap<String, u16>,
+	by_address: BTreeMap<u16, String>,
+	// Every name by its lowercase form, for lookups that don't match case
+	by_lowercase: HashMap<String, Vec<String>>,
+	generation: u64,
+}
+
+impl SymbolTable {
+	pub fn new() -> Self {
+		SymbolTable{
+			by_name: HashMap::new(),
+			by_address: BTreeMap::new(),
+			by_lowercase: HashMap::new(),
+			generation: NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
+		}
+	}
+
+	// Changes whenever a symbol is added or removed
+	pub fn generation(&self) -> u64 {
+		self.generation
+	}
+
+	pub fn insert(&mut self, name: &str, address: u16) {
+		match self.by_name.insert(name.to_string(), address) {
+			// Moving the name an address is shown as hands that address to its alphabetically first remaining name
+			Some(old) if old != address && self.by_address.get(&old).map(|n| n == name).unwrap_or(false) => {
+				match self.by_name.iter().filter(|(_, a)| **a == old).map(|(n, _)| n).min() {
+					Some(alias) => { self.by_address.insert(old, alias.clone()); },
+					None => { self.by_address.remove(&old); },
+				}
+			},
+			Some(_) => {},
+			None => { self.by_lowercase.entry(name.to_lowercase()).or_default().push(name.to_string()); },
+		}
+
+		// The first name given to an address is the one shown in disassembly
+		self.by_address.entry(address).or_insert_with(|| name.to_string());
+		self.generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn clear(&mut self) {
+		self.by_name.clear();
+		self.by_address.clear();
+		self.by_lowercase.clear();
+		self.generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn lookup(&self, name: &str) -> Option<u16> {
+		// Without an exact match, a name that differs only in case will do, unless there are several such names
+		self.by_name.get(name).copied().or_else(|| match self.by_lowercase.get(&name.to_lowercase())?.as_slice() {
+			[only] => self.by_name.get(only).copied(),
+			_ => None,
+		})
+	}
+
+	pub fn name_for(&self, address: u16) -> Option<&str> {
+		self.by_address.get(&address).map(|n| n.as_str())
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = (&u16, &String)> {
+		self.by_address.iter()
+	}
+
+	// Every name, including the ones that aren't first at their address
+	pub fn names(&self) -> impl Iterator<Item = &String> {
+		self.by_name.keys()
+	}
+
+	// The symbol name if there is one, otherwise the address in hex with the given number of digits
+	pub fn format_address(&self, address: u16, digits: usize) -> String {
+		match self.name_for(address) {
+			Some(name) => name.to_string(),
+			None => format!("${:0width$x}", address, width = digits),
+		}
+	}
+
+	// Accepts $hex, a symbol name, or bare hex if no symbol has that name
+	pub fn parse_address(&self, text: &str) -> Option<u16> {
+		if let Some(hex) = text.strip_prefix('$') {
+			return u16::from_str_radix(hex, 16).ok();
+		}
+
+		self.lookup(text).or_else(|| u16::from_str_radix(text.trim_start_matches("0x"), 16).ok())
+	}
+}
+
+// Which source line each instruction came from, for programs assembled here
+pub struct SourceMap {
+	pub file: String,
+	pub lines: BTreeMap<u16, usize>,
+}
+
+impl SourceMap {
+	pub fn line_for(&self, address: u16) -> Option<usize> {
+		self.lines.get(&address).copied()
+	}
+
+	// The first instruction on the line, or on the next line that has one
+	pub fn address_for(&self, line: usize) -> Option<(u16, usize)> {
+		self.lines.iter()
+			.filter(|(_, l)| **l >= line)
+			.min_by_key(|(address, l)| (**l, **address))
+			.map(|(address, l)| (*address, *l))
+	}
+}
+
+// =============================================================
+
+fn parse_number(text: &str) -> Option<u32> {
+	let text = text.trim();
+	if let Some(hex) = text.strip_prefix('$') {
+		u32::from_str_radix(hex, 16).ok()
+	}
+	else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+		u32::from_str_radix(hex, 16).ok()
+	}
+	else {
+		text.parse::<u32>().ok()
+	}
+}
+
+// VICE monitor labels: al C:c012 .print_string
+fn parse_vice(text: &str) -> Result<Vec<(String, u16)>, String> {
+	let mut symbols = Vec::new();
+	for (i, line) in text.lines().enumerate() {
+		let words: Vec<&str> = line.split_whitespace().collect();
+		if words.is_empty() {
+			continue;
+		}
+
+		if words.len() != 3 || words[0] != "al" {
+			return Err(format!("line {}: Expected \"al [address] .[label]\"", i + 1));
+		}
+
+		let address = words[1].rsplit(':').next().unwrap();
+		let address = u16::from_str_radix(address, 16).map_err(|_| format!("line {}: Invalid address \"{}\"", i + 1, words[1]))?;
+		symbols.push((words[2].trim_start_matches('.').to_string(), address));
+	}
+
+	Ok(symbols)
+}
+
+// ca65/ld65 debug info: sym id=0,name="print_string",...,val=0xC012,...,type=lab
+fn parse_ca65_dbg(text: &str) -> Result<Vec<(String, u16)>, String> {
+	let mut symbols = Vec::new();
+	for line in text.lines() {
+		let rest = match line.strip_prefix("sym") {
+			Some(rest) if rest.starts_with(char::is_whitespace) => rest.trim(),
+			_ => { continue; },
+		};
+
+		let mut fields = HashMap::new();
+		for field in rest.split(',') {
+			if let Some((key, value)) = field.split_once('=') {
+				fields.insert(key, value.trim_matches('"'));
+			}
+		}
+
+		// Imports have no value of their own, the matching export carries it
+		if fields.get("type") == Some(&"imp") {
+			continue;
+		}
+
+		if let (Some(name), Some(value)) = (fields.get("name"), fields.get("val").and_then(|v| parse_number(v))) {
+			if value <= 0xffff {
+				symbols.push((name.to_string(), value as u16));
+			}
+		}
+	}
+
+	Ok(symbols)
+}
+
+// 64tass and ACME label dumps: print_string = $c012 (ACME may append "; ?" comments)
+fn parse_label_dump(text: &str) -> Result<Vec<(String, u16)>, String> {
+	let mut symbols = Vec::new();
+	for (i, line) in text.lines().enumerate() {
+		let line = line.split(';').next().unwrap().trim();
+		if line.is_empty() {
+			continue;
+		}
+
+		let (name, value) = line.split_once('=').ok_or(format!("line {}: Expected \"[label] = [value]\"", i + 1))?;
+		let value = parse_number(value).ok_or(format!("line {}: Invalid value \"{}\"", i + 1, value.trim()))?;
+		if value <= 0xffff {
+			symbols.push((name.trim().trim_start_matches('.').to_string(), value as u16));
+		}
+	}
+
+	Ok(symbols)
+}
+
+pub fn load_symbol_file(filename: &str) -> Result<Vec<(String, u16)>, String> {
+	let text = fs::read_to_string(filename).map_err(|e| format!("Failed to open file: {}", e))?;
+	let first = text.lines().find(|l| !l.trim().is_empty()).unwrap_or("").trim_start();
+
+	if first.starts_with("al ") {
+		parse_vice(&text)
+	}
+	else if first.starts_with("version") && first.contains("major=") {
+		parse_ca65_dbg(&text)
+	}
+	else {
+		parse_label_dump(&text)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn lookup_ignores_case_unless_ambiguous() {
+		let mut symbols = SymbolTable::new();
+		symbols.insert("Print", 0x1000);
+		assert_eq!(symbols.lookup("print"), Some(0x1000));
+		assert_eq!(symbols.lookup("PRINT"), Some(0x1000));
+
+		symbols.insert("PRINT", 0x2000);
+		assert_eq!(symbols.lookup("Print"), Some(0x1000));
+		assert_eq!(symbols.lookup("PRINT"), Some(0x2000));
+		assert_eq!(symbols.lookup("print"), None);
+	}
+
+	#[test]
+	fn moving_a_name_keeps_its_old_address_labelled() {
+		let mut symbols = SymbolTable::new();
+		symbols.insert("start", 0x0600);
+		symbols.insert("main", 0x0600);
+		symbols.insert("entry", 0x0600);
+		assert_eq!(symbols.name_for(0x0600), Some("start"));
+
+		symbols.insert("start", 0x0800);
+		assert_eq!(symbols.name_for(0x0600), Some("entry"));
+		assert_eq!(symbols.name_for(0x0800), Some("start"));
+
+		symbols.insert("entry", 0x0900);
+		symbols.insert("main", 0x0900);
+		assert_eq!(symbols.name_for(0x0600), None);
+		assert_eq!(symbols.name_for(0x0900), Some("entry"));
+		assert_eq!(symbols.lookup("main"), Some(0x0900));
+	}
+}
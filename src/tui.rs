@@ -0,0 +1,587 @@
+// tui.rs
+
+// A full-screen debugger. The disassembly follows the program counter, with a cursor that the arrow
+// keys move and that breakpoints are toggled at. Longer commands are typed after ":".
+//
+//   s  step            n  next (step over JSR)      f  finish (run until RTS or RTI)
+//   c  continue        r  reverse step              b  toggle a breakpoint at the cursor
+//   Up/Down  move the cursor     .  cursor back to PC     PgUp/PgDn  scroll memory
+//   Esc  stop a running program  :  enter a command       q  quit
+
+use std::io::{self, IsTerminal};
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use num::FromPrimitive;
+
+use crate::breakpoints::Condition;
+use crate::debug;
+use crate::expression;
+use crate::opcodes::{Opcode, INSTRUCTION_DATA};
+use crate::program::{Program, StepMode};
+
+// How many instructions a running program gets between redraws and checks for keys
+const INSTRUCTIONS_PER_POLL: u32 = 10000;
+
+const LOG_LENGTH: usize = 500;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Resume {
+	Step,
+	Continue,
+	Until(StepMode),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum LogKind {
+	Info,
+	Warning,
+	Error,
+}
+
+struct App<'a> {
+	program: &'a mut Program,
+	running: Option<Resume>,
+	// Set when resuming from a stop, so the breakpoint the program is sitting on doesn't stop it again
+	skip_breakpoint: bool,
+	// Set by BRK, the program has to be reset before it can go on
+	ended: bool,
+	cursor: u16,
+	memory_address: u16,
+	// Rows the memory pane showed last time it was drawn, for paging
+	memory_rows: u16,
+	// The text after ":" while a command is being typed
+	command: Option<String>,
+	log: Vec<(LogKind, String)>,
+	quit: bool,
+}
+
+// =============================================================
+
+fn instruction_length(program: &Program, address: u16) -> u16 {
+	let opcode: Option<Opcode> = FromPrimitive::from_u8(program.get_memory(address));
+	opcode.map(|opcode| INSTRUCTION_DATA[&opcode].amode.size()).unwrap_or(1)
+}
+
+// Up to `count` instruction addresses leading up to `address`. Assembled programs know where their instructions
+// are, otherwise this decodes forward from the furthest point back that lands exactly on the address.
+fn preceding(program: &Program, address: u16, count: usize) -> Vec<u16> {
+	if let Some(source) = &program.source {
+		if source.lines.contains_key(&address) {
+			let mut addresses: Vec<u16> = source.lines.range(..address).rev().take(count).map(|(a, _)| *a).collect();
+			addresses.reverse();
+			return addresses;
+		}
+	}
+
+	let earliest = address.saturating_sub(count as u16 * 3);
+	for start in earliest..address {
+		let mut addresses = Vec::new();
+		let mut at = start;
+		while at < address {
+			addresses.push(at);
+			at = at.saturating_add(instruction_length(program, at));
+		}
+		if at == address {
+			let skip = addresses.len().saturating_sub(count);
+			return addresses.split_off(skip);
+		}
+	}
+	Vec::new()
+}
+
+fn parse_address(program: &Program, text: Option<&str>) -> Result<u16, String> {
+	program.evaluate_address(text.ok_or("Expected an address")?)
+}
+
+fn title(text: &str) -> Block<'_> {
+	Block::bordered().title(Span::styled(text, Style::new().fg(Color::Green)))
+}
+
+// =============================================================
+
+impl<'a> App<'a> {
+	fn new(program: &'a mut Program) -> Self {
+		let pc = program.program_counter;
+		App{
+			program,
+			running: None,
+			skip_breakpoint: true,
+			ended: false,
+			cursor: pc,
+			memory_address: 0,
+			memory_rows: 8,
+			command: None,
+			log: Vec::new(),
+			quit: false,
+		}
+	}
+
+	fn log(&mut self, kind: LogKind, message: String) {
+		self.log.push((kind, message));
+		if self.log.len() > LOG_LENGTH {
+			self.log.remove(0);
+		}
+	}
+
+	// Leaves the cursor on the program counter, with a message saying why the program stopped
+	fn stop(&mut self, message: Option<String>) {
+		self.running = None;
+		self.cursor = self.program.program_counter;
+		if let Some(message) = message {
+			self.log(LogKind::Info, message);
+		}
+	}
+
+	fn resume(&mut self, resume: Resume) {
+		if self.ended {
+			self.log(LogKind::Error, String::from("The program has ended, :reset starts it again"));
+			return;
+		}
+		self.skip_breakpoint = true;
+		self.program.flag_break = false;
+		self.running = Some(resume);
+	}
+
+	fn run_batch(&mut self, resume: Resume) {
+		for _ in 0..INSTRUCTIONS_PER_POLL {
+			let pc = self.program.program_counter;
+			if !self.skip_breakpoint {
+				let hit = self.program.check_breakpoints(pc).unwrap_or_else(|error| {
+					self.log(LogKind::Error, error.message);
+					Some(error.id)
+				});
+				if let Some(id) = hit {
+					self.stop(Some(format!("BREAKPOINT {} at ${:04x}", id, pc)));
+					return;
+				}
+			}
+			self.skip_breakpoint = false;
+
+			let cycles_before = self.program.cycles;
+			let opcode = match self.program.step(false) {
+				Ok(opcode) => opcode,
+				Err(byte) => {
+					self.stop(None);
+					self.log(LogKind::Error, debug::describe_invalid_opcode(byte, pc));
+					return;
+				},
+			};
+
+			let program = &mut *self.program;
+			if let Some(profile) = program.profile.as_mut() {
+				profile.record(pc, program.cycles - cycles_before, &program.call_stack, program.cycles);
+			}
+
+			let mut messages = Vec::new();
+			messages.extend(program.stack_anomalies.iter().map(|a| (LogKind::Warning, debug::describe_stack_anomaly(program, a))));
+			messages.extend(program.stack_faults.iter().map(|f| (LogKind::Warning, debug::describe_stack_fault(program, f))));
+			messages.extend(program.watch_hits.iter().map(|h| (LogKind::Info, debug::describe_watch_hit(program, h))));
+			let interrupted = !program.stack_faults.is_empty() || !program.watch_hits.is_empty();
+			for (kind, message) in messages {
+				self.log(kind, message);
+			}
+
+			if self.program.flag_break {
+				self.ended = true;
+				self.stop(Some(format!("BREAK at ${:04x}", pc)));
+				return;
+			}
+
+			let done = match resume {
+				Resume::Step => true,
+				Resume::Continue => false,
+				Resume::Until(mode) => mode.is_done(self.program, &opcode),
+			};
+			if done || interrupted {
+				self.stop(None);
+				return;
+			}
+		}
+	}
+
+	fn toggle_breakpoint(&mut self) {
+		let address = self.cursor;
+		let ids: Vec<u32> = self.program.breakpoints.iter().filter(|b| b.address == address).map(|b| b.id).collect();
+		if ids.is_empty() {
+			let (id, _) = self.program.add_breakpoint(address, None, false);
+			self.log(LogKind::Info, format!("Breakpoint {} at ${:04x}", id, address));
+		}
+		else {
+			for id in ids {
+				self.program.remove_breakpoint(id);
+			}
+			self.log(LogKind::Info, format!("Removed the breakpoint at ${:04x}", address));
+		}
+	}
+
+	fn reverse_step(&mut self) {
+		if self.program.reverse_step() {
+			self.ended = false;
+			self.stop(None);
+		}
+		else {
+			self.log(LogKind::Error, String::from("No history to step back through"));
+		}
+	}
+
+	fn move_cursor(&mut self, down: bool) {
+		self.cursor = if down {
+			self.cursor.wrapping_add(instruction_length(self.program, self.cursor))
+		}
+		else {
+			preceding(self.program, self.cursor, 1).first().copied().unwrap_or(self.cursor.wrapping_sub(1))
+		};
+	}
+
+	fn scroll_memory(&mut self, down: bool) {
+		let page = self.memory_rows.max(1) * 16;
+		self.memory_address = if down { self.memory_address.wrapping_add(page) } else { self.memory_address.wrapping_sub(page) };
+	}
+
+	// =============================================================
+
+	fn handle_key(&mut self, key: KeyEvent) {
+		if key.kind != KeyEventKind::Press {
+			return;
+		}
+
+		if let Some(command) = self.command.as_mut() {
+			match key.code {
+				KeyCode::Enter => {
+					let command = self.command.take().unwrap_or_default();
+					self.run_command(&command);
+				},
+				KeyCode::Esc => { self.command = None; },
+				KeyCode::Backspace => { command.pop(); },
+				KeyCode::Char(c) => { command.push(c); },
+				_ => {},
+			}
+			return;
+		}
+
+		if self.running.is_some() {
+			if matches!(key.code, KeyCode::Esc | KeyCode::Char(' ')) || key.modifiers.contains(KeyModifiers::CONTROL) {
+				self.stop(Some(format!("Stopped at ${:04x}", self.program.program_counter)));
+			}
+			return;
+		}
+
+		match key.code {
+			KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => { self.quit = true; },
+			KeyCode::Char('s') | KeyCode::F(7) => { self.resume(Resume::Step); },
+			KeyCode::Char('n') | KeyCode::F(8) => {
+				let resume = StepMode::over(self.program).map(Resume::Until).unwrap_or(Resume::Step);
+				self.resume(resume);
+			},
+			KeyCode::Char('f') => { self.resume(Resume::Until(StepMode::Out{stack_pointer: self.program.stack_pointer})); },
+			KeyCode::Char('c') | KeyCode::F(5) => { self.resume(Resume::Continue); },
+			KeyCode::Char('r') => { self.reverse_step(); },
+			KeyCode::Char('b') | KeyCode::F(9) => { self.toggle_breakpoint(); },
+			KeyCode::Up | KeyCode::Char('k') => { self.move_cursor(false); },
+			KeyCode::Down | KeyCode::Char('j') => { self.move_cursor(true); },
+			KeyCode::Char('.') => { self.cursor = self.program.program_counter; },
+			KeyCode::PageUp => { self.scroll_memory(false); },
+			KeyCode::PageDown => { self.scroll_memory(true); },
+			KeyCode::Char(':') => { self.command = Some(String::new()); },
+			KeyCode::Char('q') => { self.quit = true; },
+			KeyCode::Char('?') | KeyCode::F(1) => { self.command_help(); },
+			_ => {},
+		}
+	}
+
+	fn command_help(&mut self) {
+		for line in [
+			"Keys: s step, n next, f finish, c continue, r reverse step, b toggle breakpoint at the cursor",
+			"      Up/Down cursor, . cursor to PC, PgUp/PgDn memory, Esc stop, : command, q quit",
+			"Commands: break [address] [if condition], delete [address], until [address], goto [address],",
+			"          mem [address], set [register] [expression], print [expression], load [file], reset, quit",
+			"Addresses can be expressions such as message+4, and their numbers are hex even without a $",
+		] {
+			self.log(LogKind::Info, String::from(line));
+		}
+	}
+
+	fn run_command(&mut self, command: &str) {
+		let words: Vec<&str> = command.split_whitespace().collect();
+		let result = match words.first().copied() {
+			None => Ok(()),
+			Some("break") | Some("b") => self.break_command(&words),
+			Some("delete") | Some("d") => self.delete_command(&words),
+			Some("until") | Some("u") => parse_address(self.program, words.get(1).copied()).map(|address| {
+				self.resume(Resume::Until(StepMode::Until(address)));
+			}),
+			Some("goto") | Some("g") => parse_address(self.program, words.get(1).copied()).map(|address| { self.cursor = address; }),
+			Some("mem") | Some("m") => parse_address(self.program, words.get(1).copied()).map(|address| { self.memory_address = address; }),
+			Some("set") if words.len() > 2 => {
+				let program = &mut *self.program;
+				expression::parse(&words[2..].join(" "))
+					.and_then(|expr| expr.evaluate(program))
+					.and_then(|value| program.set_register(&words[1].to_lowercase(), value))
+					.map(|_| { self.cursor = self.program.program_counter; })
+			},
+			Some("set") => Err(String::from("Usage: set [a|x|y|sp|pc|origin|flag] [value]")),
+			Some("print") | Some("p") => expression::parse(&words[1..].join(" ")).and_then(|expr| expr.evaluate(self.program)).map(|value| {
+				self.log(LogKind::Info, format!("${:x} ({})", value, value));
+			}),
+			Some("load") if words.len() > 1 => self.program.load(&words[1..].join(" "), None, None).map(|_| {
+				self.reset();
+				self.log(LogKind::Info, format!("Loaded {}", words[1..].join(" ")));
+			}),
+			Some("load") => Err(String::from("Usage: load [filename]")),
+			Some("reset") => {
+				self.reset();
+				Ok(())
+			},
+			Some("help") => {
+				self.command_help();
+				Ok(())
+			},
+			Some("quit") | Some("q") | Some("exit") => {
+				self.quit = true;
+				Ok(())
+			},
+			Some(_) => Err(String::from("Invalid command")),
+		};
+
+		if let Err(message) = result {
+			self.log(LogKind::Error, message);
+		}
+	}
+
+	fn break_command(&mut self, words: &[&str]) -> Result<(), String> {
+		let address = match words.get(1) {
+			Some(_) => parse_address(self.program, words.get(1).copied())?,
+			None => self.cursor,
+		};
+		let condition = match words.get(2) {
+			Some(&"if") => Some(Condition::parse(&words[3..].join(" "))?),
+			Some(_) => { return Err(String::from("Usage: break [address] [if condition]")); },
+			None => None,
+		};
+		let (id, _) = self.program.add_breakpoint(address, condition, false);
+		self.log(LogKind::Info, format!("Breakpoint {} at ${:04x}", id, address));
+		Ok(())
+	}
+
+	fn delete_command(&mut self, words: &[&str]) -> Result<(), String> {
+		let address = match words.get(1) {
+			Some(_) => parse_address(self.program, words.get(1).copied())?,
+			None => self.cursor,
+		};
+		let ids: Vec<u32> = self.program.breakpoints.iter().filter(|b| b.address == address).map(|b| b.id).collect();
+		if ids.is_empty() {
+			return Err(format!("No breakpoint at ${:04x}", address));
+		}
+		for id in ids {
+			self.program.remove_breakpoint(id);
+		}
+		Ok(())
+	}
+
+	// Back to the start of the program, as run does
+	fn reset(&mut self) {
+		let program = &mut *self.program;
+		program.program_counter = program.origin;
+		program.cycles = 0;
+		program.flag_break = false;
+		program.history.clear();
+		program.call_stack.clear();
+		self.ended = false;
+		self.stop(None);
+	}
+
+	// =============================================================
+
+	fn draw(&mut self, frame: &mut Frame) {
+		let [main, memory, output, command] = Layout::vertical([
+			Constraint::Min(8), Constraint::Length(10), Constraint::Length(7), Constraint::Length(1),
+		]).areas(frame.area());
+		let [disassembly, side] = Layout::horizontal([Constraint::Min(36), Constraint::Length(56)]).areas(main);
+		let [registers, stack] = Layout::vertical([Constraint::Length(6), Constraint::Min(3)]).areas(side);
+
+		self.draw_disassembly(frame, disassembly);
+		self.draw_registers(frame, registers);
+		self.draw_stack(frame, stack);
+		self.draw_memory(frame, memory);
+		self.draw_output(frame, output);
+		self.draw_command(frame, command);
+	}
+
+	fn draw_disassembly(&self, frame: &mut Frame, area: Rect) {
+		let program = &*self.program;
+		let height = area.height.saturating_sub(2) as usize;
+		let pc = program.program_counter;
+
+		let mut rows: Vec<(Option<u16>, Line)> = Vec::new();
+		let mut address = preceding(program, self.cursor, height).first().copied().unwrap_or(self.cursor);
+		while rows.len() < height * 2 {
+			if let Some(name) = program.symbols.name_for(address) {
+				rows.push((None, Line::styled(format!("{}:", name), Style::new().fg(Color::Green))));
+			}
+
+			let (text, len) = debug::disassemble(program, address);
+			let bytes: Vec<String> = (0..len).map(|i| format!("{:02x}", program.get_memory(address.wrapping_add(i)))).collect();
+			let marker = match program.breakpoints.iter().find(|b| b.address == address) {
+				Some(breakpoint) if breakpoint.enabled => Span::styled("●", Style::new().fg(Color::Red)),
+				Some(_) => Span::styled("○", Style::new().fg(Color::Red)),
+				None => Span::raw(" "),
+			};
+			let mut line = Line::from(vec![
+				marker,
+				Span::raw(if address == pc { "▶" } else { " " }),
+				Span::raw(format!(" ${:04x}: {:<9} ", address, bytes.join(" "))),
+				Span::styled(text, Style::new().fg(Color::Yellow)),
+			]);
+			if address == pc {
+				line = line.style(Style::new().add_modifier(Modifier::BOLD));
+			}
+			if address == self.cursor {
+				line = line.style(Style::new().add_modifier(Modifier::REVERSED));
+			}
+			rows.push((Some(address), line));
+
+			match address.checked_add(len) {
+				Some(next) => { address = next; },
+				None => { break; },
+			}
+		}
+
+		// Keep the cursor a third of the way down, where there's code before it to show
+		let cursor_row = rows.iter().position(|(a, _)| *a == Some(self.cursor)).unwrap_or(0);
+		let first = cursor_row.saturating_sub(height / 3);
+		let lines: Vec<Line> = rows.into_iter().skip(first).take(height).map(|(_, line)| line).collect();
+		frame.render_widget(Paragraph::new(lines).block(title(" Disassembly ")), area);
+	}
+
+	fn draw_registers(&self, frame: &mut Frame, area: Rect) {
+		let program = &*self.program;
+		let flag = |set: bool| if set { Span::styled("+ ", Style::new().fg(Color::Green)) } else { Span::styled("- ", Style::new().fg(Color::Red)) };
+		let flags = [
+			program.flag_negative, program.flag_overflow, program.flag_break, program.flag_decimal,
+			program.flag_interrupt, program.flag_zero, program.flag_carry,
+		];
+
+		let mut flag_line = vec![Span::raw(format!("{:<5}{:<5}{:<5} ", program.reg_a, program.reg_x, program.reg_y))];
+		flag_line.extend(flags.iter().map(|set| flag(*set)));
+		let state = if self.running.is_some() { "running" } else if self.ended { "ended" } else { "stopped" };
+		let lines = vec![
+			Line::raw(format!("PC ${:04x}  SP ${:02x}  cycles {}  {}", program.program_counter, program.stack_pointer, program.cycles, state)),
+			Line::raw("A    X    Y     N V B D I Z C"),
+			Line::from(flag_line),
+		];
+		frame.render_widget(Paragraph::new(lines).block(title(" Registers ")), area);
+	}
+
+	fn draw_stack(&self, frame: &mut Frame, area: Rect) {
+		let lines: Vec<Line> = if self.program.stack_pointer == 0xff {
+			vec![Line::raw("The stack is empty (SP=$ff)")]
+		}
+		else {
+			debug::describe_stack(self.program).into_iter().map(|entry| {
+				let bytes: Vec<String> = entry.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+				Line::from(vec![
+					Span::raw(format!("${:04x}: {:<6} ", entry.address, bytes.join(" "))),
+					Span::styled(entry.label, Style::new().fg(Color::Green)),
+					Span::raw(format!(" {}", entry.note)),
+				])
+			}).collect()
+		};
+		frame.render_widget(Paragraph::new(lines).block(title(" Stack ")), area);
+	}
+
+	fn draw_memory(&mut self, frame: &mut Frame, area: Rect) {
+		self.memory_rows = area.height.saturating_sub(2);
+		let program = &*self.program;
+
+		// Bytes the last instruction wrote are highlighted
+		let written: Vec<u16> = program.history.iter().next_back().map(|step| step.writes.iter().map(|(a, _, _)| *a).collect()).unwrap_or_default();
+
+		let lines: Vec<Line> = (0..self.memory_rows).map(|row| {
+			let start = self.memory_address.wrapping_add(row * 16);
+			let mut spans = vec![Span::raw(format!("${:04x}:", start))];
+			let mut text = String::new();
+			for i in 0..16 {
+				let address = start.wrapping_add(i);
+				let byte = program.get_memory(address);
+				let style = if written.contains(&address) { Style::new().fg(Color::Yellow) } else { Style::new() };
+				spans.push(Span::styled(format!(" {:02x}", byte), style));
+				text.push(if (0x20..0x7f).contains(&byte) { byte as char } else { '.' });
+			}
+			spans.push(Span::raw(format!("  |{}|", text)));
+			Line::from(spans)
+		}).collect();
+		frame.render_widget(Paragraph::new(lines).block(title(" Memory ")), area);
+	}
+
+	fn draw_output(&self, frame: &mut Frame, area: Rect) {
+		let height = area.height.saturating_sub(2) as usize;
+		let lines: Vec<Line> = self.log.iter().skip(self.log.len().saturating_sub(height)).map(|(kind, message)| match kind {
+			LogKind::Info => Line::raw(message.as_str()),
+			LogKind::Warning => Line::from(vec![Span::styled("Warning: ", Style::new().fg(Color::Yellow)), Span::raw(message.as_str())]),
+			LogKind::Error => Line::from(vec![Span::styled("Error: ", Style::new().fg(Color::Red)), Span::raw(message.as_str())]),
+		}).collect();
+		frame.render_widget(Paragraph::new(lines).block(title(" Output ")), area);
+	}
+
+	fn draw_command(&self, frame: &mut Frame, area: Rect) {
+		let line = match &self.command {
+			Some(command) => {
+				frame.set_cursor_position((area.x + 1 + command.chars().count() as u16, area.y));
+				Line::raw(format!(":{}", command))
+			},
+			None if self.running.is_some() => Line::raw("Running, Esc to stop"),
+			None => Line::styled("s step  n next  f finish  c continue  r reverse  b breakpoint  : command  ? help  q quit",
+				Style::new().fg(Color::DarkGray)),
+		};
+		frame.render_widget(Paragraph::new(line), area);
+	}
+}
+
+// =============================================================
+
+fn event_loop(terminal: &mut DefaultTerminal, app: &mut App) -> io::Result<()> {
+	while !app.quit {
+		terminal.draw(|frame| app.draw(frame))?;
+
+		match app.running {
+			Some(resume) => {
+				app.run_batch(resume);
+				while event::poll(Duration::ZERO)? {
+					if let Event::Key(key) = event::read()? {
+						app.handle_key(key);
+					}
+				}
+			},
+			None => {
+				if let Event::Key(key) = event::read()? {
+					app.handle_key(key);
+				}
+			},
+		}
+	}
+	Ok(())
+}
+
+pub fn run(program: &mut Program) -> Result<(), String> {
+	if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+		return Err(String::from("--tui needs a terminal"));
+	}
+
+	// Start where run would
+	program.program_counter = program.origin;
+	let mut terminal = ratatui::try_init().map_err(|e| format!("Failed to set up the terminal: {}", e))?;
+	let mut app = App::new(program);
+	app.log(LogKind::Info, format!("Program at ${:04x}, press ? for help", app.program.program_counter));
+	app.memory_address = app.program.origin & 0xfff0;
+
+	let result = event_loop(&mut terminal, &mut app);
+	ratatui::restore();
+	result.map_err(|e| format!("Terminal error: {}", e))
+}